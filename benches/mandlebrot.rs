@@ -1,7 +1,9 @@
 use cranefuck::interpreter::interpret;
-use cranefuck::jit::jit;
+use cranefuck::jit::{self, jit};
 use cranefuck::optimizer::{optimize, OptimizedIr};
 use cranefuck::parser::{to_ir, tokenize};
+use cranefuck::tape::TapeConfig;
+use cranefuck::vm;
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 
 // A sample Brainfuck program. You can change this to any code you'd like to benchmark.
@@ -23,8 +25,18 @@ fn bench_interpreter(c: &mut Criterion) {
     c.bench_function("Interpreter", |b| {
         b.iter(|| {
             // Clone the IR since our functions may consume it.
-            let result =
-                interpret(black_box(ir.clone()), true).expect("Interpreter execution failed");
+            let result = interpret(black_box(ir.clone()), TapeConfig::default(), true)
+                .expect("Interpreter execution failed");
+            black_box(result);
+        })
+    });
+}
+
+fn bench_vm(c: &mut Criterion) {
+    let bytecode = vm::compile(prepare_ir(), TapeConfig::default()).unwrap();
+    c.bench_function("VM", |b| {
+        b.iter(|| {
+            let result = black_box(&bytecode).run(true).expect("VM execution failed");
             black_box(result);
         })
     });
@@ -34,7 +46,16 @@ fn bench_jit(c: &mut Criterion) {
     let ir = prepare_ir();
     c.bench_function("JIT", |b| {
         b.iter(|| {
-            jit(black_box(ir.clone()), true);
+            jit(black_box(ir.clone()), TapeConfig::default(), true).unwrap();
+        })
+    });
+}
+
+fn bench_jit_run(c: &mut Criterion) {
+    let code = jit::compile(prepare_ir(), TapeConfig::default()).unwrap();
+    c.bench_function("JIT run", |b| {
+        b.iter(|| {
+            jit::run(black_box(&code), TapeConfig::default(), true).unwrap();
         })
     });
 }
@@ -42,6 +63,6 @@ fn bench_jit(c: &mut Criterion) {
 criterion_group! {
     name = benches;
     config = custom_config();
-    targets = bench_interpreter, bench_jit
+    targets = bench_interpreter, bench_vm, bench_jit, bench_jit_run
 }
 criterion_main!(benches);
@@ -0,0 +1,134 @@
+// On-disk cache of compiled programs, one JSON file per program under
+// `$XDG_CACHE_HOME/cranefuck` (falling back to `~/.cache/cranefuck`). Entries
+// are keyed by a hash of the source and of everything else the result depends
+// on: how it was parsed, whether it was optimized, the tape settings and the
+// target machine. An entry holds the optimized IR and, once the program has
+// been run with the JIT, its machine code, along with everything its key was
+// hashed from so an entry whose hash merely collides is never used.
+
+use std::{
+    env, fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ir_text, jit::code::MachineCode, optimizer::OptimizedIr, tape::TapeConfig};
+
+/// Bumped whenever the layout of entries or the generated code changes
+const FORMAT_VERSION: u32 = 9;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    hash: u64,
+    /// The settings and the source the hash is of
+    material: String,
+}
+
+impl CacheKey {
+    /// `frontend` describes how `source` is turned into IR, such as the dialect,
+    /// and `target` the machine code is generated for.
+    pub fn new(
+        source: &str,
+        frontend: &str,
+        optimize: bool,
+        tape: TapeConfig,
+        target: &str,
+    ) -> Self {
+        let settings = format!(
+            "{} {FORMAT_VERSION}\0{frontend}\0{optimize}\0{} {:?}\0{target}\0",
+            env!("CARGO_PKG_VERSION"),
+            tape.len,
+            tape.eof,
+        );
+        let material = settings + source;
+        CacheKey {
+            hash: fnv1a(material.bytes()),
+            material,
+        }
+    }
+}
+
+impl fmt::Display for CacheKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.hash)
+    }
+}
+
+/// 64-bit FNV-1a, which unlike `DefaultHasher` is the same in every build.
+fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes.into_iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheEntry {
+    pub ir: Vec<OptimizedIr>,
+    pub code: Option<MachineCode>,
+}
+
+/// `CacheEntry` as stored, with the IR in its textual form
+#[derive(Serialize, Deserialize)]
+struct StoredEntry {
+    /// `CacheKey::material` of the key it was stored under
+    key: String,
+    ir: String,
+    code: Option<MachineCode>,
+}
+
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Cache { dir: dir.into() }
+    }
+
+    /// The cache in the user's cache directory, if there is one.
+    pub fn open() -> Option<Self> {
+        let base = env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| Path::new(dir).is_absolute())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+        Some(Cache::new(base.join("cranefuck")))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path(&self, key: &CacheKey) -> PathBuf {
+        self.dir.join(format!("{key}.json"))
+    }
+
+    /// The entry for `key`. Missing and unreadable entries are both a miss, as
+    /// are entries of another key with the same hash.
+    pub fn load(&self, key: &CacheKey) -> Option<CacheEntry> {
+        let stored = fs::read_to_string(self.path(key)).ok()?;
+        let stored: StoredEntry = serde_json::from_str(&stored).ok()?;
+        if stored.key != key.material {
+            return None;
+        }
+        Some(CacheEntry {
+            ir: ir_text::parse(&stored.ir).ok()?,
+            code: stored.code,
+        })
+    }
+
+    /// Writes the entry for `key`, replacing any previous one in a single
+    /// rename so concurrent runs never see half an entry.
+    pub fn store(&self, key: &CacheKey, entry: &CacheEntry) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let stored = StoredEntry {
+            key: key.material.clone(),
+            ir: ir_text::print(&entry.ir),
+            code: entry.code.clone(),
+        };
+        let path = self.path(key);
+        let temporary = path.with_extension(format!("json.{}", std::process::id()));
+        fs::write(&temporary, serde_json::to_string(&stored)?)?;
+        fs::rename(temporary, path)
+    }
+}
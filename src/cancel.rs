@@ -0,0 +1,34 @@
+// Stopping a running program from another thread or a signal handler. Every
+// backend checks the token at loop back-edges, so a program stops within one
+// iteration of the loop it is in, though not while it is blocked on input.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks every run holding a clone of this token to stop. Runs return
+    /// `RuntimeError::Cancelled`.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// The flag itself, a byte that is non-zero once cancelled, for generated
+    /// code to load.
+    pub(crate) fn flag(&self) -> *const u8 {
+        self.0.as_ptr() as *const u8
+    }
+}
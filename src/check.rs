@@ -0,0 +1,364 @@
+// Static checks over `Ir`, run before any optimization so every op still maps
+// back to the tokens it was built from.
+
+use std::{collections::HashMap, fmt::Write, ops::Range};
+
+use serde::Serialize;
+
+use crate::parser::{ir_token_ranges, ExtendedOp, Ir, IrLoopType, Span, Token};
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Lint {
+    /// A loop whose cell is always zero when it is reached
+    UnreachableLoop,
+    /// A loop whose body can never change the cell it tests
+    InfiniteLoop,
+    /// A move that takes the pointer left of cell 0
+    LeftEdge,
+    /// A run of `+-` or `<>` where some of the commands cancel each other
+    CancellingPair,
+    /// Code that follows a loop that never exits or the end of the program
+    UnreachableCode,
+}
+
+impl Lint {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::UnreachableLoop => "unreachable-loop",
+            Lint::InfiniteLoop => "infinite-loop",
+            Lint::LeftEdge => "left-edge",
+            Lint::CancellingPair => "cancelling-pair",
+            Lint::UnreachableCode => "unreachable-code",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Diagnostic {
+    pub lint: Lint,
+    pub message: String,
+    pub span: Span,
+}
+
+/// Runs all checks. `tokens` must be the spanned tokens `ir_ops` was built from.
+pub fn check(ir_ops: impl AsRef<[Ir]>, tokens: &[(Token, Span)]) -> Vec<Diagnostic> {
+    let ir_ops = ir_ops.as_ref();
+    let token_ranges = ir_token_ranges(
+        tokens
+            .iter()
+            .map(|(token, _)| token.clone())
+            .collect::<Vec<_>>(),
+    );
+    let spans = token_ranges
+        .iter()
+        .map(|range| {
+            let first = tokens[range.start].1;
+            let last = tokens[range.end - 1].1;
+            Span {
+                end: last.end,
+                ..first
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut checker = Checker {
+        ir_ops,
+        spans: &spans,
+        diagnostics: Vec::new(),
+    };
+
+    checker.check_cancelling_pairs(&token_ranges);
+    checker.check_range(0..ir_ops.len(), &mut State::start());
+
+    let mut diagnostics = checker.diagnostics;
+    diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+    diagnostics
+}
+
+/// Human readable diagnostics in the usual `file:line:column` layout, quoting
+/// the offending source line.
+pub fn render(diagnostics: &[Diagnostic], source: &str, path: &str) -> String {
+    let lines = source.lines().collect::<Vec<_>>();
+    let mut output = String::new();
+
+    for diagnostic in diagnostics {
+        let span = diagnostic.span;
+        let line = lines.get(span.line - 1).copied().unwrap_or_default();
+        let gutter = " ".repeat(span.line.to_string().len());
+        // Underline up to the end of the span or the end of its first line
+        let width = source[span.start..span.end]
+            .lines()
+            .next()
+            .map(|text| text.chars().count())
+            .unwrap_or(1)
+            .max(1);
+
+        writeln!(
+            output,
+            "warning[{}]: {}",
+            diagnostic.lint.name(),
+            diagnostic.message
+        )
+        .unwrap();
+        writeln!(output, "{gutter}--> {path}:{}:{}", span.line, span.column).unwrap();
+        writeln!(output, "{gutter} |").unwrap();
+        writeln!(output, "{} | {}", span.line, line).unwrap();
+        writeln!(
+            output,
+            "{gutter} | {}{}",
+            " ".repeat(span.column - 1),
+            "^".repeat(width)
+        )
+        .unwrap();
+        output.push('\n');
+    }
+
+    output
+}
+
+pub fn to_json(diagnostics: &[Diagnostic]) -> String {
+    serde_json::to_string_pretty(diagnostics).expect("Diagnostics are always serializable")
+}
+
+enum Flow {
+    Continues,
+    /// Execution provably never leaves this code
+    Diverges,
+}
+
+/// What is known about the tape. Cells are keyed by their offset from the
+/// origin; when the pointer's absolute position is lost, a new origin is
+/// picked at the current cell and `absolute` is cleared.
+#[derive(Clone)]
+struct State {
+    pointer: isize,
+    absolute: bool,
+    cells: HashMap<isize, Option<u8>>,
+    untouched_cells_are_zero: bool,
+}
+
+impl State {
+    fn start() -> Self {
+        State {
+            pointer: 0,
+            absolute: true,
+            cells: HashMap::new(),
+            untouched_cells_are_zero: true,
+        }
+    }
+
+    fn unknown() -> Self {
+        State {
+            pointer: 0,
+            absolute: false,
+            cells: HashMap::new(),
+            untouched_cells_are_zero: false,
+        }
+    }
+
+    fn cell(&self) -> Option<u8> {
+        match self.cells.get(&self.pointer) {
+            Some(value) => *value,
+            None if self.untouched_cells_are_zero => Some(0),
+            None => None,
+        }
+    }
+
+    fn set_cell(&mut self, value: Option<u8>) {
+        self.cells.insert(self.pointer, value);
+    }
+}
+
+struct Checker<'a> {
+    ir_ops: &'a [Ir],
+    spans: &'a [Span],
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker<'_> {
+    fn report(&mut self, lint: Lint, message: impl Into<String>, index: usize) {
+        self.diagnostics.push(Diagnostic {
+            lint,
+            message: message.into(),
+            span: self.spans[index],
+        });
+    }
+
+    fn check_cancelling_pairs(&mut self, token_ranges: &[Range<usize>]) {
+        for (index, op) in self.ir_ops.iter().enumerate() {
+            let tokens = token_ranges[index].len() as i64;
+            let message = match op {
+                Ir::Data(amount) if tokens > amount.abs() => "`+` and `-` cancel each other out",
+                Ir::Move(amount) if tokens > amount.abs() as i64 => {
+                    "`<` and `>` cancel each other out"
+                }
+                _ => continue,
+            };
+            self.report(Lint::CancellingPair, message, index);
+        }
+    }
+
+    fn check_range(&mut self, range: Range<usize>, state: &mut State) -> Flow {
+        let mut index = range.start;
+
+        while index < range.end {
+            match &self.ir_ops[index] {
+                Ir::Data(amount) => {
+                    let value = state.cell().map(|value| value.wrapping_add(*amount as u8));
+                    state.set_cell(value);
+                }
+                Ir::Move(amount) => {
+                    state.pointer += amount;
+                    if state.absolute && state.pointer < 0 {
+                        self.report(
+                            Lint::LeftEdge,
+                            "pointer moves left of the first cell and wraps around to the end of the tape",
+                            index,
+                        );
+                        *state = State::unknown();
+                    }
+                }
+                Ir::IO(true) => state.set_cell(None),
+                Ir::IO(false) | Ir::Debug | Ir::Extended(ExtendedOp::Store) => {}
+                Ir::Loop(IrLoopType::Start, end) => {
+                    let end = *end;
+                    if let Flow::Diverges = self.check_loop(index, end, state) {
+                        if end + 1 < range.end {
+                            self.report(
+                                Lint::UnreachableCode,
+                                "unreachable code after a loop that never exits",
+                                end + 1,
+                            );
+                        }
+                        return Flow::Diverges;
+                    }
+                    index = end;
+                }
+                Ir::Loop(IrLoopType::End, _) | Ir::Procedure(IrLoopType::End, _) => {}
+                Ir::Extended(ExtendedOp::End) => {
+                    if index + 1 < range.end {
+                        self.report(
+                            Lint::UnreachableCode,
+                            "unreachable code after the end of the program",
+                            index + 1,
+                        );
+                    }
+                    return Flow::Diverges;
+                }
+                Ir::Extended(ExtendedOp::Not) => state.set_cell(state.cell().map(|value| !value)),
+                Ir::Extended(ExtendedOp::ShiftLeft) => {
+                    state.set_cell(state.cell().map(|value| value << 1))
+                }
+                Ir::Extended(ExtendedOp::ShiftRight) => {
+                    state.set_cell(state.cell().map(|value| value >> 1))
+                }
+                // The storage byte is not tracked
+                Ir::Extended(
+                    ExtendedOp::Load | ExtendedOp::Xor | ExtendedOp::And | ExtendedOp::Or,
+                ) => state.set_cell(None),
+                Ir::Procedure(IrLoopType::Start, end) => {
+                    // Procedures can be called from anywhere, with any tape
+                    self.check_range(index + 1..*end, &mut State::unknown());
+                    index = *end;
+                }
+                Ir::Call => *state = State::unknown(),
+            }
+            index += 1;
+        }
+
+        Flow::Continues
+    }
+
+    fn check_loop(&mut self, start: usize, end: usize, state: &mut State) -> Flow {
+        let value = state.cell();
+        if value == Some(0) {
+            self.report(
+                Lint::UnreachableLoop,
+                "loop is never entered because the current cell is always zero here",
+                start,
+            );
+            return Flow::Continues;
+        }
+
+        let body = start + 1..end;
+        let effect = self.loop_effect(body.clone());
+        let ends_program = self.ir_ops[body.clone()]
+            .iter()
+            .any(|op| matches!(op, Ir::Extended(ExtendedOp::End)));
+        if !ends_program && matches!(&effect, Some(written) if !written.contains(&0)) {
+            if value.is_some() {
+                self.report(
+                    Lint::InfiniteLoop,
+                    "loop never terminates because the current cell is non-zero and the body cannot change it",
+                    start,
+                );
+                return Flow::Diverges;
+            }
+            self.report(
+                Lint::InfiniteLoop,
+                "loop never terminates once entered because the body cannot change the current cell",
+                start,
+            );
+            // Only left when it was never entered
+            state.set_cell(Some(0));
+            return Flow::Continues;
+        }
+
+        match effect {
+            Some(written) => {
+                // Balanced loop: every iteration starts on the same cell
+                let mut body_state = state.clone();
+                for offset in &written {
+                    body_state.cells.insert(state.pointer + offset, None);
+                }
+                let flow = self.check_range(body, &mut body_state);
+
+                for offset in written {
+                    state.cells.insert(state.pointer + offset, None);
+                }
+                state.set_cell(Some(0));
+                if let (Flow::Diverges, Some(_)) = (flow, value) {
+                    return Flow::Diverges;
+                }
+            }
+            None => {
+                let mut body_state = State::unknown();
+                self.check_range(body, &mut body_state);
+
+                *state = State::unknown();
+                state.set_cell(Some(0));
+            }
+        }
+
+        Flow::Continues
+    }
+
+    /// Offsets a loop body may write to, relative to the cell the loop tests,
+    /// or `None` when the body does not return the pointer to that cell.
+    fn loop_effect(&self, body: Range<usize>) -> Option<Vec<isize>> {
+        let mut pointer = 0;
+        let mut written = Vec::new();
+        let mut index = body.start;
+
+        while index < body.end {
+            match &self.ir_ops[index] {
+                Ir::Data(_) | Ir::IO(true) => written.push(pointer),
+                Ir::Extended(ExtendedOp::End | ExtendedOp::Store) => {}
+                Ir::Extended(_) => written.push(pointer),
+                Ir::Move(amount) => pointer += amount,
+                Ir::IO(false) | Ir::Debug | Ir::Loop(IrLoopType::End, _) => {}
+                Ir::Loop(IrLoopType::Start, end) => {
+                    let inner = self.loop_effect(index + 1..*end)?;
+                    written.extend(inner.into_iter().map(|offset| pointer + offset));
+                    index = *end;
+                }
+                Ir::Procedure(_, end) => index = *end,
+                Ir::Call => return None,
+            }
+            index += 1;
+        }
+
+        (pointer == 0).then_some(written)
+    }
+}
@@ -1,11 +1,13 @@
-use std::{collections::VecDeque, io::Write};
-
 use anyhow::Result;
 use thiserror::Error;
 
 use crate::{
+    cancel::CancellationToken,
+    io::{Io, NullIo, StdIo},
     optimizer::OptimizedIr,
-    parser::{Ir, IrLoopType},
+    parser::{ExtendedOp, Ir, IrLoopType},
+    snapshot::{PendingIo, Snapshot, SnapshotError},
+    tape::{InvalidTapeLength, TapeConfig},
 };
 
 #[derive(Error, Debug)]
@@ -17,84 +19,308 @@ pub enum RuntimeError {
 
     #[error("generic error")]
     Generic(#[from] anyhow::Error),
+
+    /// The program's `CancellationToken` was cancelled. `tape` is the
+    /// `dump_tape` of where the program stopped, and `snapshot` resumes it
+    /// from there.
+    #[error("cancelled at IR op {ir_index}")]
+    Cancelled {
+        ir_index: usize,
+        tape: String,
+        snapshot: Box<Snapshot>,
+    },
+
+    #[error(transparent)]
+    Snapshot(#[from] SnapshotError),
+
+    #[error(transparent)]
+    Tape(#[from] InvalidTapeLength),
+}
+
+impl RuntimeError {
+    pub(crate) fn cancelled(snapshot: Snapshot) -> Self {
+        RuntimeError::Cancelled {
+            ir_index: snapshot.ir_index,
+            tape: dump_tape(&snapshot.tape, snapshot.pointer),
+            snapshot: Box::new(snapshot),
+        }
+    }
+}
+
+/// Runs `ir_ops` against the terminal, or with `NullIo` when `ignore_io` is set.
+pub fn interpret(
+    ir_ops: impl AsRef<[OptimizedIr]>,
+    tape: TapeConfig,
+    ignore_io: bool,
+) -> Result<u8, RuntimeError> {
+    let cancel = CancellationToken::new();
+    if ignore_io {
+        interpret_with(ir_ops, tape, &mut NullIo, &cancel)
+    } else {
+        interpret_with(ir_ops, tape, &mut StdIo::default(), &cancel)
+    }
+}
+
+/// Runs `ir_ops` until it ends or `cancel` is cancelled, returning the final
+/// value of the current cell.
+pub fn interpret_with(
+    ir_ops: impl AsRef<[OptimizedIr]>,
+    tape: TapeConfig,
+    io: &mut dyn Io,
+    cancel: &CancellationToken,
+) -> Result<u8, RuntimeError> {
+    resume_with(ir_ops, tape, Snapshot::new(tape), io, cancel)
+}
+
+/// Like `interpret_with`, but continues from `snapshot`.
+pub fn resume_with(
+    ir_ops: impl AsRef<[OptimizedIr]>,
+    tape: TapeConfig,
+    snapshot: Snapshot,
+    io: &mut dyn Io,
+    cancel: &CancellationToken,
+) -> Result<u8, RuntimeError> {
+    execute(ir_ops.as_ref(), tape, snapshot, io, cancel, &mut ())
+}
+
+/// Watches the interpreter run every op, such as `trace::Tracer` and
+/// `stats::TapeStats`, and may run whole loops in its place, such as the
+/// tiered mode's compiled loops.
+pub trait Observer {
+    /// The op at `ip` is about to run on `memory` with the pointer at `pointer`.
+    fn before(&mut self, ip: usize, pointer: usize, memory: &[u8]);
+
+    /// The op from the last `before` has run.
+    fn after(&mut self, _memory: &[u8]) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    /// The loop whose `[` is at `ip` is about to run an iteration on `machine`.
+    /// Returning anything but `LoopExit::Interpreted` means the observer ran
+    /// it itself, and the interpreter carries on from there.
+    #[inline(always)]
+    fn enter_loop(&mut self, _ip: usize, _machine: Machine<'_>) -> std::io::Result<LoopExit> {
+        Ok(LoopExit::Interpreted)
+    }
+}
+
+/// The interpreter's state, for an `Observer` running a loop
+pub struct Machine<'a> {
+    pub memory: &'a mut [u8],
+    pub pointer: &'a mut usize,
+    /// Extended Brainfuck storage byte
+    pub storage: &'a mut u8,
+    pub io: &'a mut dyn Io,
+    pub cancel: &'a CancellationToken,
+}
+
+impl Machine<'_> {
+    fn reborrow(&mut self) -> Machine<'_> {
+        Machine {
+            memory: self.memory,
+            pointer: self.pointer,
+            storage: self.storage,
+            io: self.io,
+            cancel: self.cancel,
+        }
+    }
+}
+
+/// How far an `Observer` ran a loop from `Observer::enter_loop`
+pub enum LoopExit {
+    /// Not at all, the interpreter runs it
+    Interpreted,
+    /// Through to its `]`, which fell through
+    Finished,
+    /// Until `@` ended the program
+    Halted,
+    /// Until the `]` at this IR index noticed cancellation
+    Cancelled(usize),
+}
+
+/// Watches nothing, so an unobserved run compiles down to the plain loop.
+impl Observer for () {
+    #[inline(always)]
+    fn before(&mut self, _ip: usize, _pointer: usize, _memory: &[u8]) {}
 }
 
-pub fn interpret(ir_ops: impl AsRef<[OptimizedIr]>, ignore_io: bool) -> Result<u8, RuntimeError> {
-    let mut memory = vec![0; 30_000];
-    let mut instruction_pointer = 0;
-    let mut data_pointer = 0;
-    let ops = ir_ops.as_ref();
-    let mut input_buffer: VecDeque<char> = VecDeque::new();
+/// Watches with both, `A` first, which also gets the first chance to run a loop.
+impl<A: Observer, B: Observer> Observer for (A, B) {
+    fn before(&mut self, ip: usize, pointer: usize, memory: &[u8]) {
+        self.0.before(ip, pointer, memory);
+        self.1.before(ip, pointer, memory);
+    }
+
+    fn after(&mut self, memory: &[u8]) -> std::io::Result<()> {
+        self.0.after(memory)?;
+        self.1.after(memory)
+    }
+
+    fn enter_loop(&mut self, ip: usize, mut machine: Machine<'_>) -> std::io::Result<LoopExit> {
+        match self.0.enter_loop(ip, machine.reborrow())? {
+            LoopExit::Interpreted => self.1.enter_loop(ip, machine),
+            exit => Ok(exit),
+        }
+    }
+}
+
+/// Watches only if there is an observer to watch with.
+impl<O: Observer> Observer for Option<O> {
+    fn before(&mut self, ip: usize, pointer: usize, memory: &[u8]) {
+        if let Some(observer) = self {
+            observer.before(ip, pointer, memory);
+        }
+    }
+
+    fn after(&mut self, memory: &[u8]) -> std::io::Result<()> {
+        match self {
+            Some(observer) => observer.after(memory),
+            None => Ok(()),
+        }
+    }
+
+    fn enter_loop(&mut self, ip: usize, machine: Machine<'_>) -> std::io::Result<LoopExit> {
+        match self {
+            Some(observer) => observer.enter_loop(ip, machine),
+            None => Ok(LoopExit::Interpreted),
+        }
+    }
+}
+
+/// Like `resume_with`, also showing every op that runs to `observer`. Several
+/// observers can watch at once as a tuple of them.
+pub fn observe_with(
+    ir_ops: impl AsRef<[OptimizedIr]>,
+    tape: TapeConfig,
+    snapshot: Snapshot,
+    io: &mut dyn Io,
+    cancel: &CancellationToken,
+    observer: &mut impl Observer,
+) -> Result<u8, RuntimeError> {
+    execute(ir_ops.as_ref(), tape, snapshot, io, cancel, observer)
+}
+
+fn execute<O: Observer>(
+    ops: &[OptimizedIr],
+    tape: TapeConfig,
+    snapshot: Snapshot,
+    io: &mut dyn Io,
+    cancel: &CancellationToken,
+    observer: &mut O,
+) -> Result<u8, RuntimeError> {
+    snapshot.validate(ops, tape)?;
+    let io = &mut PendingIo::new(&snapshot.pending_input, io);
+    let mut instruction_pointer = snapshot.ir_index;
+    let mut data_pointer = snapshot.pointer;
+    // Extended Brainfuck storage byte
+    let mut storage = snapshot.storage;
+    // pbrain procedures by ID (index of their `Procedure(Start)`) and return addresses
+    let mut procedures: [Option<usize>; 256] = snapshot.procedure_table();
+    let mut call_stack: Vec<usize> = snapshot.call_stack;
+    let mut memory = snapshot.tape;
 
     // Set terminal to raw mode to allow reading stdin one key at a time
     // let mut stdout = std::io::stdout().into_raw_mode()?;
     // Use asynchronous stdin
     // let mut stdin = termion::async_stdin().keys();
 
-    loop {
+    // Only cancellation leaves the loop, at the IR index of a `]`
+    let stopped_at = loop {
+        observer.after(&memory)?;
         if instruction_pointer >= ops.len() {
             return Ok(memory[data_pointer]);
         }
+        observer.before(instruction_pointer, data_pointer, &memory);
 
         let op = &ops[instruction_pointer];
         match op {
             OptimizedIr::Ir(op) => match op {
                 Ir::Move(amount) => {
-                    data_pointer = ((data_pointer as isize + amount)
-                        .rem_euclid(memory.len() as isize))
-                        as usize;
+                    data_pointer = wrap(data_pointer, *amount, memory.len());
                 }
                 Ir::Data(amount) => {
                     memory[data_pointer] = memory[data_pointer].wrapping_add_signed(*amount as i8);
                 }
                 Ir::IO(true) => {
-                    if ignore_io == false {
-                        if input_buffer.len() == 0 {
-                            let mut line = String::new();
-                            std::io::stdin().read_line(&mut line)?;
-                            line = line.replace("\r\n", "\n");
-                            input_buffer.extend(line.chars());
-                        }
-
-                        let character = input_buffer.pop_front().unwrap();
-
-                        if character == '\n' {
-                            memory[data_pointer] = 10;
-                        } else {
-                            memory[data_pointer] = character as u8;
-                        }
-                    }
-                }
-                Ir::IO(false) => {
-                    if ignore_io == false {
-                        let value = memory[data_pointer] as u8;
-                        let char = if value == 10 {
-                            if cfg!(windows) {
-                                "\r\n".to_string()
-                            } else {
-                                "\n".to_string()
-                            }
-                        } else {
-                            (value as char).to_string()
-                        };
-                        print!("{}", char);
-                        std::io::stdout().flush()?;
-                    }
+                    memory[data_pointer] = match io.read()? {
+                        Some(value) => value,
+                        None => tape.eof.apply(memory[data_pointer]),
+                    };
                 }
+                Ir::IO(false) => io.write(memory[data_pointer])?,
                 Ir::Loop(IrLoopType::Start, loop_match) => {
                     let value = memory[data_pointer];
                     if value == 0 {
                         instruction_pointer = loop_match + 1;
                         continue;
                     }
+                    let machine = Machine {
+                        memory: &mut memory,
+                        pointer: &mut data_pointer,
+                        storage: &mut storage,
+                        io,
+                        cancel,
+                    };
+                    match observer.enter_loop(instruction_pointer, machine)? {
+                        LoopExit::Interpreted => {}
+                        LoopExit::Finished => {
+                            instruction_pointer = loop_match + 1;
+                            continue;
+                        }
+                        LoopExit::Halted => {
+                            observer.after(&memory)?;
+                            return Ok(memory[data_pointer]);
+                        }
+                        LoopExit::Cancelled(ir_index) => break ir_index,
+                    }
                 }
                 Ir::Loop(IrLoopType::End, loop_match) => {
                     let value = memory[data_pointer];
                     if value != 0 {
+                        if cancel.is_cancelled() {
+                            break instruction_pointer;
+                        }
                         instruction_pointer = *loop_match;
                         continue;
                     }
                 }
+                Ir::Extended(op) => {
+                    let cell = &mut memory[data_pointer];
+                    match op {
+                        ExtendedOp::End => {
+                            let cell = *cell;
+                            observer.after(&memory)?;
+                            return Ok(cell);
+                        }
+                        ExtendedOp::Store => storage = *cell,
+                        ExtendedOp::Load => *cell = storage,
+                        ExtendedOp::ShiftRight => *cell >>= 1,
+                        ExtendedOp::ShiftLeft => *cell <<= 1,
+                        ExtendedOp::Not => *cell = !*cell,
+                        ExtendedOp::Xor => *cell ^= storage,
+                        ExtendedOp::And => *cell &= storage,
+                        ExtendedOp::Or => *cell |= storage,
+                    }
+                }
+                Ir::Procedure(IrLoopType::Start, procedure_end) => {
+                    procedures[memory[data_pointer] as usize] = Some(instruction_pointer);
+                    instruction_pointer = procedure_end + 1;
+                    continue;
+                }
+                Ir::Procedure(IrLoopType::End, _) => {
+                    if let Some(return_address) = call_stack.pop() {
+                        instruction_pointer = return_address;
+                        continue;
+                    }
+                }
+                Ir::Call => {
+                    if let Some(procedure) = procedures[memory[data_pointer] as usize] {
+                        call_stack.push(instruction_pointer + 1);
+                        instruction_pointer = procedure + 1;
+                        continue;
+                    }
+                }
+                Ir::Debug => io.debug(&dump_tape(&memory, data_pointer))?,
             },
             OptimizedIr::ResetToZero => {
                 memory[data_pointer] = 0;
@@ -102,12 +328,51 @@ pub fn interpret(ir_ops: impl AsRef<[OptimizedIr]>, ignore_io: bool) -> Result<u
             OptimizedIr::AddAndZero(target) => {
                 let value = memory[data_pointer];
                 memory[data_pointer] = 0;
-                let wrapped_pointer =
-                    ((data_pointer as isize + *target).rem_euclid(memory.len() as isize)) as usize;
+                let wrapped_pointer = wrap(data_pointer, *target, memory.len());
                 memory[wrapped_pointer] = memory[wrapped_pointer].wrapping_add_signed(value as i8);
             }
         }
 
         instruction_pointer += 1;
+    };
+
+    Err(RuntimeError::cancelled(Snapshot {
+        tape: memory,
+        pointer: data_pointer,
+        ir_index: stopped_at,
+        storage,
+        procedures: Snapshot::procedures_from_table(&procedures),
+        call_stack,
+        pending_input: io.pending_input(),
+    }))
+}
+
+/// `pointer` moved by `amount` on a tape of `len` cells, wrapping around its
+/// ends. Only a move off the tape pays for the division.
+#[inline(always)]
+fn wrap(pointer: usize, amount: isize, len: usize) -> usize {
+    let moved = pointer as isize + amount;
+    if (0..len as isize).contains(&moved) {
+        moved as usize
+    } else {
+        moved.rem_euclid(len as isize) as usize
     }
 }
+
+/// One line describing the cells around `pointer`, the current one in brackets.
+pub fn dump_tape(memory: &[u8], pointer: usize) -> String {
+    const WINDOW: usize = 8;
+    let start = pointer.saturating_sub(WINDOW);
+    let end = (pointer + WINDOW + 1).min(memory.len());
+
+    let cells = (start..end)
+        .map(|index| {
+            if index == pointer {
+                format!("[{:3}]", memory[index])
+            } else {
+                format!(" {:3} ", memory[index])
+            }
+        })
+        .collect::<String>();
+    format!("# pointer {pointer}, cells {start}..{end}:{cells}")
+}
@@ -0,0 +1,334 @@
+// Where a running program's `,` reads from, `.` writes to and `#` dumps the
+// tape to. Every backend can run against any `Io`, so embedders can feed
+// programs from memory and capture what they print.
+
+use std::{
+    collections::VecDeque,
+    io::{stdin, stdout, BufRead, BufWriter, Cursor, Stdout, Write},
+    str::FromStr,
+};
+
+use thiserror::Error;
+
+pub trait Io {
+    /// The next input byte, or `None` at the end of the input.
+    fn read(&mut self) -> std::io::Result<Option<u8>>;
+
+    fn write(&mut self, value: u8) -> std::io::Result<()>;
+
+    /// A line from `#` describing the tape around the pointer.
+    fn debug(&mut self, dump: &str) -> std::io::Result<()> {
+        eprintln!("{dump}");
+        Ok(())
+    }
+
+    /// Input read ahead of the program, such as the rest of a line, which
+    /// `read` will no longer return. Saved in snapshots.
+    fn pending_input(&mut self) -> Vec<u8> {
+        Vec::new()
+    }
+}
+
+/// How newlines are translated between a program and the terminal.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Hash)]
+pub enum NewlineMode {
+    /// Bytes pass through unchanged
+    #[default]
+    Raw,
+    /// `\n` written by the program is sent as `\r\n`
+    LfToCrlf,
+    /// `\r\n` in the input reaches the program as `\n`
+    CrlfToLf,
+}
+
+#[derive(Error, Debug)]
+#[error("unknown newline mode `{0}`, expected 'raw', 'lf-to-crlf' or 'crlf-to-lf'")]
+pub struct UnknownNewlineMode(String);
+
+impl FromStr for NewlineMode {
+    type Err = UnknownNewlineMode;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "raw" => Ok(NewlineMode::Raw),
+            "lf-to-crlf" => Ok(NewlineMode::LfToCrlf),
+            "crlf-to-lf" => Ok(NewlineMode::CrlfToLf),
+            _ => Err(UnknownNewlineMode(name.to_string())),
+        }
+    }
+}
+
+/// How the bytes a program reads and writes relate to the terminal's text.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Hash)]
+pub enum IoEncoding {
+    /// Bytes pass through unchanged, so binary input and output work
+    #[default]
+    Raw,
+    /// Input must be UTF-8 and reaches the program one byte at a time; output
+    /// bytes are written unchanged
+    Utf8,
+    /// Every cell holds a whole code point: `,` stores the next character's
+    /// code point and `.` prints the character for the cell's value. With
+    /// 8-bit cells only U+0000 to U+00FF fit, and larger code points in the
+    /// input are an error.
+    CodePoint,
+}
+
+#[derive(Error, Debug)]
+#[error("unknown encoding `{0}`, expected 'raw', 'utf-8' or 'code-point'")]
+pub struct UnknownIoEncoding(String);
+
+impl FromStr for IoEncoding {
+    type Err = UnknownIoEncoding;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "raw" => Ok(IoEncoding::Raw),
+            "utf-8" | "utf8" => Ok(IoEncoding::Utf8),
+            "code-point" => Ok(IoEncoding::CodePoint),
+            _ => Err(UnknownIoEncoding(name.to_string())),
+        }
+    }
+}
+
+/// When output written to the terminal is flushed. Whatever the policy, it
+/// is flushed before the program waits for input, before a tape dump and at
+/// the end of the run.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Hash)]
+pub enum OutputBuffering {
+    /// Every byte is flushed as it is written
+    Unbuffered,
+    /// Flushed at every newline
+    #[default]
+    Line,
+    /// Flushed whenever this many bytes have collected
+    Full(usize),
+    /// Flushed only when the program reads input
+    FlushBeforeRead,
+}
+
+impl OutputBuffering {
+    /// Buffer size for `Full` without a size, and the most any other policy
+    /// holds back.
+    pub const DEFAULT_SIZE: usize = 8192;
+
+    fn capacity(self) -> usize {
+        match self {
+            OutputBuffering::Unbuffered => 0,
+            OutputBuffering::Full(size) => size,
+            _ => Self::DEFAULT_SIZE,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+#[error(
+    "unknown output buffering `{0}`, expected 'unbuffered', 'line', 'full', 'full:<bytes>' or \
+     'flush-before-read'"
+)]
+pub struct UnknownOutputBuffering(String);
+
+impl FromStr for OutputBuffering {
+    type Err = UnknownOutputBuffering;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "unbuffered" => Ok(OutputBuffering::Unbuffered),
+            "line" => Ok(OutputBuffering::Line),
+            "full" => Ok(OutputBuffering::Full(Self::DEFAULT_SIZE)),
+            "flush-before-read" => Ok(OutputBuffering::FlushBeforeRead),
+            _ => name
+                .strip_prefix("full:")
+                .and_then(|size| size.parse().ok())
+                .map(OutputBuffering::Full)
+                .ok_or_else(|| UnknownOutputBuffering(name.to_string())),
+        }
+    }
+}
+
+/// The terminal: stdin (or input given up front) read a line at a time, and
+/// stdout buffered according to an `OutputBuffering` policy, with text encoded
+/// according to an `IoEncoding` and newlines translated according to a
+/// `NewlineMode`. Whatever is left is flushed when it is dropped.
+#[derive(Debug)]
+pub struct StdIo {
+    /// Input to read instead of stdin
+    input: Option<Cursor<Vec<u8>>>,
+    /// Cell values for the rest of the current line
+    input_buffer: VecDeque<u8>,
+    encoding: IoEncoding,
+    newlines: NewlineMode,
+    buffering: OutputBuffering,
+    writer: BufWriter<Stdout>,
+}
+
+impl Default for StdIo {
+    fn default() -> Self {
+        StdIo {
+            input: None,
+            input_buffer: VecDeque::new(),
+            encoding: IoEncoding::default(),
+            newlines: NewlineMode::default(),
+            buffering: OutputBuffering::default(),
+            writer: BufWriter::with_capacity(OutputBuffering::default().capacity(), stdout()),
+        }
+    }
+}
+
+impl StdIo {
+    /// Reads the program's input from `input` rather than stdin.
+    pub fn input(self, input: impl Into<Vec<u8>>) -> Self {
+        StdIo {
+            input: Some(Cursor::new(input.into())),
+            ..self
+        }
+    }
+
+    pub fn encoding(self, encoding: IoEncoding) -> Self {
+        StdIo { encoding, ..self }
+    }
+
+    pub fn newlines(self, newlines: NewlineMode) -> Self {
+        StdIo { newlines, ..self }
+    }
+
+    pub fn buffering(self, buffering: OutputBuffering) -> Self {
+        StdIo {
+            buffering,
+            writer: BufWriter::with_capacity(buffering.capacity(), stdout()),
+            ..self
+        }
+    }
+
+    /// Writes out whatever output the policy is holding back.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Queues the cell values for `line` according to the encoding.
+    fn buffer_line(&mut self, line: Vec<u8>) -> std::io::Result<()> {
+        if self.encoding == IoEncoding::Raw {
+            self.input_buffer.extend(line);
+            return Ok(());
+        }
+        let text = String::from_utf8(line)
+            .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))?;
+        if self.encoding == IoEncoding::Utf8 {
+            self.input_buffer.extend(text.into_bytes());
+            return Ok(());
+        }
+        for character in text.chars() {
+            let value = u8::try_from(character).map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("U+{:04X} does not fit in a cell", character as u32),
+                )
+            })?;
+            self.input_buffer.push_back(value);
+        }
+        Ok(())
+    }
+}
+
+impl Io for StdIo {
+    fn read(&mut self) -> std::io::Result<Option<u8>> {
+        if self.buffering == OutputBuffering::FlushBeforeRead {
+            self.writer.flush()?;
+        }
+        if self.input_buffer.is_empty() {
+            let mut line = Vec::new();
+            match &mut self.input {
+                Some(input) => input.read_until(b'\n', &mut line)?,
+                None => {
+                    // About to block, so show everything the program asked to print
+                    self.writer.flush()?;
+                    stdin().lock().read_until(b'\n', &mut line)?
+                }
+            };
+            if self.newlines == NewlineMode::CrlfToLf && line.ends_with(b"\r\n") {
+                line.remove(line.len() - 2);
+            }
+            self.buffer_line(line)?;
+        }
+
+        Ok(self.input_buffer.pop_front())
+    }
+
+    fn write(&mut self, value: u8) -> std::io::Result<()> {
+        if value == b'\n' && self.newlines == NewlineMode::LfToCrlf {
+            self.writer.write_all(b"\r")?;
+        }
+        match self.encoding {
+            IoEncoding::Raw | IoEncoding::Utf8 => self.writer.write_all(&[value])?,
+            IoEncoding::CodePoint => write!(self.writer, "{}", char::from(value))?,
+        }
+        match self.buffering {
+            OutputBuffering::Unbuffered => self.writer.flush(),
+            OutputBuffering::Line if value == b'\n' => self.writer.flush(),
+            _ => Ok(()),
+        }
+    }
+
+    fn debug(&mut self, dump: &str) -> std::io::Result<()> {
+        // Keep the dump in order with the program's own output
+        self.writer.flush()?;
+        eprintln!("{dump}");
+        Ok(())
+    }
+
+    fn pending_input(&mut self) -> Vec<u8> {
+        self.input_buffer.drain(..).collect()
+    }
+}
+
+/// No input at all, and output and tape dumps are discarded.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NullIo;
+
+impl Io for NullIo {
+    fn read(&mut self) -> std::io::Result<Option<u8>> {
+        Ok(None)
+    }
+
+    fn write(&mut self, _: u8) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn debug(&mut self, _: &str) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Input from a byte buffer and output collected into another, byte for byte.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BufferIo {
+    pub input: VecDeque<u8>,
+    pub output: Vec<u8>,
+    /// Lines from `#`, in order
+    pub dumps: Vec<String>,
+}
+
+impl BufferIo {
+    pub fn new(input: impl AsRef<[u8]>) -> Self {
+        BufferIo {
+            input: input.as_ref().iter().copied().collect(),
+            ..Self::default()
+        }
+    }
+}
+
+impl Io for BufferIo {
+    fn read(&mut self) -> std::io::Result<Option<u8>> {
+        Ok(self.input.pop_front())
+    }
+
+    fn write(&mut self, value: u8) -> std::io::Result<()> {
+        self.output.push(value);
+        Ok(())
+    }
+
+    fn debug(&mut self, dump: &str) -> std::io::Result<()> {
+        self.dumps.push(dump.to_string());
+        Ok(())
+    }
+}
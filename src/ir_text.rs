@@ -0,0 +1,250 @@
+// Textual form of `OptimizedIr`, one op per line:
+//
+//   add 3        Ir::Data(3)
+//   move -2      Ir::Move(-2)
+//   in / out     Ir::IO(true) / Ir::IO(false)
+//   loop {       Ir::Loop(Start, _)
+//   }            Ir::Loop(End, _)
+//   zero         ResetToZero
+//   addzero +1   AddAndZero(1)
+//
+// Dialect ops:
+//
+//   end, store, load, shr, shl, not, xor, and, or    Ir::Extended(_)
+//   proc {       Ir::Procedure(Start, _)
+//   }            Ir::Procedure(End, _)
+//   call         Ir::Call
+//   debug        Ir::Debug
+//
+// Loop and procedure bodies are indented by four spaces per level. Blank lines and
+// everything after a `;` are ignored when parsing.
+
+use std::fmt::Write;
+
+use thiserror::Error;
+
+use crate::{
+    optimizer::OptimizedIr,
+    parser::{ExtendedOp, Ir, IrLoopType},
+};
+
+const INDENT: &str = "    ";
+/// Largest `move` or `addzero` operand either way, so offsets can be added to
+/// the pointer without overflowing and fit the 32-bit offsets of the JIT
+const MAX_OFFSET: isize = i32::MAX as isize;
+
+#[derive(Error, Debug)]
+pub enum IrTextError {
+    #[error("line {line}: unknown op `{op}`")]
+    UnknownOp { line: usize, op: String },
+
+    #[error("line {line}: `{op}` expects an integer operand")]
+    InvalidOperand { line: usize, op: String },
+
+    #[error("line {line}: `{op}` operand {operand} is out of range, expected at most {max} either way", max = MAX_OFFSET)]
+    OperandOutOfRange {
+        line: usize,
+        op: String,
+        operand: isize,
+    },
+
+    #[error("line {line}: unexpected operand after `{op}`")]
+    UnexpectedOperand { line: usize, op: String },
+
+    #[error("line {line}: `}}` without a matching `loop {{` or `proc {{`")]
+    UnmatchedLoopEnd { line: usize },
+
+    #[error("line {line}: `{op} {{` is never closed")]
+    UnclosedLoop { line: usize, op: String },
+}
+
+pub fn print(ir_ops: impl AsRef<[OptimizedIr]>) -> String {
+    let mut output = String::new();
+    let mut depth = 0;
+
+    for op in ir_ops.as_ref() {
+        if let OptimizedIr::Ir(Ir::Loop(IrLoopType::End, _) | Ir::Procedure(IrLoopType::End, _)) =
+            op
+        {
+            depth -= 1;
+        }
+        for _ in 0..depth {
+            output.push_str(INDENT);
+        }
+
+        match op {
+            OptimizedIr::Ir(Ir::Data(amount)) => writeln!(output, "add {}", amount),
+            OptimizedIr::Ir(Ir::Move(amount)) => writeln!(output, "move {}", amount),
+            OptimizedIr::Ir(Ir::IO(true)) => writeln!(output, "in"),
+            OptimizedIr::Ir(Ir::IO(false)) => writeln!(output, "out"),
+            OptimizedIr::Ir(Ir::Loop(IrLoopType::Start, _)) => {
+                depth += 1;
+                writeln!(output, "loop {{")
+            }
+            OptimizedIr::Ir(Ir::Loop(IrLoopType::End, _)) => writeln!(output, "}}"),
+            OptimizedIr::Ir(Ir::Extended(op)) => writeln!(output, "{}", extended_op_name(*op)),
+            OptimizedIr::Ir(Ir::Procedure(IrLoopType::Start, _)) => {
+                depth += 1;
+                writeln!(output, "proc {{")
+            }
+            OptimizedIr::Ir(Ir::Procedure(IrLoopType::End, _)) => writeln!(output, "}}"),
+            OptimizedIr::Ir(Ir::Call) => writeln!(output, "call"),
+            OptimizedIr::Ir(Ir::Debug) => writeln!(output, "debug"),
+            OptimizedIr::ResetToZero => writeln!(output, "zero"),
+            OptimizedIr::AddAndZero(target) => writeln!(output, "addzero {:+}", target),
+        }
+        .expect("Writing to a String cannot fail");
+    }
+
+    output
+}
+
+pub fn parse(text: &str) -> Result<Vec<OptimizedIr>, IrTextError> {
+    let mut ir_ops = Vec::new();
+    // (IR index, source line, is a procedure) of every `loop {` or `proc {`
+    // that is still open
+    let mut open_loops: Vec<(usize, usize, bool)> = Vec::new();
+
+    for (line_index, line) in text.lines().enumerate() {
+        let line_number = line_index + 1;
+        let line = line.split(';').next().unwrap_or_default();
+        let mut words = line.split_whitespace();
+        let Some(op) = words.next() else {
+            continue;
+        };
+        let operand = words.next();
+        if let Some(extra) = words.next() {
+            return Err(IrTextError::UnexpectedOperand {
+                line: line_number,
+                op: extra.to_string(),
+            });
+        }
+
+        let parsed = match (op, operand) {
+            ("add", Some(amount)) => {
+                OptimizedIr::Ir(Ir::Data(parse_operand(op, amount, line_number)?))
+            }
+            ("move", Some(amount)) => {
+                OptimizedIr::Ir(Ir::Move(parse_offset(op, amount, line_number)?))
+            }
+            ("addzero", Some(target)) => {
+                OptimizedIr::AddAndZero(parse_offset(op, target, line_number)?)
+            }
+            ("add" | "move" | "addzero", None) => {
+                return Err(IrTextError::InvalidOperand {
+                    line: line_number,
+                    op: op.to_string(),
+                })
+            }
+            ("in", None) => OptimizedIr::Ir(Ir::IO(true)),
+            ("out", None) => OptimizedIr::Ir(Ir::IO(false)),
+            ("zero", None) => OptimizedIr::ResetToZero,
+            ("loop" | "proc", Some("{")) => {
+                open_loops.push((ir_ops.len(), line_number, op == "proc"));
+                // Patched once the matching `}` is found
+                OptimizedIr::Ir(Ir::Loop(IrLoopType::Start, 0))
+            }
+            ("}", None) => {
+                let (start, _, procedure) = open_loops
+                    .pop()
+                    .ok_or(IrTextError::UnmatchedLoopEnd { line: line_number })?;
+                let end = ir_ops.len();
+                if procedure {
+                    ir_ops[start] = OptimizedIr::Ir(Ir::Procedure(IrLoopType::Start, end));
+                    OptimizedIr::Ir(Ir::Procedure(IrLoopType::End, start))
+                } else {
+                    ir_ops[start] = OptimizedIr::Ir(Ir::Loop(IrLoopType::Start, end));
+                    OptimizedIr::Ir(Ir::Loop(IrLoopType::End, start))
+                }
+            }
+            ("call", None) => OptimizedIr::Ir(Ir::Call),
+            ("debug", None) => OptimizedIr::Ir(Ir::Debug),
+            (_, None) if extended_op(op).is_some() => {
+                OptimizedIr::Ir(Ir::Extended(extended_op(op).unwrap()))
+            }
+            ("in" | "out" | "zero" | "}" | "call" | "debug", Some(_)) | ("loop" | "proc", _) => {
+                return Err(IrTextError::UnexpectedOperand {
+                    line: line_number,
+                    op: op.to_string(),
+                })
+            }
+            (_, Some(_)) if extended_op(op).is_some() => {
+                return Err(IrTextError::UnexpectedOperand {
+                    line: line_number,
+                    op: op.to_string(),
+                })
+            }
+            _ => {
+                return Err(IrTextError::UnknownOp {
+                    line: line_number,
+                    op: op.to_string(),
+                })
+            }
+        };
+        ir_ops.push(parsed);
+    }
+
+    if let Some((_, line, procedure)) = open_loops.pop() {
+        let op = if procedure { "proc" } else { "loop" };
+        return Err(IrTextError::UnclosedLoop {
+            line,
+            op: op.to_string(),
+        });
+    }
+
+    Ok(ir_ops)
+}
+
+fn parse_operand<T: std::str::FromStr>(
+    op: &str,
+    operand: &str,
+    line: usize,
+) -> Result<T, IrTextError> {
+    operand
+        .strip_prefix('+')
+        .unwrap_or(operand)
+        .parse()
+        .map_err(|_| IrTextError::InvalidOperand {
+            line,
+            op: op.to_string(),
+        })
+}
+
+fn parse_offset(op: &str, operand: &str, line: usize) -> Result<isize, IrTextError> {
+    let offset = parse_operand(op, operand, line)?;
+    if !(-MAX_OFFSET..=MAX_OFFSET).contains(&offset) {
+        return Err(IrTextError::OperandOutOfRange {
+            line,
+            op: op.to_string(),
+            operand: offset,
+        });
+    }
+    Ok(offset)
+}
+
+const EXTENDED_OPS: [(ExtendedOp, &str); 9] = [
+    (ExtendedOp::End, "end"),
+    (ExtendedOp::Store, "store"),
+    (ExtendedOp::Load, "load"),
+    (ExtendedOp::ShiftRight, "shr"),
+    (ExtendedOp::ShiftLeft, "shl"),
+    (ExtendedOp::Not, "not"),
+    (ExtendedOp::Xor, "xor"),
+    (ExtendedOp::And, "and"),
+    (ExtendedOp::Or, "or"),
+];
+
+fn extended_op_name(op: ExtendedOp) -> &'static str {
+    EXTENDED_OPS
+        .iter()
+        .find(|(extended_op, _)| *extended_op == op)
+        .map(|(_, name)| *name)
+        .unwrap()
+}
+
+fn extended_op(name: &str) -> Option<ExtendedOp> {
+    EXTENDED_OPS
+        .iter()
+        .find(|(_, extended_name)| *extended_name == name)
+        .map(|(op, _)| *op)
+}
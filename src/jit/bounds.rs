@@ -0,0 +1,130 @@
+// Bounds on the data pointer proven at compile time, so the JIT can leave out
+// the wraparound of moves that can't reach an end of the tape. The pointer
+// starts on cell 0 and straight-line code shifts the range it can be in. A
+// loop whose body moves the pointer back to where it started, loops inside it
+// included, is on the same range at every iteration. Anything else, such as an
+// unbalanced loop, a procedure call or a procedure body, leaves the pointer
+// anywhere on the tape.
+
+use std::ops::RangeInclusive;
+
+use crate::{
+    optimizer::OptimizedIr,
+    parser::{Ir, IrLoopType},
+};
+
+/// Lowest and highest cell the pointer can be on, if known
+type Bounds = Option<(usize, usize)>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PointerBounds {
+    /// Bounds when each op runs
+    before: Vec<Bounds>,
+    /// Whether each op can reach past an end of the tape
+    wraps: Vec<bool>,
+}
+
+impl PointerBounds {
+    pub fn analyze(ir_ops: &[OptimizedIr], tape_len: usize) -> Self {
+        let balanced = balanced_loops(ir_ops);
+        let shift = |bounds: Bounds, amount: isize| {
+            let (low, high) = bounds?;
+            let high = high.checked_add_signed(amount)?;
+            (high < tape_len).then_some((low.checked_add_signed(amount)?, high))
+        };
+
+        let mut before = Vec::with_capacity(ir_ops.len());
+        let mut wraps = vec![false; ir_ops.len()];
+        let mut bounds = Some((0, 0));
+        // Bounds on entry to every loop and procedure definition around the op
+        let mut outer = Vec::new();
+        for (index, op) in ir_ops.iter().enumerate() {
+            before.push(bounds);
+            match op {
+                OptimizedIr::Ir(Ir::Move(amount)) => {
+                    bounds = shift(bounds, *amount);
+                    wraps[index] = bounds.is_none();
+                }
+                OptimizedIr::AddAndZero(target) => {
+                    wraps[index] = shift(bounds, *target).is_none();
+                }
+                OptimizedIr::Ir(Ir::Loop(IrLoopType::Start, _)) => {
+                    outer.push(bounds);
+                    if !balanced[index] {
+                        bounds = None;
+                    }
+                }
+                OptimizedIr::Ir(Ir::Loop(IrLoopType::End, start)) => {
+                    let entry = outer.pop().expect("Brackets are balanced");
+                    // Even if a move in the body wrapped, the pointer is back
+                    // where it was, which is also where it is when resuming here
+                    bounds = if balanced[*start] { entry } else { None };
+                    before[index] = bounds;
+                }
+                // The body runs wherever the procedure is called from
+                OptimizedIr::Ir(Ir::Procedure(IrLoopType::Start, _)) => {
+                    outer.push(bounds);
+                    bounds = None;
+                }
+                OptimizedIr::Ir(Ir::Procedure(IrLoopType::End, _)) => {
+                    bounds = outer.pop().expect("Procedures are balanced");
+                }
+                OptimizedIr::Ir(Ir::Call) => bounds = None,
+                _ => {}
+            }
+        }
+        PointerBounds { before, wraps }
+    }
+
+    /// The cells the pointer can be on when the op at `index` runs, if known.
+    pub fn before(&self, index: usize) -> Option<RangeInclusive<usize>> {
+        self.before[index].map(|(low, high)| low..=high)
+    }
+
+    /// Whether the `move` or `addzero` at `index` can reach past an end of the
+    /// tape and so needs to wrap around.
+    pub fn may_wrap(&self, index: usize) -> bool {
+        self.wraps[index]
+    }
+}
+
+/// Whether each op is the `[` of a loop whose body moves the pointer back to
+/// where it started, with every loop inside it doing the same and no calls.
+fn balanced_loops(ir_ops: &[OptimizedIr]) -> Vec<bool> {
+    let mut balanced = vec![false; ir_ops.len()];
+    // Net movement so far through every loop and procedure body around the op,
+    // if known
+    let mut bodies: Vec<(usize, Option<isize>)> = Vec::new();
+    for (index, op) in ir_ops.iter().enumerate() {
+        match op {
+            OptimizedIr::Ir(Ir::Move(amount)) => {
+                if let Some((_, Some(net))) = bodies.last_mut() {
+                    *net += amount;
+                }
+            }
+            OptimizedIr::Ir(Ir::Call) => {
+                if let Some((_, net)) = bodies.last_mut() {
+                    *net = None;
+                }
+            }
+            OptimizedIr::Ir(
+                Ir::Loop(IrLoopType::Start, _) | Ir::Procedure(IrLoopType::Start, _),
+            ) => {
+                bodies.push((index, Some(0)));
+            }
+            OptimizedIr::Ir(Ir::Loop(IrLoopType::End, _)) => {
+                let (start, net) = bodies.pop().expect("Brackets are balanced");
+                if net == Some(0) {
+                    balanced[start] = true;
+                } else if let Some((_, outer)) = bodies.last_mut() {
+                    *outer = None;
+                }
+            }
+            OptimizedIr::Ir(Ir::Procedure(IrLoopType::End, _)) => {
+                bodies.pop();
+            }
+            _ => {}
+        }
+    }
+    balanced
+}
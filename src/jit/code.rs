@@ -0,0 +1,126 @@
+// Whole-program machine code detached from the `JITModule` that compiled it,
+// so it can be written to disk and linked again in a later run. Relocations
+// name imports by their `FuncId`, which stays the same as long as every module
+// declares its imports in the same order.
+
+use cranelift::codegen::{
+    binemit::Reloc,
+    ir::{ExternalName, Function, Signature, UserExternalName, UserFuncName},
+    CompiledCode, FinalizedMachReloc, FinalizedRelocTarget,
+};
+use cranelift_module::FuncId;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MachineCode {
+    alignment: u64,
+    bytes: Vec<u8>,
+    relocs: Vec<CodeReloc>,
+    map: IrMap,
+}
+
+/// The IR positions behind the numbers the code uses at run time, to convert
+/// its state to and from a `Snapshot`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(super) struct IrMap {
+    /// Length of the tape the code was compiled for
+    pub tape_len: usize,
+    /// Cells the pointer can be on at each entry point, if known; see
+    /// `bounds::PointerBounds`
+    pub entry_bounds: Vec<Option<(usize, usize)>>,
+    /// IR index of the `]` each entry point after the first starts at; entry 0
+    /// is the start of the program
+    pub entries: Vec<usize>,
+    /// IR index of the `Procedure(Start)` of every procedure, by number - 1
+    pub procedures: Vec<usize>,
+    /// IR index of every call site, by number - 1
+    pub call_sites: Vec<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CodeReloc {
+    offset: u32,
+    kind: Reloc,
+    target: RelocTarget,
+    addend: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum RelocTarget {
+    /// An imported function, by `FuncId`
+    Import(u32),
+    /// An offset into the code itself
+    Offset(u32),
+}
+
+impl MachineCode {
+    pub(super) fn new(compiled: &CompiledCode, func: &Function, map: IrMap) -> Self {
+        let relocs = compiled
+            .buffer
+            .relocs()
+            .iter()
+            .map(|reloc| CodeReloc {
+                offset: reloc.offset,
+                kind: reloc.kind,
+                target: match &reloc.target {
+                    FinalizedRelocTarget::ExternalName(ExternalName::User(name)) => {
+                        RelocTarget::Import(func.params.user_named_funcs()[*name].index)
+                    }
+                    FinalizedRelocTarget::Func(offset) => RelocTarget::Offset(*offset),
+                    FinalizedRelocTarget::ExternalName(name) => {
+                        unreachable!("Generated code never references {name:?}")
+                    }
+                },
+                addend: reloc.addend,
+            })
+            .collect();
+
+        MachineCode {
+            alignment: compiled.buffer.alignment as u64,
+            bytes: compiled.code_buffer().to_vec(),
+            relocs,
+            map,
+        }
+    }
+
+    pub(super) fn map(&self) -> &IrMap {
+        &self.map
+    }
+
+    pub fn alignment(&self) -> u64 {
+        self.alignment
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Relocations for `Module::define_function_bytes`, along with the stub
+    /// function they refer to imports through.
+    pub(super) fn relocs(
+        &self,
+        func_id: FuncId,
+        signature: Signature,
+    ) -> (Function, Vec<FinalizedMachReloc>) {
+        let mut func =
+            Function::with_name_signature(UserFuncName::user(0, func_id.as_u32()), signature);
+        let relocs = self
+            .relocs
+            .iter()
+            .map(|reloc| FinalizedMachReloc {
+                offset: reloc.offset,
+                kind: reloc.kind,
+                target: match reloc.target {
+                    RelocTarget::Import(index) => {
+                        FinalizedRelocTarget::ExternalName(ExternalName::User(
+                            func.declare_imported_user_function(UserExternalName::new(0, index)),
+                        ))
+                    }
+                    RelocTarget::Offset(offset) => FinalizedRelocTarget::Func(offset),
+                },
+                addend: reloc.addend,
+            })
+            .collect();
+        (func, relocs)
+    }
+}
@@ -1,101 +1,84 @@
-use std::{
-    cell::RefCell,
-    collections::VecDeque,
-    io::{BufWriter, Write},
-    sync::atomic::{AtomicBool, Ordering},
-};
+// The generated code calls back into these with the IO pointer it was given as
+// the first argument. All state lives behind that pointer, so any number of
+// programs can run at once.
 
-// INPUT
-// =====
-#[no_mangle]
-pub extern "C" fn io_input(input_buffer: *const i64) -> u8 {
-    let input_buffer =
-        unsafe { std::mem::transmute::<*const i64, &mut VecDeque<char>>(input_buffer) };
-
-    if input_buffer.len() == 0 {
-        let mut line = String::new();
-        std::io::stdin()
-            .read_line(&mut line)
-            .expect("Failed to read line");
-        line = line.replace("\r\n", "\n");
-        input_buffer.extend(line.chars());
-    }
-
-    let character = input_buffer.pop_front().expect("No more input");
+use crate::{interpreter::dump_tape, io::Io};
 
-    if character == '\n' {
-        10
-    } else {
-        character as u8
-    }
-}
-#[no_mangle]
-pub extern "C" fn io_input_noop(_: i64) -> u8 {
-    0
-}
 // ======
-// OUTPUT
+// IGNORE
 // ======
-thread_local! {
-    static WRITER: RefCell<Option<(BufWriter<std::io::Stdout>, usize)>> = RefCell::new(None);
-}
-static ATEXIT_REGISTERED: AtomicBool = AtomicBool::new(false);
-extern "C" {
-    fn atexit(cb: extern "C" fn()) -> i32;
-}
-extern "C" fn flush_at_exit() {
-    WRITER.with(|w| {
-        if let Some(mut writer) = w.borrow_mut().take() {
-            let _ = writer.0.flush();
-        }
-    });
+#[no_mangle]
+pub extern "C" fn io_input_noop(_: i64) -> i32 {
+    -1
 }
 #[no_mangle]
-pub extern "C" fn io_output(value: u8) {
-    // Register atexit handler if not already done
-    if !ATEXIT_REGISTERED.load(Ordering::Relaxed) {
-        unsafe {
-            atexit(flush_at_exit);
-        }
-        ATEXIT_REGISTERED.store(true, Ordering::Relaxed);
-    }
-
-    const FLUSH_THRESHOLD: usize = 80; // Flush after this many characters
-
-    WRITER.with(|w| {
-        let mut w_ref = w.borrow_mut();
-        if w_ref.is_none() {
-            *w_ref = Some((BufWriter::with_capacity(4096, std::io::stdout()), 0));
-        }
+pub extern "C" fn io_output_noop(_: i64, _: u8) {}
+#[no_mangle]
+pub extern "C" fn io_debug_noop(_: i64, _: i64, _: i64, _: i64) {}
+// ==========
+// PROGRAM IO
+// ==========
+/// What the `io_context_*` functions get as their first argument: the `Io` a
+/// program runs against, and the first error it returned. Once there is an
+/// error, input is at its end and output is dropped.
+pub struct IoContext<'a> {
+    io: &'a mut dyn Io,
+    error: Option<std::io::Error>,
+}
 
-        let (writer, char_count) = w_ref.as_mut().unwrap();
+impl<'a> IoContext<'a> {
+    pub fn new(io: &'a mut dyn Io) -> Self {
+        IoContext { io, error: None }
+    }
 
-        if value == 10 {
-            if cfg!(windows) {
-                writer
-                    .write_all(b"\r\n")
-                    .expect("Failed to write to stdout");
-            } else {
-                writer.write_all(b"\n").expect("Failed to write to stdout");
-            }
-            // Always flush on newlines for interactive behavior
-            writer.flush().expect("Failed to flush stdout");
-            *char_count = 0; // Reset counter after flush
-        } else {
-            writer
-                .write_all(&[value])
-                .expect("Failed to write to stdout");
+    pub fn io(&mut self) -> &mut dyn Io {
+        self.io
+    }
 
-            *char_count += 1;
+    /// The first error since the last call, if any.
+    pub fn take_error(&mut self) -> std::io::Result<()> {
+        self.error.take().map_or(Ok(()), Err)
+    }
 
-            // Also flush after threshold characters without a newline
-            if *char_count >= FLUSH_THRESHOLD {
-                writer.flush().expect("Failed to flush stdout");
-                *char_count = 0; // Reset counter after flush
-            }
+    fn attempt<T>(&mut self, f: impl FnOnce(&mut dyn Io) -> std::io::Result<T>) -> Option<T> {
+        if self.error.is_some() {
+            return None;
         }
-    });
+        f(self.io).map_err(|error| self.error = Some(error)).ok()
+    }
 }
+
+/// # Safety
+///
+/// `context` must point to the `IoContext` passed to the running code.
 #[no_mangle]
-pub extern "C" fn io_output_noop(_: u8) {}
-// ======
+pub unsafe extern "C" fn io_context_input(context: *mut IoContext) -> i32 {
+    let context = unsafe { &mut *context };
+    match context.attempt(|io| io.read()) {
+        Some(Some(value)) => value as i32,
+        _ => -1,
+    }
+}
+/// # Safety
+///
+/// `context` must point to the `IoContext` passed to the running code.
+#[no_mangle]
+pub unsafe extern "C" fn io_context_output(context: *mut IoContext, value: u8) {
+    let context = unsafe { &mut *context };
+    context.attempt(|io| io.write(value));
+}
+/// # Safety
+///
+/// `context` must point to the `IoContext` passed to the running code, and
+/// `memory` to its tape of `memory_len` cells.
+#[no_mangle]
+pub unsafe extern "C" fn io_context_debug(
+    context: *mut IoContext,
+    memory: *const u8,
+    memory_len: i64,
+    data_offset: i64,
+) {
+    let context = unsafe { &mut *context };
+    let memory = unsafe { std::slice::from_raw_parts(memory, memory_len as usize) };
+    context.attempt(|io| io.debug(&dump_tape(memory, data_offset as usize)));
+}
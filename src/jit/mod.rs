@@ -1,20 +1,40 @@
-use cranelift::{codegen::ir::UserFuncName, prelude::*};
+use bounds::PointerBounds;
+use code::{IrMap, MachineCode};
+use cranelift::{
+    codegen::{
+        control::ControlPlane,
+        ir::{BlockCall, FuncRef, UserFuncName},
+        isa::OwnedTargetIsa,
+    },
+    prelude::*,
+};
 use cranelift_jit::{JITBuilder, JITModule};
-use cranelift_module::{default_libcall_names, Linkage, Module};
-use io::{io_input, io_input_noop, io_output, io_output_noop};
-use std::{
-    collections::{HashMap, VecDeque},
-    mem,
+use cranelift_module::{default_libcall_names, FuncId, Linkage, Module};
+use io::{
+    io_context_debug, io_context_input, io_context_output, io_debug_noop, io_input_noop,
+    io_output_noop, IoContext,
 };
+use procedures::{procedure_call, procedure_define, procedure_return, Procedures};
+use std::{collections::HashMap, mem};
 
+pub mod bounds;
+pub mod code;
 pub mod io;
+pub mod procedures;
+pub mod tiered;
 
 use crate::{
+    cancel::CancellationToken,
+    interpreter::RuntimeError,
+    io::{Io, StdIo},
     optimizer::OptimizedIr,
-    parser::{Ir, IrLoopType},
+    parser::{ExtendedOp, Ir, IrLoopType},
+    snapshot::{PendingIo, Snapshot, SnapshotError},
+    tape::{EofPolicy, InvalidTapeLength, TapeConfig},
 };
 
-pub fn jit(ir_ops: impl AsRef<[OptimizedIr]>, ignore_io: bool) {
+/// Cranelift ISA for the host machine, shared by every JIT tier.
+fn host_isa() -> OwnedTargetIsa {
     let mut flag_builder = settings::builder();
     flag_builder
         .set("use_colocated_libcalls", "false")
@@ -26,56 +46,381 @@ pub fn jit(ir_ops: impl AsRef<[OptimizedIr]>, ignore_io: bool) {
     let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
         panic!("host machine is not supported: {msg}");
     });
-    let isa = isa_builder
+    isa_builder
         .finish(settings::Flags::new(flag_builder))
-        .unwrap();
-    let mut jit_builder = JITBuilder::with_isa(isa, default_libcall_names());
-    jit_builder.symbol(
-        "__io_output",
-        if ignore_io {
-            io_output_noop as *const u8
-        } else {
-            io_output as *const u8
-        },
-    );
-    jit_builder.symbol(
-        "__io_input",
-        if ignore_io {
-            io_input_noop as *const u8
-        } else {
-            io_input as *const u8
-        },
-    );
-    let mut module = JITModule::new(jit_builder);
+        .unwrap()
+}
 
-    // IO functions
+/// What the `__io_*` imports are linked to. All of them take the IO pointer
+/// passed to the generated code as their first argument.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum IoLinkage {
+    /// No input, and output is discarded
+    Ignore,
+    /// An `io::IoContext`
+    Context,
+}
+
+fn link_io(jit_builder: &mut JITBuilder, linkage: IoLinkage) {
+    let (input, output, debug) = match linkage {
+        IoLinkage::Ignore => (
+            io_input_noop as *const u8,
+            io_output_noop as *const u8,
+            io_debug_noop as *const u8,
+        ),
+        IoLinkage::Context => (
+            io_context_input as *const u8,
+            io_context_output as *const u8,
+            io_context_debug as *const u8,
+        ),
+    };
+    jit_builder.symbol("__io_input", input);
+    jit_builder.symbol("__io_output", output);
+    jit_builder.symbol("__io_debug", debug);
+}
+
+/// The `__io_*` imports, declared against the symbols registered by `link_io`.
+struct IoFuncs {
+    input: FuncId,
+    output: FuncId,
+    debug: FuncId,
+}
+
+fn declare_io(module: &mut JITModule) -> IoFuncs {
     let mut io_output_sig = module.make_signature();
+    io_output_sig.params.push(AbiParam::new(types::I64));
     io_output_sig.params.push(AbiParam::new(types::I8));
-    let io_output_func = module
+    let output = module
         .declare_function("__io_output", Linkage::Import, &io_output_sig)
         .unwrap();
     let mut io_input_sig = module.make_signature();
     io_input_sig.params.push(AbiParam::new(types::I64));
-    io_input_sig.returns.push(AbiParam::new(types::I8));
-    let io_input_func = module
+    io_input_sig.returns.push(AbiParam::new(types::I32));
+    let input = module
         .declare_function("__io_input", Linkage::Import, &io_input_sig)
         .unwrap();
+    let mut io_debug_sig = module.make_signature();
+    io_debug_sig.params.push(AbiParam::new(types::I64));
+    io_debug_sig.params.push(AbiParam::new(types::I64));
+    io_debug_sig.params.push(AbiParam::new(types::I64));
+    io_debug_sig.params.push(AbiParam::new(types::I64));
+    let debug = module
+        .declare_function("__io_debug", Linkage::Import, &io_debug_sig)
+        .unwrap();
+    IoFuncs {
+        input,
+        output,
+        debug,
+    }
+}
 
-    let mut ctx = module.make_context();
-    let mut func_ctx = FunctionBuilderContext::new();
+/// Operations that end a block: loops, procedures, calls and `@`
+fn is_control_flow(ir: &OptimizedIr) -> bool {
+    matches!(
+        ir,
+        OptimizedIr::Ir(
+            Ir::Loop(_, _) | Ir::Procedure(_, _) | Ir::Call | Ir::Extended(ExtendedOp::End)
+        )
+    )
+}
+
+/// What the ops that don't affect control flow are emitted against.
+struct OpEmitter {
+    memory_ptr: Value,
+    /// `memory_ptr + memory_len`, one past the last cell
+    memory_end: Value,
+    memory_len: Value,
+    tape_len: usize,
+    /// Passed to every IO import, see `IoLinkage`
+    io_ptr: Value,
+    /// Address of the current cell, less `offset`
+    data_ptr: Variable,
+    /// Moves that can't wrap since `data_ptr` was last defined, folded into
+    /// the offsets of loads and stores until the end of the block
+    offset: i32,
+    /// Extended Brainfuck storage byte
+    storage: Variable,
+    input: FuncRef,
+    output: FuncRef,
+    debug: FuncRef,
+    eof: EofPolicy,
+}
+
+impl OpEmitter {
+    /// `pointer + amount`, wrapped around the tape by comparing against
+    /// whichever end of it the move heads for
+    fn wrap(&self, builder: &mut FunctionBuilder, pointer: Value, amount: isize) -> Value {
+        let len = self.tape_len as i64;
+        let amount = amount as i64 % len;
+        let moved = builder.ins().iadd_imm(pointer, amount);
+        let (outside, wrapped) = if amount >= 0 {
+            (
+                builder
+                    .ins()
+                    .icmp(IntCC::UnsignedGreaterThanOrEqual, moved, self.memory_end),
+                builder.ins().iadd_imm(moved, -len),
+            )
+        } else {
+            (
+                builder
+                    .ins()
+                    .icmp(IntCC::UnsignedLessThan, moved, self.memory_ptr),
+                builder.ins().iadd_imm(moved, len),
+            )
+        };
+        builder.ins().select(outside, wrapped, moved)
+    }
+
+    /// Applies the folded moves to `data_ptr`, which must be done before the
+    /// end of every block.
+    fn flush(&mut self, builder: &mut FunctionBuilder) {
+        if self.offset != 0 {
+            let data_ptr = builder.use_var(self.data_ptr);
+            let data_ptr = builder.ins().iadd_imm(data_ptr, self.offset as i64);
+            builder.def_var(self.data_ptr, data_ptr);
+            self.offset = 0;
+        }
+    }
+
+    /// The address and immediate offset of the cell `amount` past the current
+    /// one, which must not wrap.
+    fn cell(&self, builder: &mut FunctionBuilder, amount: isize) -> (Value, i32) {
+        let data_ptr = builder.use_var(self.data_ptr);
+        let offset = self.offset as isize + amount;
+        match i32::try_from(offset) {
+            Ok(offset) => (data_ptr, offset),
+            Err(_) => (builder.ins().iadd_imm(data_ptr, offset as i64), 0),
+        }
+    }
+
+    fn load(&self, builder: &mut FunctionBuilder) -> Value {
+        let (address, offset) = self.cell(builder, 0);
+        builder
+            .ins()
+            .load(types::I8, MemFlags::new(), address, offset)
+    }
+
+    fn store(&self, builder: &mut FunctionBuilder, value: Value) {
+        let (address, offset) = self.cell(builder, 0);
+        builder.ins().store(MemFlags::new(), value, address, offset);
+    }
+
+    /// Emits `ir`, leaving out the wraparound of a `move` or `addzero` unless
+    /// it `may_wrap`.
+    fn emit(&mut self, builder: &mut FunctionBuilder, ir: &OptimizedIr, may_wrap: bool) {
+        let storage = self.storage;
+        let io_ptr = self.io_ptr;
+        match ir {
+            OptimizedIr::Ir(ir) => match ir {
+                Ir::Data(amount) => {
+                    // Increase the value at the memory pointer by the amount
+                    let memory_value = self.load(builder);
+                    let constant = builder.ins().iconst(types::I8, *amount);
+                    let new_memory_value = builder.ins().iadd(memory_value, constant);
+                    self.store(builder, new_memory_value);
+                }
+                Ir::Move(amount) => {
+                    let folded = i32::try_from(self.offset as isize + amount);
+                    match folded {
+                        Ok(offset) if !may_wrap => self.offset = offset,
+                        _ => {
+                            self.flush(builder);
+                            let data_ptr = builder.use_var(self.data_ptr);
+                            let data_ptr = if may_wrap {
+                                self.wrap(builder, data_ptr, *amount)
+                            } else {
+                                builder.ins().iadd_imm(data_ptr, *amount as i64)
+                            };
+                            builder.def_var(self.data_ptr, data_ptr);
+                        }
+                    }
+                }
+                Ir::IO(true) => {
+                    let result = builder.ins().call(self.input, &[io_ptr]);
+                    let result = builder.inst_results(result)[0];
+                    // -1 marks the end of the input, which truncates to 255
+                    let value = builder.ins().ireduce(types::I8, result);
+                    let replacement = match self.eof {
+                        EofPolicy::MinusOne => None,
+                        EofPolicy::Zero => Some(builder.ins().iconst(types::I8, 0)),
+                        EofPolicy::Unchanged => Some(self.load(builder)),
+                    };
+                    let value = match replacement {
+                        Some(replacement) => {
+                            let eof = builder.ins().icmp_imm(IntCC::SignedLessThan, result, 0);
+                            builder.ins().select(eof, replacement, value)
+                        }
+                        None => value,
+                    };
+                    self.store(builder, value);
+                }
+                Ir::IO(false) => {
+                    let memory_value = self.load(builder);
+                    builder.ins().call(self.output, &[io_ptr, memory_value]);
+                }
+                Ir::Extended(ExtendedOp::Store) => {
+                    let memory_value = self.load(builder);
+                    builder.def_var(storage, memory_value);
+                }
+                Ir::Extended(op) => {
+                    let memory_value = self.load(builder);
+                    let storage_value = builder.use_var(storage);
+                    let new_memory_value = match op {
+                        ExtendedOp::Load => storage_value,
+                        ExtendedOp::ShiftRight => builder.ins().ushr_imm(memory_value, 1),
+                        ExtendedOp::ShiftLeft => builder.ins().ishl_imm(memory_value, 1),
+                        ExtendedOp::Not => builder.ins().bnot(memory_value),
+                        ExtendedOp::Xor => builder.ins().bxor(memory_value, storage_value),
+                        ExtendedOp::And => builder.ins().band(memory_value, storage_value),
+                        ExtendedOp::Or => builder.ins().bor(memory_value, storage_value),
+                        ExtendedOp::End | ExtendedOp::Store => unreachable!(),
+                    };
+                    self.store(builder, new_memory_value);
+                }
+                Ir::Debug => {
+                    let (address, offset) = self.cell(builder, 0);
+                    let address = builder.ins().iadd_imm(address, offset as i64);
+                    let data_offset = builder.ins().isub(address, self.memory_ptr);
+                    builder.ins().call(
+                        self.debug,
+                        &[io_ptr, self.memory_ptr, self.memory_len, data_offset],
+                    );
+                }
+                _ => unreachable!("{ir:?} ends a block"),
+            },
+            OptimizedIr::ResetToZero => {
+                let constant = builder.ins().iconst(types::I8, 0);
+                self.store(builder, constant);
+            }
+            OptimizedIr::AddAndZero(target) => {
+                let source_value = self.load(builder);
+                let (target_ptr, target_offset) = if may_wrap {
+                    self.flush(builder);
+                    let data_ptr = builder.use_var(self.data_ptr);
+                    (self.wrap(builder, data_ptr, *target), 0)
+                } else {
+                    self.cell(builder, *target)
+                };
+                let target_value =
+                    builder
+                        .ins()
+                        .load(types::I8, MemFlags::new(), target_ptr, target_offset);
+                let new_target_value = builder.ins().iadd(target_value, source_value);
+                builder
+                    .ins()
+                    .store(MemFlags::new(), new_target_value, target_ptr, target_offset);
+
+                let constant = builder.ins().iconst(types::I8, 0);
+                self.store(builder, constant);
+            }
+        }
+    }
+}
+
+/// The target `jit` compiles for, in enough detail to tell whether machine code
+/// from another run can be reused.
+pub fn target_description() -> String {
+    let isa = host_isa();
+    let isa_flags = isa
+        .isa_flags()
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    format!("{} {} {}", isa.triple(), isa_flags.join(" "), isa.flags())
+}
+
+/// A module for whole-program code with every import declared. The order of
+/// declarations is fixed so `FuncId`s in cached `MachineCode` stay valid.
+struct ProgramModule {
+    module: JITModule,
+    io: IoFuncs,
+    procedure_define: FuncId,
+    procedure_call: FuncId,
+    procedure_return: FuncId,
+    main_func: FuncId,
+    main_sig: Signature,
+}
+
+fn program_module(io_linkage: IoLinkage) -> ProgramModule {
+    let mut jit_builder = JITBuilder::with_isa(host_isa(), default_libcall_names());
+    link_io(&mut jit_builder, io_linkage);
+    jit_builder.symbol("__procedure_define", procedure_define as *const u8);
+    jit_builder.symbol("__procedure_call", procedure_call as *const u8);
+    jit_builder.symbol("__procedure_return", procedure_return as *const u8);
+    let mut module = JITModule::new(jit_builder);
+
+    // IO functions
+    let io_funcs = declare_io(&mut module);
 
+    // pbrain procedure functions
+    let mut procedure_define_sig = module.make_signature();
+    procedure_define_sig.params.push(AbiParam::new(types::I64));
+    procedure_define_sig.params.push(AbiParam::new(types::I8));
+    procedure_define_sig.params.push(AbiParam::new(types::I32));
+    let procedure_define_func = module
+        .declare_function("__procedure_define", Linkage::Import, &procedure_define_sig)
+        .unwrap();
+    let mut procedure_call_sig = module.make_signature();
+    procedure_call_sig.params.push(AbiParam::new(types::I64));
+    procedure_call_sig.params.push(AbiParam::new(types::I8));
+    procedure_call_sig.params.push(AbiParam::new(types::I32));
+    procedure_call_sig.returns.push(AbiParam::new(types::I32));
+    let procedure_call_func = module
+        .declare_function("__procedure_call", Linkage::Import, &procedure_call_sig)
+        .unwrap();
+    let mut procedure_return_sig = module.make_signature();
+    procedure_return_sig.params.push(AbiParam::new(types::I64));
+    procedure_return_sig.returns.push(AbiParam::new(types::I32));
+    let procedure_return_func = module
+        .declare_function("__procedure_return", Linkage::Import, &procedure_return_sig)
+        .unwrap();
+
+    // Memory, its length, IO, procedures, the cancellation flag and the
+    // `MachineState`
     let mut func_sig = module.make_signature();
-    func_sig.params.push(AbiParam::new(types::I64));
-    func_sig.params.push(AbiParam::new(types::I64));
-    func_sig.params.push(AbiParam::new(types::I64));
+    for _ in 0..6 {
+        func_sig.params.push(AbiParam::new(types::I64));
+    }
 
     let main_func = module
         .declare_function("main_func", Linkage::Local, &func_sig)
         .unwrap();
 
+    ProgramModule {
+        module,
+        io: io_funcs,
+        procedure_define: procedure_define_func,
+        procedure_call: procedure_call_func,
+        procedure_return: procedure_return_func,
+        main_func,
+        main_sig: func_sig,
+    }
+}
+
+/// Compiles `ir_ops` into machine code for the host, to be run with `run`.
+pub fn compile(
+    ir_ops: impl AsRef<[OptimizedIr]>,
+    tape: TapeConfig,
+) -> Result<MachineCode, InvalidTapeLength> {
+    tape.validate()?;
+    // Which IO functions are linked in makes no difference to the code
+    let ProgramModule {
+        mut module,
+        io: io_funcs,
+        procedure_define: procedure_define_func,
+        procedure_call: procedure_call_func,
+        procedure_return: procedure_return_func,
+        main_func,
+        main_sig: func_sig,
+    } = program_module(IoLinkage::Ignore);
+
+    let mut ctx = module.make_context();
+    let mut func_ctx = FunctionBuilderContext::new();
+
     ctx.func.signature = func_sig;
     ctx.func.name = UserFuncName::user(0, main_func.as_u32());
 
+    let map;
     {
         let mut builder: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
         let entry_block = builder.create_block();
@@ -85,37 +430,75 @@ pub fn jit(ir_ops: impl AsRef<[OptimizedIr]>, ignore_io: bool) {
 
         let memory_ptr = builder.block_params(entry_block)[0];
         let memory_len = builder.block_params(entry_block)[1];
-        let input_buffer_ptr = builder.block_params(entry_block)[2];
+        let io_ptr = builder.block_params(entry_block)[2];
+        let procedures_ptr = builder.block_params(entry_block)[3];
+        let cancel_flag = builder.block_params(entry_block)[4];
+        let state_ptr = builder.block_params(entry_block)[5];
 
         // Data pointer variable
-        let data_offset = Variable::new(0);
-        let data_ptr = Variable::new(1);
-        builder.declare_var(data_offset, types::I64);
+        let data_ptr = Variable::new(0);
         builder.declare_var(data_ptr, types::I64);
-        builder.def_var(data_ptr, memory_ptr);
+        let data_offset_value = builder.ins().load(
+            types::I64,
+            MemFlags::trusted(),
+            state_ptr,
+            mem::offset_of!(MachineState, data_offset) as i32,
+        );
+        let data_ptr_value = builder.ins().iadd(memory_ptr, data_offset_value);
+        builder.def_var(data_ptr, data_ptr_value);
+
+        // Extended Brainfuck storage byte
+        let storage = Variable::new(1);
+        builder.declare_var(storage, types::I8);
+        let storage_value = builder.ins().load(
+            types::I8,
+            MemFlags::trusted(),
+            state_ptr,
+            mem::offset_of!(MachineState, storage) as i32,
+        );
+        builder.def_var(storage, storage_value);
 
-        // IO functions
-        let input_callee = module.declare_func_in_func(io_input_func, &mut builder.func);
-        let output_callee = module.declare_func_in_func(io_output_func, &mut builder.func);
+        let mut emitter = OpEmitter {
+            memory_ptr,
+            memory_end: builder.ins().iadd(memory_ptr, memory_len),
+            memory_len,
+            tape_len: tape.len,
+            io_ptr,
+            data_ptr,
+            offset: 0,
+            storage,
+            input: module.declare_func_in_func(io_funcs.input, builder.func),
+            output: module.declare_func_in_func(io_funcs.output, builder.func),
+            debug: module.declare_func_in_func(io_funcs.debug, builder.func),
+            eof: tape.eof,
+        };
+
+        // Procedure functions
+        let procedure_define_callee =
+            module.declare_func_in_func(procedure_define_func, builder.func);
+        let procedure_call_callee = module.declare_func_in_func(procedure_call_func, builder.func);
+        let procedure_return_callee =
+            module.declare_func_in_func(procedure_return_func, builder.func);
 
         // Pre-create an exit block for use when index+1 is out of range.
         let exit_block = builder.create_block();
+        // Back-edges go here once cancelled, passing the index of their `]`
+        let cancel_block = builder.create_block();
+        builder.append_block_param(cancel_block, types::I64);
 
         let ir_ops = ir_ops.as_ref();
+        let bounds = PointerBounds::analyze(ir_ops, tape.len);
         // First pass to create the blocks
         let mut operation_to_block = HashMap::new();
         for (index, ir) in ir_ops.iter().enumerate() {
-            match ir {
-                OptimizedIr::Ir(Ir::Loop(_, _)) => {
-                    operation_to_block.insert(index, builder.create_block());
-                }
-                _ => {} // do nothing
+            if is_control_flow(ir) {
+                operation_to_block.insert(index, builder.create_block());
             }
         }
-        // Also create blocks for the successor of each loop instruction.
+        // Also create blocks for the successor of each control flow instruction.
         // If index+1 is beyond the end, use exit_block.
         for (index, ir) in ir_ops.iter().enumerate() {
-            if let OptimizedIr::Ir(Ir::Loop(_, _)) = ir {
+            if is_control_flow(ir) {
                 let next_index = index + 1;
                 if next_index < ir_ops.len() {
                     operation_to_block
@@ -127,12 +510,56 @@ pub fn jit(ir_ops: impl AsRef<[OptimizedIr]>, ignore_io: bool) {
             }
         }
 
+        // Procedures are numbered from 1 in program order, call sites likewise;
+        // 0 means "no procedure" and "empty call stack" respectively
+        let procedure_numbers = ir_ops
+            .iter()
+            .enumerate()
+            .filter(|(_, ir)| matches!(ir, OptimizedIr::Ir(Ir::Procedure(IrLoopType::Start, _))))
+            .enumerate()
+            .map(|(number, (index, _))| (index, number as u32 + 1))
+            .collect::<HashMap<_, _>>();
+        let call_sites = ir_ops
+            .iter()
+            .enumerate()
+            .filter(|(_, ir)| matches!(ir, OptimizedIr::Ir(Ir::Call)))
+            .enumerate()
+            .map(|(number, (index, _))| (index, number as u32 + 1))
+            .collect::<HashMap<_, _>>();
+
+        // Resuming from a snapshot enters at a `]`, which re-tests its cell
+        let start_block = builder.create_block();
+        let entries = ir_ops
+            .iter()
+            .enumerate()
+            .filter(|(_, ir)| matches!(ir, OptimizedIr::Ir(Ir::Loop(IrLoopType::End, _))))
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+        let entry = builder.ins().load(
+            types::I64,
+            MemFlags::trusted(),
+            state_ptr,
+            mem::offset_of!(MachineState, position) as i32,
+        );
+        let entry = builder.ins().ireduce(types::I32, entry);
+        let entry_blocks = std::iter::once(start_block)
+            .chain(entries.iter().map(|index| operation_to_block[index]))
+            .map(|block| builder.func.dfg.block_call(block, &[]))
+            .collect::<Vec<BlockCall>>();
+        let default = builder.func.dfg.block_call(start_block, &[]);
+        let jump_table = builder.create_jump_table(JumpTableData::new(default, &entry_blocks));
+        builder.ins().br_table(entry, jump_table);
+        builder.switch_to_block(start_block);
+
         // Second pass for compiling the operations
-        let mut _current_block = entry_block;
+        let mut _current_block = start_block;
         let mut current_block_index = -1;
         let mut skip_next_jump = false;
-        for (index, ir) in ir_ops.iter().enumerate() {
+        for (index, op) in ir_ops.iter().enumerate() {
             let index_block = operation_to_block.get(&index);
+            if index_block.is_some() {
+                emitter.flush(&mut builder);
+            }
             if let Some(block) = index_block {
                 if index as i32 != current_block_index {
                     if !skip_next_jump {
@@ -146,51 +573,8 @@ pub fn jit(ir_ops: impl AsRef<[OptimizedIr]>, ignore_io: bool) {
                 }
             }
 
-            match ir {
-                OptimizedIr::Ir(ir) => match ir {
-                    Ir::Data(amount) => {
-                        let data_ptr_val = builder.use_var(data_ptr);
-
-                        // Increase the value at the memory pointer by the amount
-                        let memory_value =
-                            builder
-                                .ins()
-                                .load(types::I8, MemFlags::new(), data_ptr_val, 0);
-                        let constant = builder.ins().iconst(types::I8, *amount as i64);
-                        let new_memory_value = builder.ins().iadd(memory_value, constant);
-                        builder
-                            .ins()
-                            .store(MemFlags::new(), new_memory_value, data_ptr_val, 0);
-                    }
-                    Ir::Move(amount) => {
-                        let mut data_offset_var = builder.use_var(data_offset);
-                        data_offset_var = builder.ins().iadd_imm(data_offset_var, *amount as i64);
-                        let remainder = builder.ins().srem(data_offset_var, memory_len);
-                        let less_than_zero =
-                            builder.ins().icmp_imm(IntCC::SignedLessThan, remainder, 0);
-                        let increased_data_offset = builder.ins().iadd(remainder, memory_len);
-                        data_offset_var =
-                            builder
-                                .ins()
-                                .select(less_than_zero, increased_data_offset, remainder);
-                        builder.def_var(data_offset, data_offset_var);
-
-                        // update the data_offset_ptr
-                        let data_ptr_val = builder.ins().iadd(memory_ptr, data_offset_var);
-                        builder.def_var(data_ptr, data_ptr_val);
-                    }
-                    Ir::IO(true) => {
-                        let data_ptr = builder.use_var(data_ptr);
-                        let result = builder.ins().call(input_callee, &[input_buffer_ptr]);
-                        let result = builder.inst_results(result)[0];
-                        builder.ins().store(MemFlags::new(), result, data_ptr, 0);
-                    }
-                    Ir::IO(false) => {
-                        let data_ptr = builder.use_var(data_ptr);
-                        let memory_value =
-                            builder.ins().load(types::I8, MemFlags::new(), data_ptr, 0);
-                        builder.ins().call(output_callee, &[memory_value]);
-                    }
+            match op {
+                OptimizedIr::Ir(ir) if is_control_flow(op) => match ir {
                     Ir::Loop(IrLoopType::Start, jump_index) => {
                         let jump_block = operation_to_block
                             .get(&(jump_index + 1))
@@ -211,55 +595,283 @@ pub fn jit(ir_ops: impl AsRef<[OptimizedIr]>, ignore_io: bool) {
                     Ir::Loop(IrLoopType::End, jump_index) => {
                         let jump_block =
                             operation_to_block.get(jump_index).expect("Block not found");
-                        builder.ins().jump(*jump_block, &[]);
+                        let cancelled =
+                            builder
+                                .ins()
+                                .load(types::I8, MemFlags::trusted(), cancel_flag, 0);
+                        let ir_index = builder.ins().iconst(types::I64, index as i64);
+                        builder
+                            .ins()
+                            .brif(cancelled, cancel_block, &[ir_index], *jump_block, &[]);
                         skip_next_jump = true;
                     }
-                },
-                OptimizedIr::ResetToZero => {
-                    let data_ptr = builder.use_var(data_ptr);
-                    let constant = builder.ins().iconst(types::I8, 0 as i64);
-                    builder.ins().store(MemFlags::new(), constant, data_ptr, 0);
-                }
-                OptimizedIr::AddAndZero(target) => {
-                    let source_ptr = builder.use_var(data_ptr);
-                    let source_value =
-                        builder
+                    Ir::Extended(ExtendedOp::End) => {
+                        builder.ins().jump(exit_block, &[]);
+                        skip_next_jump = true;
+                    }
+                    Ir::Procedure(IrLoopType::Start, end_index) => {
+                        // Register the body under the current cell and skip it
+                        let data_ptr = builder.use_var(data_ptr);
+                        let memory_value =
+                            builder.ins().load(types::I8, MemFlags::new(), data_ptr, 0);
+                        let number = builder
                             .ins()
-                            .load(types::I8, MemFlags::new(), source_ptr, 0);
-
-                    let target_ptr = builder.ins().iadd_imm(source_ptr, *target as i64);
-                    let target_value =
-                        builder
+                            .iconst(types::I32, procedure_numbers[&index] as i64);
+                        builder.ins().call(
+                            procedure_define_callee,
+                            &[procedures_ptr, memory_value, number],
+                        );
+                        let jump_block = operation_to_block
+                            .get(&(end_index + 1))
+                            .expect("Block not found");
+                        builder.ins().jump(*jump_block, &[]);
+                        skip_next_jump = true;
+                    }
+                    Ir::Procedure(IrLoopType::End, _) => {
+                        // Jump back to the block after the call site on top of the call stack
+                        let call_site = builder
                             .ins()
-                            .load(types::I8, MemFlags::new(), target_ptr, 0);
-
-                    let new_target_value = builder.ins().iadd(target_value, source_value);
-                    builder
-                        .ins()
-                        .store(MemFlags::new(), new_target_value, target_ptr, 0);
-
-                    let constant = builder.ins().iconst(types::I8, 0 as i64);
-                    builder
-                        .ins()
-                        .store(MemFlags::new(), constant, source_ptr, 0);
-                }
-                _ => {
-                    // do nothing
-                }
+                            .call(procedure_return_callee, &[procedures_ptr]);
+                        let call_site = builder.inst_results(call_site)[0];
+                        let mut return_blocks = vec![exit_block; call_sites.len() + 1];
+                        for (call_index, number) in &call_sites {
+                            return_blocks[*number as usize] = operation_to_block[&(call_index + 1)];
+                        }
+                        let return_blocks = return_blocks
+                            .into_iter()
+                            .map(|block| builder.func.dfg.block_call(block, &[]))
+                            .collect::<Vec<BlockCall>>();
+                        let default = builder.func.dfg.block_call(exit_block, &[]);
+                        let jump_table =
+                            builder.create_jump_table(JumpTableData::new(default, &return_blocks));
+                        builder.ins().br_table(call_site, jump_table);
+                        skip_next_jump = true;
+                    }
+                    Ir::Call => {
+                        // Jump to the body of the procedure registered under the current cell,
+                        // or straight to the next operation if there is none
+                        let data_ptr = builder.use_var(data_ptr);
+                        let memory_value =
+                            builder.ins().load(types::I8, MemFlags::new(), data_ptr, 0);
+                        let call_site = builder.ins().iconst(types::I32, call_sites[&index] as i64);
+                        let procedure = builder.ins().call(
+                            procedure_call_callee,
+                            &[procedures_ptr, memory_value, call_site],
+                        );
+                        let procedure = builder.inst_results(procedure)[0];
+                        let successor_block = operation_to_block[&(index + 1)];
+                        let mut procedure_blocks =
+                            vec![successor_block; procedure_numbers.len() + 1];
+                        for (procedure_index, number) in &procedure_numbers {
+                            procedure_blocks[*number as usize] =
+                                operation_to_block[&(procedure_index + 1)];
+                        }
+                        let procedure_blocks = procedure_blocks
+                            .into_iter()
+                            .map(|block| builder.func.dfg.block_call(block, &[]))
+                            .collect::<Vec<BlockCall>>();
+                        let default = builder.func.dfg.block_call(successor_block, &[]);
+                        let jump_table = builder
+                            .create_jump_table(JumpTableData::new(default, &procedure_blocks));
+                        builder.ins().br_table(procedure, jump_table);
+                        skip_next_jump = true;
+                    }
+                    _ => unreachable!("{ir:?} does not end a block"),
+                },
+                op => emitter.emit(&mut builder, op, bounds.may_wrap(index)),
             }
         }
 
         if !skip_next_jump {
+            emitter.flush(&mut builder);
             builder.ins().jump(exit_block, &[]);
         }
         builder.switch_to_block(exit_block);
+        let finished = builder.ins().iconst(types::I64, -1);
+        builder.ins().store(
+            MemFlags::trusted(),
+            finished,
+            state_ptr,
+            mem::offset_of!(MachineState, position) as i32,
+        );
+        builder.ins().return_(&[]);
+
+        builder.switch_to_block(cancel_block);
+        let ir_index = builder.block_params(cancel_block)[0];
+        builder.ins().store(
+            MemFlags::trusted(),
+            ir_index,
+            state_ptr,
+            mem::offset_of!(MachineState, position) as i32,
+        );
+        let data_ptr = builder.use_var(data_ptr);
+        let data_offset = builder.ins().isub(data_ptr, memory_ptr);
+        builder.ins().store(
+            MemFlags::trusted(),
+            data_offset,
+            state_ptr,
+            mem::offset_of!(MachineState, data_offset) as i32,
+        );
+        let storage = builder.use_var(storage);
+        builder.ins().store(
+            MemFlags::trusted(),
+            storage,
+            state_ptr,
+            mem::offset_of!(MachineState, storage) as i32,
+        );
         builder.ins().return_(&[]);
         builder.seal_all_blocks();
         builder.finalize();
+
+        let in_order = |numbers: HashMap<usize, u32>| {
+            let mut indices = numbers.into_iter().collect::<Vec<_>>();
+            indices.sort_by_key(|(_, number)| *number);
+            indices.into_iter().map(|(index, _)| index).collect()
+        };
+        map = IrMap {
+            tape_len: tape.len,
+            entry_bounds: std::iter::once(Some((0, 0)))
+                .chain(entries.iter().map(|index| {
+                    bounds
+                        .before(*index)
+                        .map(|bounds| (*bounds.start(), *bounds.end()))
+                }))
+                .collect(),
+            entries,
+            procedures: in_order(procedure_numbers),
+            call_sites: in_order(call_sites),
+        };
+    }
+
+    ctx.compile(module.isa(), &mut ControlPlane::default())
+        .expect("Failed to compile");
+    Ok(MachineCode::new(
+        ctx.compiled_code().unwrap(),
+        &ctx.func,
+        map,
+    ))
+}
+
+/// Machine state the generated code starts from and, once cancelled, stops at
+#[repr(C)]
+struct MachineState {
+    /// On entry the entry point to start at, see `IrMap::entries`. On return
+    /// the IR index of the `]` that noticed cancellation, or -1 if the program
+    /// ran to the end.
+    position: i64,
+    data_offset: i64,
+    storage: u8,
+}
+
+/// Links `code` from `compile` and runs it on a fresh tape against the
+/// terminal, or with no IO at all when `ignore_io` is set.
+pub fn run(code: &MachineCode, tape: TapeConfig, ignore_io: bool) -> Result<(), RuntimeError> {
+    let cancel = CancellationToken::new();
+    if ignore_io {
+        match execute(
+            code,
+            tape,
+            Snapshot::new(tape),
+            IoLinkage::Ignore,
+            0,
+            &cancel,
+        )? {
+            Some(snapshot) => Err(RuntimeError::cancelled(snapshot)),
+            None => Ok(()),
+        }
+    } else {
+        run_with(code, tape, &mut StdIo::default(), &cancel)
+    }
+}
+
+/// Links `code` from `compile` and runs it on a fresh tape against `io`, until
+/// it ends or `cancel` is cancelled.
+pub fn run_with(
+    code: &MachineCode,
+    tape: TapeConfig,
+    io: &mut dyn Io,
+    cancel: &CancellationToken,
+) -> Result<(), RuntimeError> {
+    resume_with(code, tape, Snapshot::new(tape), io, cancel)
+}
+
+/// Like `run_with`, but continues from `snapshot`. Snapshots resume at the
+/// start of the program or at a `]`, which is where all backends stop.
+pub fn resume_with(
+    code: &MachineCode,
+    tape: TapeConfig,
+    snapshot: Snapshot,
+    io: &mut dyn Io,
+    cancel: &CancellationToken,
+) -> Result<(), RuntimeError> {
+    let io = &mut PendingIo::new(&snapshot.pending_input, io);
+    let mut context = IoContext::new(io);
+    let result = execute(
+        code,
+        tape,
+        snapshot,
+        IoLinkage::Context,
+        (&mut context) as *mut IoContext as i64,
+        cancel,
+    );
+    context.take_error()?;
+    match result? {
+        Some(mut snapshot) => {
+            snapshot.pending_input = context.io().pending_input();
+            Err(RuntimeError::cancelled(snapshot))
+        }
+        None => Ok(()),
+    }
+}
+
+/// Runs `code` from `snapshot`, returning where it stopped if it was cancelled.
+fn execute(
+    code: &MachineCode,
+    tape: TapeConfig,
+    snapshot: Snapshot,
+    io_linkage: IoLinkage,
+    io_ptr: i64,
+    cancel: &CancellationToken,
+) -> Result<Option<Snapshot>, SnapshotError> {
+    snapshot.validate_tape(tape)?;
+    let map = code.map();
+    // The code leaves out wraparounds that can't happen on the tape it was
+    // compiled for, starting from where the program can be
+    if tape.len != map.tape_len {
+        return Err(SnapshotError::TapeLength {
+            expected: map.tape_len,
+            found: tape.len,
+        });
     }
+    let entry = match snapshot.ir_index {
+        0 => 0,
+        index => {
+            map.entries
+                .binary_search(&index)
+                .map_err(|_| SnapshotError::NotResumable(index))?
+                + 1
+        }
+    };
+    if let Some((low, high)) = map.entry_bounds[entry] {
+        if !(low..=high).contains(&snapshot.pointer) {
+            return Err(SnapshotError::Unreachable {
+                ir_index: snapshot.ir_index,
+                pointer: snapshot.pointer,
+            });
+        }
+    }
+    let mut procedures = Procedures::from_snapshot(&snapshot, map)?;
 
-    module.define_function(main_func, &mut ctx).unwrap();
-    module.clear_context(&mut ctx);
+    let ProgramModule {
+        mut module,
+        main_func,
+        main_sig,
+        ..
+    } = program_module(io_linkage);
+    let (func, relocs) = code.relocs(main_func, main_sig);
+    module
+        .define_function_bytes(main_func, &func, code.alignment(), code.bytes(), &relocs)
+        .unwrap();
 
     // Perform linking.
     module
@@ -270,15 +882,47 @@ pub fn jit(ir_ops: impl AsRef<[OptimizedIr]>, ignore_io: bool) {
     let code_b = module.get_finalized_function(main_func);
 
     // Cast it to a rust function pointer type.
-    let ptr_b = unsafe { mem::transmute::<_, extern "C" fn(i64, i64, i64)>(code_b) };
+    let ptr_b = unsafe {
+        mem::transmute::<*const u8, extern "C" fn(i64, i64, i64, i64, *const u8, *mut MachineState)>(
+            code_b,
+        )
+    };
 
-    let mut memory = [0u8; 30000];
+    let mut memory = snapshot.tape;
     let memory_ptr = { memory.as_mut_ptr() as *mut i64 };
-    let mut input_buffer: VecDeque<char> = VecDeque::new();
-    let input_buffer_ptr = (&mut input_buffer) as *mut _ as *mut i64;
+    let procedures_ptr = (&mut procedures) as *mut Procedures;
+    let mut state = MachineState {
+        position: entry as i64,
+        data_offset: snapshot.pointer as i64,
+        storage: snapshot.storage,
+    };
     ptr_b(
         memory_ptr as i64,
         memory.len() as i64,
-        input_buffer_ptr as i64,
+        io_ptr,
+        procedures_ptr as i64,
+        cancel.flag(),
+        &mut state,
     );
+
+    if state.position < 0 {
+        return Ok(None);
+    }
+    let mut stopped = Snapshot {
+        tape: memory,
+        pointer: state.data_offset as usize,
+        ir_index: state.position as usize,
+        storage: state.storage,
+        ..Snapshot::new(TapeConfig { len: 0, ..tape })
+    };
+    procedures.save(map, &mut stopped);
+    Ok(Some(stopped))
+}
+
+pub fn jit(
+    ir_ops: impl AsRef<[OptimizedIr]>,
+    tape: TapeConfig,
+    ignore_io: bool,
+) -> Result<(), RuntimeError> {
+    run(&compile(ir_ops, tape)?, tape, ignore_io)
 }
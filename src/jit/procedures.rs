@@ -0,0 +1,105 @@
+// pbrain procedure table and call stack used by the generated code. Procedures
+// and call sites are identified by numbers starting at 1, assigned by the JIT.
+
+use super::code::IrMap;
+use crate::snapshot::{Snapshot, SnapshotError};
+
+pub struct Procedures {
+    table: [u32; 256],
+    call_stack: Vec<u32>,
+}
+
+impl Procedures {
+    pub fn new() -> Self {
+        Procedures {
+            table: [0; 256],
+            call_stack: Vec::new(),
+        }
+    }
+}
+
+impl Procedures {
+    /// The procedures and call stack of `snapshot`, numbered as in `map`.
+    pub(super) fn from_snapshot(snapshot: &Snapshot, map: &IrMap) -> Result<Self, SnapshotError> {
+        let number = |positions: &[usize], index: usize| {
+            positions
+                .iter()
+                .position(|position| *position == index)
+                .map(|position| position as u32 + 1)
+                .ok_or(SnapshotError::NotResumable(index))
+        };
+        let mut procedures = Procedures::new();
+        for (id, index) in &snapshot.procedures {
+            procedures.table[*id as usize] = number(&map.procedures, *index)?;
+        }
+        for index in &snapshot.call_stack {
+            // Return addresses are the op after the call
+            let call = index
+                .checked_sub(1)
+                .ok_or(SnapshotError::NotResumable(*index))?;
+            procedures.call_stack.push(
+                number(&map.call_sites, call).map_err(|_| SnapshotError::NotResumable(*index))?,
+            );
+        }
+        Ok(procedures)
+    }
+
+    /// Stores the procedures and call stack in `snapshot` as IR indices.
+    pub(super) fn save(&self, map: &IrMap, snapshot: &mut Snapshot) {
+        snapshot.procedures = (0..=u8::MAX)
+            .filter(|id| self.table[*id as usize] != 0)
+            .map(|id| (id, map.procedures[self.table[id as usize] as usize - 1]))
+            .collect();
+        snapshot.call_stack = self
+            .call_stack
+            .iter()
+            .map(|call_site| map.call_sites[*call_site as usize - 1] + 1)
+            .collect();
+    }
+}
+
+impl Default for Procedures {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// # Safety
+///
+/// `procedures` must point to the `Procedures` owned by the running `jit` call.
+#[no_mangle]
+pub unsafe extern "C" fn procedure_define(procedures: *mut Procedures, id: u8, procedure: u32) {
+    let procedures = unsafe { &mut *procedures };
+    procedures.table[id as usize] = procedure;
+}
+
+/// Returns the procedure registered under `id` and pushes `call_site` onto the
+/// call stack, or returns 0 without touching the stack if there is none.
+///
+/// # Safety
+///
+/// `procedures` must point to the `Procedures` owned by the running `jit` call.
+#[no_mangle]
+pub unsafe extern "C" fn procedure_call(
+    procedures: *mut Procedures,
+    id: u8,
+    call_site: u32,
+) -> u32 {
+    let procedures = unsafe { &mut *procedures };
+    let procedure = procedures.table[id as usize];
+    if procedure != 0 {
+        procedures.call_stack.push(call_site);
+    }
+    procedure
+}
+
+/// Pops the call site to return to, 0 if the call stack is empty.
+///
+/// # Safety
+///
+/// `procedures` must point to the `Procedures` owned by the running `jit` call.
+#[no_mangle]
+pub unsafe extern "C" fn procedure_return(procedures: *mut Procedures) -> u32 {
+    let procedures = unsafe { &mut *procedures };
+    procedures.call_stack.pop().unwrap_or(0)
+}
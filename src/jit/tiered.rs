@@ -0,0 +1,404 @@
+// Tiered execution. Programs run in the IR interpreter, which counts how often
+// each loop starts an iteration, and loops that get hot are compiled into
+// Cranelift functions working on the interpreter's own tape. Control only moves into
+// compiled code at a loop head and comes back after the matching `]`, so
+// neither side ever has to resume in the middle of the other's loop.
+
+use std::{collections::HashMap, mem};
+
+use cranelift::{codegen::ir::UserFuncName, prelude::*};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Linkage, Module};
+
+use super::{
+    declare_io, host_isa, io::IoContext, is_control_flow, link_io, IoFuncs, IoLinkage, OpEmitter,
+};
+use crate::{
+    cancel::CancellationToken,
+    interpreter::{self, LoopExit, Machine, Observer, RuntimeError},
+    io::{Io, NullIo, StdIo},
+    optimizer::OptimizedIr,
+    parser::{ExtendedOp, Ir, IrLoopType},
+    snapshot::Snapshot,
+    tape::TapeConfig,
+};
+
+/// Iterations a loop runs in the interpreter before it is compiled
+pub const HOT_LOOP_THRESHOLD: u32 = 1000;
+
+/// Interpreter state a compiled loop reads on entry and writes back on exit
+#[repr(C)]
+struct LoopState {
+    data_offset: i64,
+    /// Index of the `]` that noticed cancellation, or -1
+    cancelled_at: i64,
+    storage: u8,
+    /// Set when the loop ran `@`
+    halted: u8,
+}
+
+type CompiledLoop = extern "C" fn(
+    memory: *mut u8,
+    memory_len: i64,
+    io: *mut IoContext,
+    state: *mut LoopState,
+    cancel_flag: *const u8,
+);
+
+#[derive(Clone, Copy)]
+enum LoopTier {
+    /// Iterations started so far
+    Interpreted(u32),
+    Compiled(CompiledLoop),
+    /// The loop defines or calls pbrain procedures, which only the interpreter
+    /// keeps a call stack for
+    Pinned,
+}
+
+struct LoopCompiler {
+    module: JITModule,
+    io: IoFuncs,
+    ctx: codegen::Context,
+    func_ctx: FunctionBuilderContext,
+    tape: TapeConfig,
+}
+
+impl LoopCompiler {
+    fn new(tape: TapeConfig) -> Self {
+        let mut jit_builder = JITBuilder::with_isa(host_isa(), default_libcall_names());
+        // Both tiers go through the same `Io`, so they can't reorder each
+        // other's output
+        link_io(&mut jit_builder, IoLinkage::Context);
+        let mut module = JITModule::new(jit_builder);
+        let io = declare_io(&mut module);
+        let ctx = module.make_context();
+
+        LoopCompiler {
+            module,
+            io,
+            ctx,
+            func_ctx: FunctionBuilderContext::new(),
+            tape,
+        }
+    }
+
+    /// Compiles the loop whose `[` is at `start`, nested loops included.
+    fn compile(&mut self, ir_ops: &[OptimizedIr], start: usize) -> CompiledLoop {
+        let OptimizedIr::Ir(Ir::Loop(IrLoopType::Start, end)) = ir_ops[start] else {
+            panic!("No loop starts at {start}");
+        };
+
+        let mut func_sig = self.module.make_signature();
+        for _ in 0..5 {
+            func_sig.params.push(AbiParam::new(types::I64));
+        }
+        let func = self
+            .module
+            .declare_function(&format!("loop_{start}"), Linkage::Local, &func_sig)
+            .unwrap();
+        self.ctx.func.signature = func_sig;
+        self.ctx.func.name = UserFuncName::user(0, func.as_u32());
+
+        {
+            let mut builder = FunctionBuilder::new(&mut self.ctx.func, &mut self.func_ctx);
+            let entry_block = builder.create_block();
+            builder.switch_to_block(entry_block);
+            builder.append_block_params_for_function_params(entry_block);
+
+            let memory_ptr = builder.block_params(entry_block)[0];
+            let memory_len = builder.block_params(entry_block)[1];
+            let io_ptr = builder.block_params(entry_block)[2];
+            let state_ptr = builder.block_params(entry_block)[3];
+            let cancel_flag = builder.block_params(entry_block)[4];
+
+            let data_ptr = Variable::new(0);
+            let storage = Variable::new(1);
+            builder.declare_var(data_ptr, types::I64);
+            builder.declare_var(storage, types::I8);
+            let offset_value = builder.ins().load(
+                types::I64,
+                MemFlags::trusted(),
+                state_ptr,
+                mem::offset_of!(LoopState, data_offset) as i32,
+            );
+            let data_ptr_value = builder.ins().iadd(memory_ptr, offset_value);
+            builder.def_var(data_ptr, data_ptr_value);
+            let storage_value = builder.ins().load(
+                types::I8,
+                MemFlags::trusted(),
+                state_ptr,
+                mem::offset_of!(LoopState, storage) as i32,
+            );
+            builder.def_var(storage, storage_value);
+
+            let mut emitter = OpEmitter {
+                memory_ptr,
+                memory_end: builder.ins().iadd(memory_ptr, memory_len),
+                memory_len,
+                tape_len: self.tape.len,
+                io_ptr,
+                data_ptr,
+                offset: 0,
+                storage,
+                input: self
+                    .module
+                    .declare_func_in_func(self.io.input, builder.func),
+                output: self
+                    .module
+                    .declare_func_in_func(self.io.output, builder.func),
+                debug: self
+                    .module
+                    .declare_func_in_func(self.io.debug, builder.func),
+                eof: self.tape.eof,
+            };
+
+            // Leaving the loop, normally or through `@`, writes the state back
+            let exit_block = builder.create_block();
+            let halt_block = builder.create_block();
+            let cancel_block = builder.create_block();
+            builder.append_block_param(cancel_block, types::I64);
+
+            let range = start..=end;
+            let mut operation_to_block = HashMap::new();
+            for index in range.clone() {
+                if is_control_flow(&ir_ops[index]) {
+                    operation_to_block
+                        .entry(index)
+                        .or_insert_with(|| builder.create_block());
+                    if index < end {
+                        operation_to_block
+                            .entry(index + 1)
+                            .or_insert_with(|| builder.create_block());
+                    }
+                }
+            }
+            operation_to_block.insert(end + 1, exit_block);
+
+            builder.ins().jump(operation_to_block[&start], &[]);
+            let mut terminated = true;
+            for index in range {
+                if let Some(block) = operation_to_block.get(&index) {
+                    emitter.flush(&mut builder);
+                    if !terminated {
+                        builder.ins().jump(*block, &[]);
+                    }
+                    builder.switch_to_block(*block);
+                    terminated = false;
+                }
+
+                match &ir_ops[index] {
+                    OptimizedIr::Ir(Ir::Loop(IrLoopType::Start, loop_end)) => {
+                        let data_ptr = builder.use_var(data_ptr);
+                        let value = builder.ins().load(types::I8, MemFlags::new(), data_ptr, 0);
+                        builder.ins().brif(
+                            value,
+                            operation_to_block[&(index + 1)],
+                            &[],
+                            operation_to_block[&(loop_end + 1)],
+                            &[],
+                        );
+                        terminated = true;
+                    }
+                    OptimizedIr::Ir(Ir::Loop(IrLoopType::End, loop_start)) => {
+                        let cancelled =
+                            builder
+                                .ins()
+                                .load(types::I8, MemFlags::trusted(), cancel_flag, 0);
+                        let ir_index = builder.ins().iconst(types::I64, index as i64);
+                        builder.ins().brif(
+                            cancelled,
+                            cancel_block,
+                            &[ir_index],
+                            operation_to_block[loop_start],
+                            &[],
+                        );
+                        terminated = true;
+                    }
+                    OptimizedIr::Ir(Ir::Extended(ExtendedOp::End)) => {
+                        builder.ins().jump(halt_block, &[]);
+                        terminated = true;
+                    }
+                    op if is_control_flow(op) => {
+                        unreachable!("Loops with procedures are never compiled")
+                    }
+                    op => emitter.emit(&mut builder, op, true),
+                }
+            }
+
+            builder.switch_to_block(halt_block);
+            let halted = builder.ins().iconst(types::I8, 1);
+            builder.ins().store(
+                MemFlags::trusted(),
+                halted,
+                state_ptr,
+                mem::offset_of!(LoopState, halted) as i32,
+            );
+            builder.ins().jump(exit_block, &[]);
+
+            builder.switch_to_block(cancel_block);
+            let ir_index = builder.block_params(cancel_block)[0];
+            builder.ins().store(
+                MemFlags::trusted(),
+                ir_index,
+                state_ptr,
+                mem::offset_of!(LoopState, cancelled_at) as i32,
+            );
+            builder.ins().jump(exit_block, &[]);
+
+            builder.switch_to_block(exit_block);
+            let data_ptr_value = builder.use_var(data_ptr);
+            let offset_value = builder.ins().isub(data_ptr_value, memory_ptr);
+            builder.ins().store(
+                MemFlags::trusted(),
+                offset_value,
+                state_ptr,
+                mem::offset_of!(LoopState, data_offset) as i32,
+            );
+            let storage_value = builder.use_var(storage);
+            builder.ins().store(
+                MemFlags::trusted(),
+                storage_value,
+                state_ptr,
+                mem::offset_of!(LoopState, storage) as i32,
+            );
+            builder.ins().return_(&[]);
+            builder.seal_all_blocks();
+            builder.finalize();
+        }
+
+        self.module.define_function(func, &mut self.ctx).unwrap();
+        self.module.clear_context(&mut self.ctx);
+        self.module
+            .finalize_definitions()
+            .expect("Failed to finalize definitions");
+
+        let code = self.module.get_finalized_function(func);
+        unsafe { mem::transmute::<*const u8, CompiledLoop>(code) }
+    }
+}
+
+/// Runs `ir_ops` against the terminal, or with `NullIo` when `ignore_io` is set.
+pub fn tiered(
+    ir_ops: impl AsRef<[OptimizedIr]>,
+    tape: TapeConfig,
+    ignore_io: bool,
+) -> Result<u8, RuntimeError> {
+    let cancel = CancellationToken::new();
+    if ignore_io {
+        tiered_with(ir_ops, tape, &mut NullIo, &cancel)
+    } else {
+        tiered_with(ir_ops, tape, &mut StdIo::default(), &cancel)
+    }
+}
+
+/// Runs `ir_ops` in the interpreter, compiling loops once they have started
+/// `HOT_LOOP_THRESHOLD` iterations. Returns the final value of the current
+/// cell like `interpreter::interpret_with`, and checks `cancel` at back-edges
+/// in both tiers.
+pub fn tiered_with(
+    ir_ops: impl AsRef<[OptimizedIr]>,
+    tape: TapeConfig,
+    io: &mut dyn Io,
+    cancel: &CancellationToken,
+) -> Result<u8, RuntimeError> {
+    resume_with(ir_ops, tape, Snapshot::new(tape), io, cancel)
+}
+
+/// Like `tiered_with`, but continues from `snapshot`.
+pub fn resume_with(
+    ir_ops: impl AsRef<[OptimizedIr]>,
+    tape: TapeConfig,
+    snapshot: Snapshot,
+    io: &mut dyn Io,
+    cancel: &CancellationToken,
+) -> Result<u8, RuntimeError> {
+    let ops = ir_ops.as_ref();
+    let mut tiers = Tiers {
+        ops,
+        tiers: loop_tiers(ops),
+        compiler: LoopCompiler::new(tape),
+    };
+    interpreter::observe_with(ops, tape, snapshot, io, cancel, &mut tiers)
+}
+
+/// The tier of every loop by the IR index of its `[`, in a single pass. A loop
+/// holding a procedure definition or call is pinned, and so is every loop
+/// around it.
+fn loop_tiers(ops: &[OptimizedIr]) -> Vec<LoopTier> {
+    let mut tiers = vec![LoopTier::Interpreted(0); ops.len()];
+    let mut open_loops = Vec::new();
+    for (index, op) in ops.iter().enumerate() {
+        match op {
+            OptimizedIr::Ir(Ir::Loop(IrLoopType::Start, _)) => open_loops.push(index),
+            OptimizedIr::Ir(Ir::Loop(IrLoopType::End, _)) => {
+                let start = open_loops.pop();
+                if let (Some(start), Some(&outer)) = (start, open_loops.last()) {
+                    if matches!(tiers[start], LoopTier::Pinned) {
+                        tiers[outer] = LoopTier::Pinned;
+                    }
+                }
+            }
+            OptimizedIr::Ir(Ir::Procedure(..) | Ir::Call) => {
+                if let Some(&innermost) = open_loops.last() {
+                    tiers[innermost] = LoopTier::Pinned;
+                }
+            }
+            _ => {}
+        }
+    }
+    tiers
+}
+
+/// Counts how often the interpreter enters each loop, and runs loops
+/// compiled once they are hot.
+struct Tiers<'a> {
+    ops: &'a [OptimizedIr],
+    tiers: Vec<LoopTier>,
+    compiler: LoopCompiler,
+}
+
+impl Observer for Tiers<'_> {
+    #[inline(always)]
+    fn before(&mut self, _ip: usize, _pointer: usize, _memory: &[u8]) {}
+
+    fn enter_loop(&mut self, ip: usize, machine: Machine<'_>) -> std::io::Result<LoopExit> {
+        let compiled = match &mut self.tiers[ip] {
+            LoopTier::Compiled(compiled) => *compiled,
+            LoopTier::Pinned => return Ok(LoopExit::Interpreted),
+            LoopTier::Interpreted(count) => {
+                *count += 1;
+                if *count < HOT_LOOP_THRESHOLD {
+                    return Ok(LoopExit::Interpreted);
+                }
+                let compiled = self.compiler.compile(self.ops, ip);
+                self.tiers[ip] = LoopTier::Compiled(compiled);
+                compiled
+            }
+        };
+
+        let mut state = LoopState {
+            data_offset: *machine.pointer as i64,
+            cancelled_at: -1,
+            storage: *machine.storage,
+            halted: 0,
+        };
+        let mut io = IoContext::new(machine.io);
+        compiled(
+            machine.memory.as_mut_ptr(),
+            machine.memory.len() as i64,
+            &mut io,
+            &mut state,
+            machine.cancel.flag(),
+        );
+        io.take_error()?;
+        *machine.pointer = state.data_offset as usize;
+        *machine.storage = state.storage;
+        Ok(if state.cancelled_at >= 0 {
+            LoopExit::Cancelled(state.cancelled_at as usize)
+        } else if state.halted != 0 {
+            LoopExit::Halted
+        } else {
+            LoopExit::Finished
+        })
+    }
+}
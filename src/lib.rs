@@ -1,6 +1,20 @@
-#![feature(let_chains)]
-
+pub mod cache;
+pub mod cancel;
+pub mod check;
 pub mod interpreter;
+pub mod io;
+pub mod ir_text;
 pub mod jit;
 pub mod optimizer;
 pub mod parser;
+pub mod program;
+pub mod snapshot;
+pub mod stats;
+pub mod substitution;
+pub mod tape;
+pub mod trace;
+pub mod transpile;
+pub mod vm;
+pub mod wasm;
+
+pub use program::{run_batch, Backend, Error, Executable, OptLevel, Program};
@@ -1,58 +1,658 @@
-#![feature(let_chains)]
-
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{anyhow, Result};
+use clap::{Parser, Subcommand, ValueEnum};
 use std::fs;
 use std::io::{self, Read, Write};
+use std::ops::RangeInclusive;
+use std::sync::OnceLock;
 
-mod interpreter;
-mod jit;
-mod optimizer;
-mod parser;
+use cranefuck::{
+    cache::{Cache, CacheEntry, CacheKey},
+    cancel::CancellationToken,
+    check,
+    interpreter::{self, RuntimeError},
+    io::{IoEncoding, NewlineMode, OutputBuffering, StdIo},
+    ir_text, jit, optimizer,
+    optimizer::OptimizedIr,
+    parser,
+    snapshot::Snapshot,
+    stats::TapeStats,
+    substitution,
+    tape::{self, EofPolicy, TapeConfig},
+    trace::{self, TraceFilter, Tracer},
+    transpile, vm, wasm,
+};
 
 /// A robust Brainfuck CLI tool with REPL, file, and piped input support.
 #[derive(Parser, Debug)]
 #[command(
     name = "brainfuck-cli",
     version = "1.0",
-    about = "A robust Brainfuck CLI tool with REPL, file, and piped input support."
+    about = "A robust Brainfuck CLI tool with REPL, file, and piped input support.",
+    // Running options given before a subcommand would otherwise be ignored
+    args_conflicts_with_subcommands = true
 )]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Path to a Brainfuck source file
     #[arg(short, long)]
     file: Option<String>,
 
-    /// Execution mode: 'jit' (default) or 'interpreter'
+    /// Brainfuck source given on the command line instead of a file
+    #[arg(long, conflicts_with = "file")]
+    program: Option<String>,
+
+    /// Input for the program instead of stdin
+    #[arg(long, conflicts_with = "input_file")]
+    input: Option<String>,
+
+    /// File to read the program's input from instead of stdin
+    #[arg(long)]
+    input_file: Option<String>,
+
+    /// Execution mode: 'jit' (default), 'interpreter', 'vm' (bytecode interpreter) or
+    /// 'tiered' (interpreter that compiles hot loops)
     #[arg(short, long, default_value = "jit")]
     mode: String,
 
     /// Enable verbose output
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     verbose: bool,
 
     /// Enable optimizations
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     optimize: bool,
+
+    #[command(flatten)]
+    frontend: Frontend,
+
+    /// How cells relate to text: 'raw' bytes (default), 'utf-8' (input must be
+    /// UTF-8, split into bytes) or 'code-point' (a character per cell)
+    #[arg(long, default_value = "raw")]
+    encoding: IoEncoding,
+
+    /// Newline translation: 'raw' (default), 'lf-to-crlf' on output or
+    /// 'crlf-to-lf' on input
+    #[arg(long, default_value = "raw")]
+    newlines: NewlineMode,
+
+    /// When output is flushed: 'unbuffered', 'line' (default), 'full' (8 KiB),
+    /// 'full:<bytes>' or 'flush-before-read'. It is always flushed before
+    /// waiting for input
+    #[arg(long, default_value = "line")]
+    buffering: OutputBuffering,
+
+    /// Print the (optimized) IR in its textual form and exit
+    #[arg(long)]
+    emit_ir: bool,
+
+    /// Neither read nor write the compilation cache in $XDG_CACHE_HOME/cranefuck
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Run in the interpreter, writing a JSON line for every op that runs to
+    /// this file, or to stderr for '-'
+    #[arg(long)]
+    trace: Option<String>,
+
+    /// Trace only every nth op
+    #[arg(long, default_value_t = 1, requires = "trace")]
+    trace_every: u64,
+
+    /// Trace only the ops at these IR indices, such as 10-20
+    #[arg(long, value_parser = trace::parse_range, requires = "trace")]
+    trace_ops: Option<RangeInclusive<usize>>,
+
+    /// Trace only the ops from these source lines, such as 10-20
+    #[arg(long, value_parser = trace::parse_range, requires = "trace")]
+    trace_lines: Option<RangeInclusive<usize>>,
+
+    /// Run in the interpreter and print the cells the program touched, the
+    /// reads and writes of each and how often the pointer wrapped around the
+    /// tape to stderr
+    #[arg(long)]
+    stats: bool,
+
+    /// Run in the interpreter and draw how often each touched cell was
+    /// accessed to this file: a PPM image if it ends in '.ppm', otherwise
+    /// text, or text on stderr for '-'
+    #[arg(long)]
+    heatmap: Option<String>,
+
+    /// Write the machine state to this file when interrupted with Ctrl-C
+    #[arg(long)]
+    snapshot: Option<String>,
+
+    /// Start from a snapshot written by --snapshot instead of a fresh tape, in
+    /// any mode
+    #[arg(long)]
+    resume: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Reformat Brainfuck source, indenting loops and grouping runs
+    Fmt {
+        #[command(flatten)]
+        file: SourceFile,
+
+        /// Write the result back to the file instead of printing it
+        #[arg(short, long, requires = "SourceFile")]
+        write: bool,
+    },
+    /// Strip everything except the eight Brainfuck commands
+    Minify {
+        #[command(flatten)]
+        file: SourceFile,
+
+        /// Also remove `+-` and `<>` pairs that cancel each other out
+        #[arg(short, long)]
+        cancel: bool,
+
+        /// Write the result back to the file instead of printing it
+        #[arg(short, long, requires = "SourceFile")]
+        write: bool,
+    },
+    /// Report suspicious constructs such as loops that never terminate
+    Check {
+        #[command(flatten)]
+        file: SourceFile,
+
+        /// Output format for the diagnostics
+        #[arg(long, value_enum, default_value = "human")]
+        format: CheckFormat,
+    },
+
+    /// Translate a program into C, Rust or WebAssembly text (WASI)
+    Transpile {
+        #[command(flatten)]
+        file: SourceFile,
+
+        #[command(flatten)]
+        frontend: Frontend,
+
+        /// Output language: 'c', 'rust' or 'wat'
+        #[arg(short, long)]
+        target: transpile::Target,
+    },
+    /// Compile a program into a standalone WebAssembly module
+    Wasm {
+        #[command(flatten)]
+        file: SourceFile,
+
+        #[command(flatten)]
+        frontend: Frontend,
+
+        /// Where to write the module; defaults to the source path with a
+        /// `.wasm` extension, or stdout when reading stdin
+        #[arg(long)]
+        output: Option<String>,
+
+        /// I/O imports: 'wasi' (default) or 'host' (`env.input`, `env.output`
+        /// and `env.debug`)
+        #[arg(long, default_value = "wasi")]
+        imports: transpile::WasmImports,
+    },
+}
+
+/// The source file of a subcommand, given either like the top-level
+/// `--file` or as a positional argument.
+#[derive(clap::Args, Debug)]
+struct SourceFile {
+    /// Path to a Brainfuck source file (reads stdin when omitted)
+    #[arg(value_name = "FILE", conflicts_with = "file")]
+    path: Option<String>,
+
+    /// Path to a Brainfuck source file, same as FILE
+    #[arg(short, long)]
+    file: Option<String>,
+}
+
+impl SourceFile {
+    fn path(&self) -> Option<&str> {
+        self.path.as_deref().or(self.file.as_deref())
+    }
+}
+
+/// How the source is read and the tape it runs on, for running a program and
+/// the subcommands that translate one. `fmt`, `minify` and `check` only know
+/// classic Brainfuck and reject these.
+#[derive(clap::Args, Debug)]
+struct Frontend {
+    /// Command set: 'classic' (default), 'debug' (adds '#'), 'extended' (Extended
+    /// Brainfuck Type I), 'pbrain', or one of the substitution languages 'ook',
+    /// 'blub' and 'alphuck' (also picked from the file extension)
+    #[arg(short, long, conflicts_with = "dialect_file")]
+    dialect: Option<parser::Dialect>,
+
+    /// TOML or JSON file mapping each command to a word or phrase
+    #[arg(long)]
+    dialect_file: Option<String>,
+
+    /// Treat the source as textual IR instead of Brainfuck
+    #[arg(long)]
+    ir: bool,
+
+    /// Number of cells on the tape
+    #[arg(long, default_value_t = TapeConfig::default().len, value_parser = tape::parse_len)]
+    tape_size: usize,
+
+    /// What `,` stores once the input is exhausted: 'unchanged' (default), 'zero'
+    /// or 'minus-one'
+    #[arg(long, default_value = "unchanged")]
+    eof: EofPolicy,
+}
+
+#[derive(ValueEnum, Clone, Debug)]
+enum CheckFormat {
+    Human,
+    Json,
+}
+
+fn read_source(file: Option<&str>) -> Result<String> {
+    Ok(match file {
+        Some(file_path) => fs::read_to_string(file_path)?,
+        None => {
+            let mut buffer = String::new();
+            io::stdin().read_to_string(&mut buffer)?;
+            buffer
+        }
+    })
+}
+
+fn write_output(file: Option<&str>, write: bool, output: &str) -> Result<()> {
+    match file {
+        Some(file_path) if write => fs::write(file_path, output)?,
+        _ => print!("{}", output),
+    }
+    Ok(())
+}
+
+/// Runs `ir_ops` in the interpreter with the tracer `--trace` and its filters
+/// ask for, and the tape statistics `--stats` and `--heatmap` ask for.
+fn run_observed(
+    args: &Args,
+    source: &str,
+    ir_ops: &[OptimizedIr],
+    snapshot: Snapshot,
+    terminal: &mut StdIo,
+    cancel: &CancellationToken,
+) -> Result<(), RuntimeError> {
+    let lines = op_lines(args, source)?;
+    if args.trace_lines.is_some() && lines.is_none() {
+        return Err(anyhow!("--trace-lines needs the source of a single-character dialect").into());
+    }
+    let mut output = match args.trace.as_deref() {
+        Some(path) => Some(open_output(path)?),
+        None => None,
+    };
+    let filter = TraceFilter {
+        every: args.trace_every,
+        ops: args.trace_ops.clone(),
+        lines: args.trace_lines.clone(),
+    };
+    let tracer = output.as_mut().map(|output| {
+        let tracer = Tracer::new(output, ir_ops, filter);
+        match lines {
+            Some(lines) => tracer.lines(lines),
+            None => tracer,
+        }
+    });
+    let tape = tape_config(&args.frontend);
+    let stats = (args.stats || args.heatmap.is_some()).then(|| TapeStats::new(ir_ops, tape));
+
+    let mut observers = (tracer, stats);
+    // Statistics are written even if the program fails or is interrupted
+    let result =
+        interpreter::observe_with(ir_ops, tape, snapshot, terminal, cancel, &mut observers);
+    let (tracer, stats) = observers;
+    drop(tracer);
+    if let Some(output) = output.as_mut() {
+        output.flush()?;
+    }
+
+    if let Some(stats) = &stats {
+        if args.stats {
+            // Keeps the report apart from what the program printed
+            terminal.flush()?;
+            stats.report(&mut io::stderr())?;
+        }
+        if let Some(path) = args.heatmap.as_deref() {
+            let mut heatmap = open_output(path)?;
+            if path.ends_with(".ppm") {
+                stats.ppm_heatmap(&mut heatmap)?;
+            } else {
+                stats.ascii_heatmap(&mut heatmap)?;
+            }
+            heatmap.flush()?;
+        }
+    }
+    result.map(drop)
+}
+
+/// Opens `path` to write to, or stderr for '-'.
+fn open_output(path: &str) -> io::Result<Box<dyn Write>> {
+    Ok(match path {
+        "-" => Box::new(io::stderr()),
+        path => Box::new(io::BufWriter::new(fs::File::create(path)?)),
+    })
+}
+
+/// The source line of every op `build_ir` makes of `source`, if it is in a
+/// dialect of single-character commands.
+fn op_lines(args: &Args, source: &str) -> Result<Option<Vec<usize>>> {
+    let dialect = resolve_dialect(&args.frontend, args.file.as_deref())?;
+    if args.frontend.ir || matches!(dialect, parser::Dialect::Substitution(_)) {
+        return Ok(None);
+    }
+    let tokens = parser::tokenize_dialect_with_spans(source, &dialect);
+    let ir = parser::to_ir(
+        tokens
+            .iter()
+            .map(|(token, _)| token.clone())
+            .collect::<Vec<_>>(),
+    )?;
+    let origins = if args.optimize {
+        optimizer::optimize_with_origins(&ir).1
+    } else {
+        (0..ir.len()).collect()
+    };
+    Ok(Some(trace::op_lines(&tokens, &origins)))
+}
+
+/// Splits piped source at the first `!` into the program and its input, as
+/// is customary, unless `!` means something else in the chosen language.
+fn split_piped_input(args: &Args, source: Vec<u8>) -> Result<(String, Option<Vec<u8>>)> {
+    let separated = !args.frontend.ir
+        && matches!(
+            resolve_dialect(&args.frontend, None)?,
+            parser::Dialect::Classic | parser::Dialect::Debug | parser::Dialect::PBrain
+        );
+    match source.iter().position(|byte| *byte == b'!') {
+        Some(separator) if separated => Ok((
+            String::from_utf8(source[..separator].to_vec())?,
+            Some(source[separator + 1..].to_vec()),
+        )),
+        _ => Ok((String::from_utf8(source)?, None)),
+    }
+}
+
+/// The terminal the program runs against, reading from `--input`,
+/// `--input-file` or input piped after the source before falling back to stdin.
+fn terminal(args: &Args, piped_input: Option<Vec<u8>>) -> Result<StdIo> {
+    let io = StdIo::default()
+        .encoding(args.encoding)
+        .newlines(args.newlines)
+        .buffering(args.buffering);
+    let input = match (&args.input, &args.input_file) {
+        (Some(input), _) => Some(input.clone().into_bytes()),
+        (None, Some(path)) => Some(fs::read(path)?),
+        (None, None) => piped_input,
+    };
+    Ok(match input {
+        Some(input) => io.input(input),
+        None => io,
+    })
+}
+
+fn tape_config(frontend: &Frontend) -> TapeConfig {
+    TapeConfig {
+        len: frontend.tape_size,
+        eof: frontend.eof,
+    }
+}
+
+fn resolve_dialect(frontend: &Frontend, file: Option<&str>) -> Result<parser::Dialect> {
+    // Explicit choices win over the file extension.
+    Ok(if let Some(dialect) = &frontend.dialect {
+        dialect.clone()
+    } else if let Some(path) = &frontend.dialect_file {
+        parser::Dialect::Substitution(substitution::Substitution::load(path)?)
+    } else {
+        file.and_then(|path| std::path::Path::new(path).extension()?.to_str())
+            .and_then(substitution::Substitution::for_extension)
+            .map(parser::Dialect::Substitution)
+            .unwrap_or_default()
+    })
+}
+
+/// Parses `source` as Brainfuck (or textual IR with `--ir`) and optimizes it
+/// when requested.
+fn build_ir(
+    args: &Args,
+    frontend: &Frontend,
+    source: &str,
+    file: Option<&str>,
+) -> Result<Vec<OptimizedIr>> {
+    let verbose = args.verbose;
+    let optimize = args.optimize;
+
+    let optimized_ir = if frontend.ir {
+        ir_text::parse(source)?
+    } else {
+        // Tokenize and convert code to an intermediate representation.
+        let tokens = parser::tokenize_dialect(source, &resolve_dialect(frontend, file)?);
+        if verbose {
+            println!("Tokens: {:?}", tokens);
+        }
+        let ir = parser::to_ir(tokens)?;
+        if verbose {
+            println!("Intermediate Representation (IR): {:?}", ir);
+        }
+
+        if optimize {
+            optimizer::optimize(&ir)
+        } else {
+            optimizer::noop_optimzer(&ir)
+        }
+    };
+    if verbose && optimize {
+        println!("Optimized IR: {:?}", optimized_ir);
+    }
+
+    Ok(optimized_ir)
+}
+
+/// Writes `entry` to the cache, if there is one. Failing to is not an error.
+fn store_in_cache(cache: Option<&(Cache, CacheKey)>, entry: &CacheEntry, verbose: bool) {
+    if let Some((cache, key)) = cache {
+        if let Err(error) = cache.store(key, entry) {
+            if verbose {
+                println!("Could not write to {}: {error}", cache.dir().display());
+            }
+        }
+    }
+}
+
+/// Cancelled by the first Ctrl-C while a program runs
+static INTERRUPT: OnceLock<CancellationToken> = OnceLock::new();
+
+/// Called from the SIGINT handler. A second Ctrl-C, for example while the
+/// program is waiting for input, exits straight away.
+fn interrupt() {
+    if let Some(token) = INTERRUPT.get() {
+        if token.is_cancelled() {
+            exit_now(130);
+        }
+        token.cancel();
+    }
+}
+
+#[cfg(unix)]
+fn install_interrupt_handler() {
+    const SIGINT: i32 = 2;
+    extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+    }
+    extern "C" fn on_interrupt(_: i32) {
+        interrupt();
+    }
+    unsafe {
+        signal(SIGINT, on_interrupt);
+    }
+}
+
+#[cfg(unix)]
+fn exit_now(code: i32) {
+    // `process::exit` runs atexit handlers, which isn't safe in a signal handler
+    extern "C" {
+        fn _exit(status: i32) -> !;
+    }
+    unsafe { _exit(code) }
+}
+
+#[cfg(windows)]
+fn install_interrupt_handler() {
+    const CTRL_C_EVENT: u32 = 0;
+    extern "system" {
+        fn SetConsoleCtrlHandler(handler: extern "system" fn(u32) -> i32, add: i32) -> i32;
+    }
+    extern "system" fn on_interrupt(event: u32) -> i32 {
+        if event != CTRL_C_EVENT {
+            return 0;
+        }
+        interrupt();
+        1
+    }
+    unsafe {
+        SetConsoleCtrlHandler(on_interrupt, 1);
+    }
+}
+
+#[cfg(windows)]
+fn exit_now(code: i32) {
+    // Console handlers run on a thread of their own
+    std::process::exit(code)
+}
+
+/// Reports where an interrupted program stopped, saves its snapshot to
+/// `snapshot_path` if given and exits like a shell would after SIGINT. Other
+/// results are passed on.
+fn exit_if_interrupted(
+    result: Result<(), RuntimeError>,
+    ir_ops: &[OptimizedIr],
+    snapshot_path: Option<&str>,
+) -> Result<()> {
+    match result {
+        Err(RuntimeError::Cancelled {
+            ir_index,
+            tape,
+            snapshot,
+        }) => {
+            let op = ir_text::print(&ir_ops[ir_index..=ir_index]);
+            eprintln!();
+            eprintln!("Interrupted at IR op {ir_index} ({})", op.trim());
+            eprintln!("{tape}");
+            if let Some(path) = snapshot_path {
+                snapshot.save(path)?;
+                eprintln!("Saved the snapshot to {path}");
+            }
+            std::process::exit(130);
+        }
+        result => Ok(result?),
+    }
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
+
+    match &args.command {
+        Some(Command::Fmt { file, write }) => {
+            let source = read_source(file.path())?;
+            return write_output(file.path(), *write, &parser::format(&source));
+        }
+        Some(Command::Minify {
+            file,
+            cancel,
+            write,
+        }) => {
+            let source = read_source(file.path())?;
+            let mut minified = parser::minify(&source, *cancel);
+            minified.push('\n');
+            return write_output(file.path(), *write, &minified);
+        }
+        Some(Command::Check { file, format }) => {
+            let source = read_source(file.path())?;
+            let tokens = parser::tokenize_with_spans(&source);
+            let ir = parser::to_ir(
+                tokens
+                    .iter()
+                    .map(|(token, _)| token.clone())
+                    .collect::<Vec<_>>(),
+            )?;
+            let diagnostics = check::check(&ir, &tokens);
+            match format {
+                CheckFormat::Human => {
+                    let path = file.path().unwrap_or("<stdin>");
+                    print!("{}", check::render(&diagnostics, &source, path));
+                }
+                CheckFormat::Json => println!("{}", check::to_json(&diagnostics)),
+            }
+            if !diagnostics.is_empty() {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Command::Transpile {
+            file,
+            frontend,
+            target,
+        }) => {
+            let source = read_source(file.path())?;
+            let ir = build_ir(&args, frontend, &source, file.path())?;
+            print!(
+                "{}",
+                transpile::transpile(ir, *target, tape_config(frontend))?
+            );
+            return Ok(());
+        }
+        Some(Command::Wasm {
+            file,
+            frontend,
+            output,
+            imports,
+        }) => {
+            let source = read_source(file.path())?;
+            let ir = build_ir(&args, frontend, &source, file.path())?;
+            let module = wasm::compile(ir, tape_config(frontend), *imports)?;
+            let output = output.clone().or_else(|| {
+                file.path().map(|file| {
+                    std::path::Path::new(file)
+                        .with_extension("wasm")
+                        .to_string_lossy()
+                        .into_owned()
+                })
+            });
+            match output {
+                Some(path) => fs::write(path, module)?,
+                None => io::stdout().write_all(&module)?,
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+
     let verbose = args.verbose;
-    let optimize = args.optimize;
 
     // Determine the source of the Brainfuck code.
-    let brainfuck_code = if let Some(file_path) = args.file {
+    let mut piped_input = None;
+    let brainfuck_code = if let Some(file_path) = &args.file {
         if verbose {
             println!("Reading Brainfuck code from file: {}", file_path);
         }
         fs::read_to_string(file_path)?
+    } else if let Some(program) = &args.program {
+        program.clone()
     } else if !atty::is(atty::Stream::Stdin) {
         if verbose {
             println!("Reading Brainfuck code from piped input...");
         }
-        let mut buffer = String::new();
-        io::stdin().read_to_string(&mut buffer)?;
-        buffer
+        let mut buffer = Vec::new();
+        io::stdin().read_to_end(&mut buffer)?;
+        let (program, input) = split_piped_input(&args, buffer)?;
+        piped_input = input;
+        program
     } else {
         if verbose {
             println!("Entering Brainfuck REPL mode. Type your code and press Enter.");
@@ -70,47 +670,132 @@ fn main() -> Result<()> {
         println!("Brainfuck code loaded: {:?}", brainfuck_code);
     }
 
-    // Tokenize and convert code to an intermediate representation.
-    let tokens = parser::tokenize(&brainfuck_code);
-    if verbose {
-        println!("Tokens: {:?}", tokens);
-    }
-    let ir = parser::to_ir(tokens)?;
-    if verbose {
-        println!("Intermediate Representation (IR): {:?}", ir);
+    let tape = tape_config(&args.frontend);
+
+    // Only files are cached; piped programs and the REPL are usually one-offs
+    let cache = match (&args.file, args.no_cache) {
+        (Some(file), false) => {
+            let frontend = if args.frontend.ir {
+                "ir".to_string()
+            } else {
+                format!("{:?}", resolve_dialect(&args.frontend, Some(file))?)
+            };
+            let key = CacheKey::new(
+                &brainfuck_code,
+                &frontend,
+                args.optimize,
+                tape,
+                &jit::target_description(),
+            );
+            Cache::open().map(|cache| (cache, key))
+        }
+        _ => None,
+    };
+    let cached = cache.as_ref().and_then(|(cache, key)| cache.load(key));
+    if verbose && cached.is_some() {
+        println!("Loaded the program from the cache");
     }
 
-    let optimized_ir = if optimize {
-        optimizer::optimize(&ir)
-    } else {
-        optimizer::noop_optimzer(&ir)
+    let optimized_ir = match &cached {
+        Some(entry) => entry.ir.clone(),
+        None => {
+            let ir = build_ir(&args, &args.frontend, &brainfuck_code, args.file.as_deref())?;
+            store_in_cache(
+                cache.as_ref(),
+                &CacheEntry {
+                    ir: ir.clone(),
+                    code: None,
+                },
+                verbose,
+            );
+            ir
+        }
     };
-    if verbose && optimize {
-        println!("Optimized IR: {:?}", optimized_ir);
+
+    if args.emit_ir {
+        print!("{}", ir_text::print(&optimized_ir));
+        return Ok(());
     }
 
+    let snapshot = match &args.resume {
+        Some(path) => {
+            let snapshot = Snapshot::load(path)?;
+            snapshot.validate(&optimized_ir, tape)?;
+            snapshot
+        }
+        None => Snapshot::new(tape),
+    };
+
+    let mut terminal = terminal(&args, piped_input)?;
+    let cancel = INTERRUPT.get_or_init(CancellationToken::new);
+    install_interrupt_handler();
+
     // Execute the Brainfuck code based on the selected mode.
-    match args.mode.as_str() {
+    let result = match args.mode.as_str() {
+        _ if args.trace.is_some() || args.stats || args.heatmap.is_some() => {
+            if verbose {
+                println!("Observing Brainfuck code in interpreter mode...");
+            }
+            run_observed(
+                &args,
+                &brainfuck_code,
+                &optimized_ir,
+                snapshot,
+                &mut terminal,
+                cancel,
+            )
+        }
         "interpreter" => {
             if verbose {
                 println!("Executing Brainfuck code in interpreter mode...");
             }
-            interpreter::interpret(optimized_ir, false)?;
+            interpreter::resume_with(&optimized_ir, tape, snapshot, &mut terminal, cancel).map(drop)
+        }
+        "vm" => {
+            if verbose {
+                println!("Executing Brainfuck code in bytecode VM mode...");
+            }
+            vm::compile(&optimized_ir, tape)?
+                .resume_with(snapshot, &mut terminal, cancel)
+                .map(drop)
+        }
+        "tiered" => {
+            if verbose {
+                println!("Executing Brainfuck code in tiered mode...");
+            }
+            jit::tiered::resume_with(&optimized_ir, tape, snapshot, &mut terminal, cancel).map(drop)
         }
         "jit" => {
             if verbose {
                 println!("Executing Brainfuck code in JIT mode...");
             }
-            jit::jit(optimized_ir, false);
+            let code = match cached.and_then(|entry| entry.code) {
+                Some(code) => code,
+                None => {
+                    let code = jit::compile(&optimized_ir, tape)?;
+                    store_in_cache(
+                        cache.as_ref(),
+                        &CacheEntry {
+                            ir: optimized_ir.clone(),
+                            code: Some(code.clone()),
+                        },
+                        verbose,
+                    );
+                    code
+                }
+            };
+            jit::resume_with(&code, tape, snapshot, &mut terminal, cancel)
         }
         other => {
             eprintln!(
-                "Error: Invalid mode '{}'. Use 'interpreter' or 'jit'.",
+                "Error: Invalid mode '{}'. Use 'interpreter', 'vm', 'tiered' or 'jit'.",
                 other
             );
             std::process::exit(1);
         }
-    }
+    };
 
-    Ok(())
+    // Flush the output before a possible exit
+    drop(terminal);
+    exit_if_interrupted(result, &optimized_ir, args.snapshot.as_deref())
 }
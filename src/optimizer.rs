@@ -1,6 +1,6 @@
 use crate::parser::{Ir, IrLoopType};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum OptimizedIr {
     Ir(Ir),
     ResetToZero,
@@ -17,20 +17,29 @@ pub fn noop_optimzer(ir_ops: impl AsRef<[Ir]>) -> Vec<OptimizedIr> {
     ir_ops.as_ref().iter().map(|ir| ir.clone().into()).collect()
 }
 pub fn optimize(ir_ops: impl AsRef<[Ir]>) -> Vec<OptimizedIr> {
+    optimize_with_origins(ir_ops).0
+}
+
+/// Like `optimize`, also returning the index of the `Ir` op each optimized op
+/// starts at.
+pub fn optimize_with_origins(ir_ops: impl AsRef<[Ir]>) -> (Vec<OptimizedIr>, Vec<usize>) {
     let ir_ops = ir_ops.as_ref().to_vec();
-    let mut optimized_ops = ir_ops.into_iter().map(OptimizedIr::Ir).collect::<Vec<_>>();
+    let origins = (0..ir_ops.len()).collect::<Vec<_>>();
+    let optimized_ops = ir_ops.into_iter().map(OptimizedIr::Ir).collect::<Vec<_>>();
 
     // Apply optimizations in passes
-    optimized_ops = optimize_reset_to_zero(optimized_ops);
-    optimized_ops = optimize_add_and_zero(optimized_ops);
-
-    optimized_ops
+    let (optimized_ops, origins) = optimize_reset_to_zero(optimized_ops, &origins);
+    optimize_add_and_zero(optimized_ops, &origins)
 }
 
 // [-], [+] -> ResetToZero
-fn optimize_reset_to_zero(ir_ops: Vec<OptimizedIr>) -> Vec<OptimizedIr> {
+fn optimize_reset_to_zero(
+    ir_ops: Vec<OptimizedIr>,
+    origins: &[usize],
+) -> (Vec<OptimizedIr>, Vec<usize>) {
     let mut instruction_pointer = 0;
     let mut optimized_ops = Vec::with_capacity(ir_ops.len());
+    let mut optimized_origins = Vec::with_capacity(ir_ops.len());
     let mut index_map = (0..ir_ops.len()).collect::<Vec<_>>();
 
     while instruction_pointer < ir_ops.len() {
@@ -40,21 +49,30 @@ fn optimize_reset_to_zero(ir_ops: Vec<OptimizedIr>) -> Vec<OptimizedIr> {
         {
             if *amount == -1 || *amount == 1 {
                 optimized_ops.push(OptimizedIr::ResetToZero);
+                optimized_origins.push(origins[instruction_pointer]);
                 instruction_pointer += 3;
                 shift_indices(&mut index_map, instruction_pointer, -2);
                 continue; // Skip the default push and increment
             }
         }
         optimized_ops.push(ir_ops[instruction_pointer].clone());
+        optimized_origins.push(origins[instruction_pointer]);
         instruction_pointer += 1;
     }
 
-    update_loop_indices(optimized_ops, &index_map)
+    (
+        update_loop_indices(optimized_ops, &index_map),
+        optimized_origins,
+    )
 }
 // [-N>+N<] -> AddAndZero
-fn optimize_add_and_zero(ir_ops: Vec<OptimizedIr>) -> Vec<OptimizedIr> {
+fn optimize_add_and_zero(
+    ir_ops: Vec<OptimizedIr>,
+    origins: &[usize],
+) -> (Vec<OptimizedIr>, Vec<usize>) {
     let mut instruction_pointer = 0;
     let mut optimized_ops = Vec::with_capacity(ir_ops.len());
+    let mut optimized_origins = Vec::with_capacity(ir_ops.len());
     let mut index_map = (0..ir_ops.len()).collect::<Vec<_>>();
 
     while instruction_pointer < ir_ops.len() {
@@ -64,21 +82,26 @@ fn optimize_add_and_zero(ir_ops: Vec<OptimizedIr>) -> Vec<OptimizedIr> {
         {
             if *amount1 == -1 && *amount2 == 1 && *move_right_amount == -*move_left_amount {
                 optimized_ops.push(OptimizedIr::AddAndZero(*move_right_amount));
+                optimized_origins.push(origins[instruction_pointer]);
                 instruction_pointer += 6;
                 shift_indices(&mut index_map, instruction_pointer, -5);
                 continue; // Skip the default push and increment
             }
         }
         optimized_ops.push(ir_ops[instruction_pointer].clone());
+        optimized_origins.push(origins[instruction_pointer]);
         instruction_pointer += 1;
     }
 
-    update_loop_indices(optimized_ops, &index_map)
+    (
+        update_loop_indices(optimized_ops, &index_map),
+        optimized_origins,
+    )
 }
 
-fn shift_indices(index_map: &mut Vec<usize>, start_index: usize, shift: isize) {
-    for i in start_index..index_map.len() {
-        index_map[i] = (index_map[i] as isize + shift) as usize;
+fn shift_indices(index_map: &mut [usize], start_index: usize, shift: isize) {
+    for index in index_map.iter_mut().skip(start_index) {
+        *index = (*index as isize + shift) as usize;
     }
 }
 
@@ -87,7 +110,7 @@ fn update_loop_indices(
     index_map: &[usize],
 ) -> Vec<OptimizedIr> {
     for op in &mut optimized_ops {
-        if let OptimizedIr::Ir(Ir::Loop(_, index)) = op {
+        if let OptimizedIr::Ir(Ir::Loop(_, index) | Ir::Procedure(_, index)) = op {
             *index = index_map[*index];
         }
     }
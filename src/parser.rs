@@ -1,9 +1,15 @@
 // > < + - . , [ ]
+// Extended Brainfuck Type I: @ $ ! } { ~ ^ & |
+// pbrain: ( ) :
+// Debugging: #
 
-use std::collections::HashMap;
+use std::{ops::Range, str::FromStr};
 
+use serde::Serialize;
 use thiserror::Error;
 
+use crate::substitution::Substitution;
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token {
     Increment,
@@ -14,23 +20,174 @@ pub enum Token {
     Input,
     LoopStart,
     LoopEnd,
+    // Extended Brainfuck Type I
+    End,
+    Store,
+    Load,
+    ShiftRight,
+    ShiftLeft,
+    Not,
+    Xor,
+    And,
+    Or,
+    // pbrain
+    ProcedureStart,
+    ProcedureEnd,
+    Call,
+    // Debugging
+    Debug,
+}
+
+/// The command set `tokenize_dialect` recognises. Every dialect is a superset
+/// of the eight classic commands; everything else is a comment.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub enum Dialect {
+    #[default]
+    Classic,
+    /// Classic plus the `#` tape dump
+    Debug,
+    /// Extended Brainfuck Type I: `@ $ ! } { ~ ^ & |` and `#`
+    Extended,
+    /// pbrain procedures: `( ) :` and `#`
+    PBrain,
+    /// A trivial substitution of the commands, such as Ook!
+    Substitution(Substitution),
+}
+
+impl Dialect {
+    /// The token for a single-character command, always `None` for substitutions.
+    pub(crate) fn token(&self, c: char) -> Option<Token> {
+        match (self, c) {
+            (Dialect::Classic, _) => token(c),
+            (Dialect::Substitution(_), _) => None,
+            (_, '#') => Some(Token::Debug),
+            (Dialect::Extended, '@') => Some(Token::End),
+            (Dialect::Extended, '$') => Some(Token::Store),
+            (Dialect::Extended, '!') => Some(Token::Load),
+            (Dialect::Extended, '}') => Some(Token::ShiftRight),
+            (Dialect::Extended, '{') => Some(Token::ShiftLeft),
+            (Dialect::Extended, '~') => Some(Token::Not),
+            (Dialect::Extended, '^') => Some(Token::Xor),
+            (Dialect::Extended, '&') => Some(Token::And),
+            (Dialect::Extended, '|') => Some(Token::Or),
+            (Dialect::PBrain, '(') => Some(Token::ProcedureStart),
+            (Dialect::PBrain, ')') => Some(Token::ProcedureEnd),
+            (Dialect::PBrain, ':') => Some(Token::Call),
+            _ => token(c),
+        }
+    }
+}
+
+impl FromStr for Dialect {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "classic" => Ok(Dialect::Classic),
+            "debug" => Ok(Dialect::Debug),
+            "extended" => Ok(Dialect::Extended),
+            "pbrain" => Ok(Dialect::PBrain),
+            _ => Substitution::builtin(name)
+                .map(Dialect::Substitution)
+                .ok_or(format!(
+                    "unknown dialect '{name}', expected classic, debug, extended, pbrain, ook, blub or alphuck"
+                )),
+        }
+    }
+}
+
+fn token(c: char) -> Option<Token> {
+    match c {
+        '+' => Some(Token::Increment),
+        '-' => Some(Token::Decrement),
+        '>' => Some(Token::MoveRight),
+        '<' => Some(Token::MoveLeft),
+        '.' => Some(Token::Output),
+        ',' => Some(Token::Input),
+        '[' => Some(Token::LoopStart),
+        ']' => Some(Token::LoopEnd),
+        _ => None,
+    }
 }
 
 pub fn tokenize(input: &str) -> Vec<Token> {
-    input
-        .chars()
-        .filter_map(|c| match c {
-            '+' => Some(Token::Increment),
-            '-' => Some(Token::Decrement),
-            '>' => Some(Token::MoveRight),
-            '<' => Some(Token::MoveLeft),
-            '.' => Some(Token::Output),
-            ',' => Some(Token::Input),
-            '[' => Some(Token::LoopStart),
-            ']' => Some(Token::LoopEnd),
-            _ => None,
-        })
-        .collect()
+    input.chars().filter_map(token).collect()
+}
+
+pub fn tokenize_dialect(input: &str, dialect: &Dialect) -> Vec<Token> {
+    match dialect {
+        Dialect::Substitution(substitution) => substitution.tokenize(input),
+        _ => input.chars().filter_map(|c| dialect.token(c)).collect(),
+    }
+}
+
+/// Location of a token in the source: byte offsets plus the 1-based line and
+/// column (in characters) of `start`.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+pub fn tokenize_with_spans(input: &str) -> Vec<(Token, Span)> {
+    tokenize_dialect_with_spans(input, &Dialect::Classic)
+}
+
+/// Like `tokenize_with_spans` for any dialect of single-character commands.
+/// Substitutions have no spans and produce no tokens.
+pub fn tokenize_dialect_with_spans(input: &str, dialect: &Dialect) -> Vec<(Token, Span)> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut column = 1;
+
+    for (start, c) in input.char_indices() {
+        if let Some(token) = dialect.token(c) {
+            let span = Span {
+                start,
+                end: start + c.len_utf8(),
+                line,
+                column,
+            };
+            tokens.push((token, span));
+        }
+
+        if c == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
+        }
+    }
+
+    tokens
+}
+
+/// The range of tokens each `Ir` op produced by `to_ir` was built from.
+pub fn ir_token_ranges(tokens: impl AsRef<[Token]>) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    let mut previous: Option<&Token> = None;
+
+    for (index, token) in tokens.as_ref().iter().enumerate() {
+        let merges = matches!(
+            (previous, token),
+            (
+                Some(Token::MoveRight | Token::MoveLeft),
+                Token::MoveRight | Token::MoveLeft
+            ) | (
+                Some(Token::Increment | Token::Decrement),
+                Token::Increment | Token::Decrement
+            )
+        );
+        match ranges.last_mut() {
+            Some(range) if merges => range.end = index + 1,
+            _ => ranges.push(index..index + 1),
+        }
+        previous = Some(token);
+    }
+
+    ranges
 }
 
 #[derive(Error, Debug)]
@@ -38,6 +195,12 @@ pub enum IrError {
     #[error("unmatched loop at index {index}")]
     UnmatchedLoop { index: usize },
 
+    #[error("unmatched procedure at index {index}")]
+    UnmatchedProcedure { index: usize },
+
+    #[error("bracket at index {close} closes the bracket of another kind at index {open}")]
+    CrossedBrackets { open: usize, close: usize },
+
     #[error("generic error")]
     Generic(#[from] anyhow::Error),
 }
@@ -54,12 +217,46 @@ pub enum Ir {
     Move(isize),
     IO(bool),
     Loop(IrLoopType, usize),
+    /// Extended Brainfuck Type I operations on the current cell and the storage byte
+    Extended(ExtendedOp),
+    /// pbrain procedure definition, matched like `Loop`. Reaching `Start`
+    /// registers the body under the current cell's value and skips it
+    Procedure(IrLoopType, usize),
+    /// pbrain call of the procedure registered under the current cell's value,
+    /// a no-op when there is none
+    Call,
+    /// Dump the tape around the pointer to stderr
+    Debug,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ExtendedOp {
+    /// `@`: end the program
+    End,
+    /// `$`: storage = cell
+    Store,
+    /// `!`: cell = storage
+    Load,
+    /// `}`: cell >>= 1
+    ShiftRight,
+    /// `{`: cell <<= 1
+    ShiftLeft,
+    /// `~`: cell = !cell
+    Not,
+    /// `^`: cell ^= storage
+    Xor,
+    /// `&`: cell &= storage
+    And,
+    /// `|`: cell |= storage
+    Or,
 }
 
 pub fn to_ir(tokens: impl AsRef<[Token]>) -> Result<Vec<Ir>, IrError> {
     let tokens = tokens.as_ref();
     let mut ir_ops = Vec::with_capacity(tokens.len());
-    let mut token_to_ir_map = HashMap::with_capacity(tokens.len());
+    // Token and IR index of every `[` and `(` that is still open, as loops and
+    // procedures have to nest within each other
+    let mut open_brackets: Vec<(usize, usize)> = Vec::new();
 
     for (instruction_pointer, token) in tokens.iter().enumerate() {
         match token {
@@ -93,74 +290,217 @@ pub fn to_ir(tokens: impl AsRef<[Token]>) -> Result<Vec<Ir>, IrError> {
             }
             Token::Output => ir_ops.push(Ir::IO(false)),
             Token::Input => ir_ops.push(Ir::IO(true)),
+            // Patched with the index of the matching bracket once it is found
             Token::LoopStart => {
-                let mut counter = 0;
-                let mut temporary_pointer = instruction_pointer + 1;
-
-                loop {
-                    let token = &tokens[temporary_pointer];
-
-                    match token {
-                        Token::LoopStart => counter += 1,
-                        Token::LoopEnd => {
-                            if counter > 0 {
-                                counter -= 1;
-                            } else {
-                                ir_ops.push(Ir::Loop(IrLoopType::Start, temporary_pointer));
-                                break;
-                            }
-                        }
-                        _ => (),
+                open_brackets.push((instruction_pointer, ir_ops.len()));
+                ir_ops.push(Ir::Loop(IrLoopType::Start, 0));
+            }
+            Token::ProcedureStart => {
+                open_brackets.push((instruction_pointer, ir_ops.len()));
+                ir_ops.push(Ir::Procedure(IrLoopType::Start, 0));
+            }
+            Token::LoopEnd | Token::ProcedureEnd => {
+                let procedure = *token == Token::ProcedureEnd;
+                let unmatched = if procedure {
+                    IrError::UnmatchedProcedure {
+                        index: instruction_pointer,
                     }
-
-                    temporary_pointer += 1;
-                    if temporary_pointer == tokens.len() {
-                        return Err(IrError::UnmatchedLoop {
-                            index: instruction_pointer,
-                        });
+                } else {
+                    IrError::UnmatchedLoop {
+                        index: instruction_pointer,
                     }
-                }
-            }
-            Token::LoopEnd => {
-                let mut counter = 0;
-                let mut temporary_pointer = instruction_pointer - 1;
-
-                loop {
-                    let token = &tokens[temporary_pointer];
-
-                    match token {
-                        Token::LoopEnd => counter += 1,
-                        Token::LoopStart => {
-                            if counter > 0 {
-                                counter -= 1;
-                            } else {
-                                ir_ops.push(Ir::Loop(IrLoopType::End, temporary_pointer));
-                                break;
-                            }
-                        }
-                        _ => (),
+                };
+                let (open, start) = open_brackets.pop().ok_or(unmatched)?;
+                let end = ir_ops.len();
+                match (&mut ir_ops[start], procedure) {
+                    (Ir::Loop(_, index), false) => {
+                        *index = end;
+                        ir_ops.push(Ir::Loop(IrLoopType::End, start));
                     }
-
-                    temporary_pointer -= 1;
-                    if temporary_pointer == tokens.len() {
-                        return Err(IrError::UnmatchedLoop {
-                            index: instruction_pointer,
-                        });
+                    (Ir::Procedure(_, index), true) => {
+                        *index = end;
+                        ir_ops.push(Ir::Procedure(IrLoopType::End, start));
+                    }
+                    _ => {
+                        return Err(IrError::CrossedBrackets {
+                            open,
+                            close: instruction_pointer,
+                        })
                     }
                 }
             }
+            Token::End => ir_ops.push(Ir::Extended(ExtendedOp::End)),
+            Token::Store => ir_ops.push(Ir::Extended(ExtendedOp::Store)),
+            Token::Load => ir_ops.push(Ir::Extended(ExtendedOp::Load)),
+            Token::ShiftRight => ir_ops.push(Ir::Extended(ExtendedOp::ShiftRight)),
+            Token::ShiftLeft => ir_ops.push(Ir::Extended(ExtendedOp::ShiftLeft)),
+            Token::Not => ir_ops.push(Ir::Extended(ExtendedOp::Not)),
+            Token::Xor => ir_ops.push(Ir::Extended(ExtendedOp::Xor)),
+            Token::And => ir_ops.push(Ir::Extended(ExtendedOp::And)),
+            Token::Or => ir_ops.push(Ir::Extended(ExtendedOp::Or)),
+            Token::Call => ir_ops.push(Ir::Call),
+            Token::Debug => ir_ops.push(Ir::Debug),
+        }
+    }
+
+    match open_brackets.first() {
+        Some((index, start)) if matches!(ir_ops[*start], Ir::Procedure(_, _)) => {
+            Err(IrError::UnmatchedProcedure { index: *index })
+        }
+        Some((index, _)) => Err(IrError::UnmatchedLoop { index: *index }),
+        None => Ok(ir_ops),
+    }
+}
+
+// Formatting
+// ==========
+const FORMAT_INDENT: &str = "    ";
+const FORMAT_WIDTH: usize = 80;
+
+#[derive(PartialEq)]
+enum FormatLine {
+    Empty,
+    Code,
+    Bracket,
+}
+
+struct Formatter {
+    output: String,
+    depth: usize,
+    line: String,
+    line_depth: usize,
+    line_kind: FormatLine,
+}
+
+impl Formatter {
+    fn flush(&mut self) {
+        if self.line_kind != FormatLine::Empty {
+            let line = std::mem::take(&mut self.line);
+            self.emit(self.line_depth, &line);
+            self.line_kind = FormatLine::Empty;
+        }
+    }
+
+    fn emit(&mut self, depth: usize, text: &str) {
+        for _ in 0..depth {
+            self.output.push_str(FORMAT_INDENT);
+        }
+        self.output.push_str(text);
+        self.output.push('\n');
+    }
+
+    fn command(&mut self, command: char) {
+        if self.line_kind == FormatLine::Bracket {
+            self.flush();
+        }
+        if self.line_kind == FormatLine::Empty {
+            self.line_depth = self.depth;
+        }
+
+        // Runs of the same command stay together, different runs are separated by a space
+        let starts_run = !self.line.ends_with(command);
+        let width = self.line_depth * FORMAT_INDENT.len() + self.line.len() + 2;
+        if starts_run && self.line_kind == FormatLine::Code && width > FORMAT_WIDTH {
+            self.flush();
+            self.line_depth = self.depth;
+        }
+        if starts_run && !self.line.is_empty() {
+            self.line.push(' ');
+        }
+        self.line.push(command);
+        self.line_kind = FormatLine::Code;
+    }
+
+    fn bracket(&mut self, bracket: char) {
+        self.flush();
+        if bracket == ']' {
+            self.depth = self.depth.saturating_sub(1);
+        }
+        self.line.push(bracket);
+        self.line_depth = self.depth;
+        self.line_kind = FormatLine::Bracket;
+        if bracket == '[' {
+            self.depth += 1;
+        }
+    }
+
+    fn comment(&mut self, comment: &str) {
+        for (index, text) in comment.split('\n').enumerate() {
+            let text = text.trim();
+            if text.is_empty() {
+                continue;
+            }
+
+            // A comment on the same source line as the preceding code trails that code
+            if index == 0 && self.line_kind != FormatLine::Empty {
+                self.line.push(' ');
+                self.line.push_str(text);
+                self.flush();
+            } else {
+                self.flush();
+                self.emit(self.depth, text);
+            }
+        }
+    }
+}
+
+fn is_command(c: char) -> bool {
+    matches!(c, '+' | '-' | '>' | '<' | '.' | ',' | '[' | ']')
+}
+
+/// Reformats Brainfuck source deterministically: loop bodies are indented by
+/// depth, runs of the same command are grouped and comments are kept in place,
+/// either trailing the code they followed or on their own line.
+pub fn format(input: &str) -> String {
+    let mut formatter = Formatter {
+        output: String::new(),
+        depth: 0,
+        line: String::new(),
+        line_depth: 0,
+        line_kind: FormatLine::Empty,
+    };
+
+    let mut comment_start = None;
+    for (index, c) in input.char_indices() {
+        if !is_command(c) {
+            comment_start.get_or_insert(index);
+            continue;
+        }
+        if let Some(start) = comment_start.take() {
+            formatter.comment(&input[start..index]);
         }
 
-        token_to_ir_map.insert(instruction_pointer, ir_ops.len() - 1);
+        match c {
+            '[' | ']' => formatter.bracket(c),
+            _ => formatter.command(c),
+        }
+    }
+    if let Some(start) = comment_start {
+        formatter.comment(&input[start..]);
     }
+    formatter.flush();
+
+    formatter.output
+}
+
+/// Strips everything except the eight commands. With `cancel_pairs`, adjacent
+/// `+-`/`-+` and `<>`/`><` pairs are removed as well, since they have no effect.
+pub fn minify(input: &str, cancel_pairs: bool) -> String {
+    let mut output = String::with_capacity(input.len());
 
-    // Put proper IR indices for loops, instead of the token ones
-    for ref mut ir_op in ir_ops.iter_mut() {
-        match ir_op {
-            Ir::Loop(_, ref mut index) => *index = *token_to_ir_map.get(index).unwrap(),
-            _ => (),
+    for c in input.chars().filter(|c| is_command(*c)) {
+        if cancel_pairs {
+            let cancels = matches!(
+                (output.chars().last(), c),
+                (Some('+'), '-') | (Some('-'), '+') | (Some('<'), '>') | (Some('>'), '<')
+            );
+            if cancels {
+                output.pop();
+                continue;
+            }
         }
+        output.push(c);
     }
 
-    Ok(ir_ops)
+    output
 }
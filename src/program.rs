@@ -0,0 +1,237 @@
+// High-level API for embedding: parse a program once, pick how it is optimized
+// and which backend runs it, then run it any number of times against an `Io`.
+//
+//     let mut io = BufferIo::new("");
+//     Program::parse(source)?
+//         .optimize(OptLevel::Full)
+//         .compile(Backend::Jit)?
+//         .run(&mut io)?;
+
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use thiserror::Error;
+
+use crate::{
+    cancel::CancellationToken,
+    interpreter::{self, RuntimeError},
+    io::Io,
+    jit::{self, code::MachineCode, tiered},
+    optimizer::{self, OptimizedIr},
+    parser::{self, Dialect, Ir, IrError},
+    snapshot::Snapshot,
+    tape::{InvalidTapeLength, TapeConfig},
+    vm::{self, Bytecode},
+};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Parse(#[from] IrError),
+    #[error(transparent)]
+    Runtime(#[from] RuntimeError),
+    #[error(transparent)]
+    Tape(#[from] InvalidTapeLength),
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Runtime(error.into())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum OptLevel {
+    /// Run the IR as parsed
+    None,
+    /// Fold clear loops and transfer loops, see `optimizer::optimize`
+    #[default]
+    Full,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Backend {
+    /// The IR interpreter
+    Interpreter,
+    /// The bytecode VM
+    #[default]
+    Vm,
+    /// The interpreter, JIT-compiling hot loops
+    Tiered,
+    /// Cranelift, compiling the whole program up front
+    Jit,
+}
+
+/// A parsed program along with how it is to be optimized and the tape it runs on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    ir: Vec<Ir>,
+    level: OptLevel,
+    tape: TapeConfig,
+}
+
+impl Program {
+    /// Parses classic Brainfuck.
+    pub fn parse(source: &str) -> Result<Self, Error> {
+        Self::parse_dialect(source, &Dialect::Classic)
+    }
+
+    pub fn parse_dialect(source: &str, dialect: &Dialect) -> Result<Self, Error> {
+        Ok(Self::from_ir(parser::to_ir(parser::tokenize_dialect(
+            source, dialect,
+        ))?))
+    }
+
+    pub fn from_ir(ir: Vec<Ir>) -> Self {
+        Program {
+            ir,
+            level: OptLevel::default(),
+            tape: TapeConfig::default(),
+        }
+    }
+
+    pub fn optimize(self, level: OptLevel) -> Self {
+        Program { level, ..self }
+    }
+
+    pub fn tape(self, tape: TapeConfig) -> Self {
+        Program { tape, ..self }
+    }
+
+    pub fn ir(&self) -> &[Ir] {
+        &self.ir
+    }
+
+    /// The IR at the chosen optimization level.
+    pub fn optimized_ir(&self) -> Vec<OptimizedIr> {
+        match self.level {
+            OptLevel::None => optimizer::noop_optimzer(&self.ir),
+            OptLevel::Full => optimizer::optimize(&self.ir),
+        }
+    }
+
+    pub fn compile(&self, backend: Backend) -> Result<Executable, Error> {
+        self.tape.validate()?;
+        let ir = self.optimized_ir();
+        let compiled = match backend {
+            Backend::Interpreter => Compiled::Interpreter(ir),
+            Backend::Vm => Compiled::Vm(vm::compile(ir, self.tape)?),
+            Backend::Tiered => Compiled::Tiered(ir),
+            Backend::Jit => Compiled::Jit(jit::compile(ir, self.tape)?),
+        };
+        Ok(Executable {
+            compiled,
+            tape: self.tape,
+        })
+    }
+}
+
+/// A program ready to run. Every run starts from a fresh tape.
+#[derive(Debug, Clone)]
+pub struct Executable {
+    compiled: Compiled,
+    tape: TapeConfig,
+}
+
+#[derive(Debug, Clone)]
+enum Compiled {
+    Interpreter(Vec<OptimizedIr>),
+    Vm(Bytecode),
+    Tiered(Vec<OptimizedIr>),
+    Jit(MachineCode),
+}
+
+impl Executable {
+    pub fn run(&self, io: &mut dyn Io) -> Result<(), Error> {
+        self.run_cancellable(io, &CancellationToken::new())
+    }
+
+    /// Runs until the program ends or `cancel` is cancelled, in which case
+    /// this returns `RuntimeError::Cancelled`.
+    pub fn run_cancellable(
+        &self,
+        io: &mut dyn Io,
+        cancel: &CancellationToken,
+    ) -> Result<(), Error> {
+        match &self.compiled {
+            Compiled::Interpreter(ir) => {
+                interpreter::interpret_with(ir, self.tape, io, cancel)?;
+            }
+            Compiled::Vm(bytecode) => {
+                bytecode.run_with(io, cancel)?;
+            }
+            Compiled::Tiered(ir) => {
+                tiered::tiered_with(ir, self.tape, io, cancel)?;
+            }
+            Compiled::Jit(code) => jit::run_with(code, self.tape, io, cancel)?,
+        }
+        Ok(())
+    }
+
+    /// Continues a run from `snapshot`, such as the one carried by
+    /// `RuntimeError::Cancelled`, which may come from any backend as long as
+    /// the program, optimization level and tape are the same.
+    pub fn resume(
+        &self,
+        snapshot: Snapshot,
+        io: &mut dyn Io,
+        cancel: &CancellationToken,
+    ) -> Result<(), Error> {
+        match &self.compiled {
+            Compiled::Interpreter(ir) => {
+                interpreter::resume_with(ir, self.tape, snapshot, io, cancel)?;
+            }
+            Compiled::Vm(bytecode) => {
+                bytecode.resume_with(snapshot, io, cancel)?;
+            }
+            Compiled::Tiered(ir) => {
+                tiered::resume_with(ir, self.tape, snapshot, io, cancel)?;
+            }
+            Compiled::Jit(code) => jit::resume_with(code, self.tape, snapshot, io, cancel)?,
+        }
+        Ok(())
+    }
+}
+
+/// Runs every executable against its own `Io` on a pool of one thread per
+/// CPU, returning each `Io` along with its result in the order of `jobs`.
+/// Runs share no state, so output never interleaves between them.
+pub fn run_batch<'a, I: Io + Send>(
+    jobs: impl IntoIterator<Item = (&'a Executable, I)>,
+) -> Vec<(I, Result<(), Error>)> {
+    let jobs = jobs
+        .into_iter()
+        .map(|(executable, io)| Mutex::new((executable, io, None)))
+        .collect::<Vec<_>>();
+    let threads = thread::available_parallelism()
+        .map_or(1, NonZeroUsize::get)
+        .min(jobs.len());
+    let next = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                while let Some(job) = jobs.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let (executable, io, result) = &mut *job.lock().unwrap();
+                    *result = Some(executable.run(io));
+                }
+            });
+        }
+    });
+
+    jobs.into_iter()
+        .map(|job| {
+            let (_, io, result) = job.into_inner().unwrap();
+            (
+                io,
+                result.expect("Every job runs before the pool is joined"),
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,172 @@
+// Machine state of a program stopped between two IR ops, written to disk as
+// JSON. Every position is an index into the program's `OptimizedIr`, so a run
+// cancelled in one backend can be resumed in any other.
+
+use std::{collections::BTreeMap, collections::VecDeque, fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    io::Io,
+    optimizer::OptimizedIr,
+    parser::{Ir, IrLoopType},
+    tape::TapeConfig,
+};
+
+#[derive(Error, Debug)]
+pub enum SnapshotError {
+    #[error("the snapshot has {found} cells but the tape has {expected}")]
+    TapeLength { expected: usize, found: usize },
+
+    #[error("the snapshot's pointer {0} is off the tape")]
+    Pointer(usize),
+
+    #[error("IR op {0} is not a position in this program")]
+    IrIndex(usize),
+
+    #[error("this backend can't resume at IR op {0}")]
+    NotResumable(usize),
+
+    #[error("the program can't be at IR op {ir_index} with its pointer at {pointer}")]
+    Unreachable { ir_index: usize, pointer: usize },
+
+    #[error("invalid snapshot")]
+    Format(#[from] serde_json::Error),
+
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub tape: Vec<u8>,
+    pub pointer: usize,
+    /// Index of the next IR op to run
+    pub ir_index: usize,
+    /// Extended Brainfuck storage byte
+    pub storage: u8,
+    /// pbrain procedures by ID, as the index of their `Procedure(Start)`
+    pub procedures: BTreeMap<u8, usize>,
+    /// pbrain return addresses, innermost last
+    pub call_stack: Vec<usize>,
+    /// Input the `Io` had read ahead of the program, see `Io::pending_input`
+    pub pending_input: Vec<u8>,
+}
+
+impl Snapshot {
+    /// The state before the first op runs.
+    pub fn new(tape: TapeConfig) -> Self {
+        Snapshot {
+            tape: vec![0; tape.len],
+            pointer: 0,
+            ir_index: 0,
+            storage: 0,
+            procedures: BTreeMap::new(),
+            call_stack: Vec::new(),
+            pending_input: Vec::new(),
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SnapshotError> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        Ok(fs::write(path, serde_json::to_string(self)?)?)
+    }
+
+    /// Checks that the snapshot fits `tape` and only refers to positions in
+    /// `ir_ops`, so resuming from it can't index out of bounds.
+    pub fn validate(&self, ir_ops: &[OptimizedIr], tape: TapeConfig) -> Result<(), SnapshotError> {
+        self.validate_tape(tape)?;
+        let procedure = |index: usize| {
+            matches!(
+                ir_ops.get(index),
+                Some(OptimizedIr::Ir(Ir::Procedure(IrLoopType::Start, _)))
+            )
+        };
+        let call = |index: usize| {
+            index
+                .checked_sub(1)
+                .is_some_and(|call| matches!(ir_ops.get(call), Some(OptimizedIr::Ir(Ir::Call))))
+        };
+        if self.ir_index > ir_ops.len() {
+            return Err(SnapshotError::IrIndex(self.ir_index));
+        }
+        if let Some(index) = self.procedures.values().find(|index| !procedure(**index)) {
+            return Err(SnapshotError::IrIndex(*index));
+        }
+        if let Some(index) = self.call_stack.iter().find(|index| !call(**index)) {
+            return Err(SnapshotError::IrIndex(*index));
+        }
+        Ok(())
+    }
+
+    /// The half of `validate` that doesn't need the IR.
+    pub(crate) fn validate_tape(&self, tape: TapeConfig) -> Result<(), SnapshotError> {
+        if self.tape.len() != tape.len {
+            return Err(SnapshotError::TapeLength {
+                expected: tape.len,
+                found: self.tape.len(),
+            });
+        }
+        if self.pointer >= tape.len {
+            return Err(SnapshotError::Pointer(self.pointer));
+        }
+        Ok(())
+    }
+
+    /// `procedures` as the table the interpreters keep.
+    pub(crate) fn procedure_table(&self) -> [Option<usize>; 256] {
+        let mut table = [None; 256];
+        for (id, index) in &self.procedures {
+            table[*id as usize] = Some(*index);
+        }
+        table
+    }
+
+    pub(crate) fn procedures_from_table(table: &[Option<usize>; 256]) -> BTreeMap<u8, usize> {
+        (0..=u8::MAX)
+            .filter_map(|id| Some((id, table[id as usize]?)))
+            .collect()
+    }
+}
+
+/// Reads a snapshot's pending input before any from `io`.
+pub(crate) struct PendingIo<'a> {
+    pending: VecDeque<u8>,
+    io: &'a mut dyn Io,
+}
+
+impl<'a> PendingIo<'a> {
+    pub(crate) fn new(pending: &[u8], io: &'a mut dyn Io) -> Self {
+        PendingIo {
+            pending: pending.iter().copied().collect(),
+            io,
+        }
+    }
+}
+
+impl Io for PendingIo<'_> {
+    fn read(&mut self) -> std::io::Result<Option<u8>> {
+        match self.pending.pop_front() {
+            Some(value) => Ok(Some(value)),
+            None => self.io.read(),
+        }
+    }
+
+    fn write(&mut self, value: u8) -> std::io::Result<()> {
+        self.io.write(value)
+    }
+
+    fn debug(&mut self, dump: &str) -> std::io::Result<()> {
+        self.io.debug(dump)
+    }
+
+    fn pending_input(&mut self) -> Vec<u8> {
+        let mut pending = self.pending.drain(..).collect::<Vec<_>>();
+        pending.extend(self.io.pending_input());
+        pending
+    }
+}
@@ -0,0 +1,283 @@
+// Tape usage from the interpreter: the cells a program touched, how often each
+// was read and written, and how often the pointer wrapped around an end of the
+// tape. Cells are numbered as if the tape went on past its ends, so that -1 is
+// the last cell reached by moving left from the first, and the extent of the
+// touched cells is the length of tape a program needs.
+
+use std::{io::Write, ops::RangeInclusive};
+
+use crate::{
+    interpreter::Observer,
+    optimizer::OptimizedIr,
+    parser::{ExtendedOp, Ir, IrLoopType},
+    tape::TapeConfig,
+};
+
+/// Cells per row of a heatmap
+const HEATMAP_WIDTH: usize = 64;
+/// Pixels per side of a cell in a PPM heatmap
+const PPM_SCALE: usize = 8;
+/// Characters of an ASCII heatmap, from untouched to busiest
+const ASCII_SHADES: &[u8] = b" .:-=+*#%@";
+
+/// Counts the accesses of a run as an `Observer` of the interpreter.
+pub struct TapeStats<'a> {
+    ops: &'a [OptimizedIr],
+    reads: Vec<u64>,
+    writes: Vec<u64>,
+    /// Lowest and highest cell the pointer has been on or accessed, unwrapped
+    extent: Option<(isize, isize)>,
+    /// What to add to a cell index to unwrap it, from the wraparounds so far
+    unwrap: isize,
+    wraparounds: u64,
+    steps: u64,
+}
+
+impl<'a> TapeStats<'a> {
+    pub fn new(ir_ops: &'a [OptimizedIr], tape: TapeConfig) -> Self {
+        TapeStats {
+            ops: ir_ops,
+            reads: vec![0; tape.len],
+            writes: vec![0; tape.len],
+            extent: None,
+            unwrap: 0,
+            wraparounds: 0,
+            steps: 0,
+        }
+    }
+
+    /// Times each cell was read, by its index on the tape
+    pub fn reads(&self) -> &[u64] {
+        &self.reads
+    }
+
+    /// Times each cell was written, by its index on the tape
+    pub fn writes(&self) -> &[u64] {
+        &self.writes
+    }
+
+    /// The lowest and highest cell the program touched, unwrapped
+    pub fn extent(&self) -> Option<RangeInclusive<isize>> {
+        self.extent.map(|(min, max)| min..=max)
+    }
+
+    /// Times the pointer, or the target of an `addzero`, went past an end of
+    /// the tape and came back in at the other
+    pub fn wraparounds(&self) -> u64 {
+        self.wraparounds
+    }
+
+    /// Ops run
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// The number of cells the program touched, which without wraparounds is
+    /// the shortest tape it runs the same on.
+    pub fn min_tape_len(&self) -> usize {
+        self.extent.map_or(0, |(min, max)| (max - min) as usize + 1)
+    }
+
+    /// The unwrapped cells to show, each with its index on the tape: the
+    /// extent, or the whole tape once the extent is longer.
+    fn cells(&self) -> Vec<(isize, usize)> {
+        let len = self.reads.len();
+        match self.extent {
+            None => Vec::new(),
+            Some(_) if self.min_tape_len() > len => {
+                (0..len).map(|cell| (cell as isize, cell)).collect()
+            }
+            Some((min, max)) => (min..=max)
+                .map(|cell| (cell, cell.rem_euclid(len as isize) as usize))
+                .collect(),
+        }
+    }
+
+    /// Writes a summary, followed by the reads and writes of every cell that
+    /// saw any.
+    pub fn report(&self, output: &mut dyn Write) -> std::io::Result<()> {
+        writeln!(output, "ops run: {}", self.steps)?;
+        match self.extent() {
+            Some(extent) => writeln!(
+                output,
+                "cells touched: {}..={} ({} cells)",
+                extent.start(),
+                extent.end(),
+                self.min_tape_len()
+            )?,
+            None => writeln!(output, "cells touched: none")?,
+        }
+        if self.wraparounds == 0 {
+            writeln!(output, "wraparounds: 0")?;
+        } else {
+            writeln!(
+                output,
+                "wraparounds: {}, the program relies on the tape wrapping around",
+                self.wraparounds
+            )?;
+        }
+        writeln!(output, "{:>8} {:>12} {:>12}", "cell", "reads", "writes")?;
+        for (cell, index) in self.cells() {
+            let (reads, writes) = (self.reads[index], self.writes[index]);
+            if reads > 0 || writes > 0 {
+                writeln!(output, "{cell:>8} {reads:>12} {writes:>12}")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Draws the accesses to the touched cells as rows of characters, each
+    /// starting with the index of its first cell.
+    pub fn ascii_heatmap(&self, output: &mut dyn Write) -> std::io::Result<()> {
+        let cells = self.cells();
+        let heat = self.heat(&cells);
+        for (row, heat) in cells.chunks(HEATMAP_WIDTH).zip(heat.chunks(HEATMAP_WIDTH)) {
+            let shades = heat
+                .iter()
+                .map(|heat| {
+                    let last = ASCII_SHADES.len() - 1;
+                    let shade = if *heat == 0.0 {
+                        0
+                    } else {
+                        1 + (heat * (last - 1) as f64).round() as usize
+                    };
+                    ASCII_SHADES[shade] as char
+                })
+                .collect::<String>();
+            writeln!(output, "{:>8} |{shades}|", row[0].0)?;
+        }
+        Ok(())
+    }
+
+    /// Draws the accesses to the touched cells as a binary PPM image, a square
+    /// per cell in rows of 64, from black for untouched through red and
+    /// yellow to white for the busiest.
+    pub fn ppm_heatmap(&self, output: &mut dyn Write) -> std::io::Result<()> {
+        let heat = self.heat(&self.cells());
+        let columns = heat.len().clamp(1, HEATMAP_WIDTH);
+        let rows = heat.len().div_ceil(HEATMAP_WIDTH).max(1);
+        write!(
+            output,
+            "P6\n{} {}\n255\n",
+            columns * PPM_SCALE,
+            rows * PPM_SCALE
+        )?;
+        for row in 0..rows {
+            let mut line = Vec::with_capacity(columns * PPM_SCALE * 3);
+            for column in 0..columns {
+                let heat = heat.get(row * HEATMAP_WIDTH + column).copied();
+                let color = heat.map_or([0; 3], heat_color);
+                for _ in 0..PPM_SCALE {
+                    line.extend(color);
+                }
+            }
+            for _ in 0..PPM_SCALE {
+                output.write_all(&line)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Accesses of every one of `cells`, scaled logarithmically to 0 for none
+    /// through 1 for the busiest cell.
+    fn heat(&self, cells: &[(isize, usize)]) -> Vec<f64> {
+        let accesses = cells
+            .iter()
+            .map(|(_, index)| self.reads[*index] + self.writes[*index])
+            .collect::<Vec<_>>();
+        let busiest = (*accesses.iter().max().unwrap_or(&0) as f64).ln_1p();
+        accesses
+            .into_iter()
+            .map(|count| match count {
+                0 => 0.0,
+                count => (count as f64).ln_1p() / busiest,
+            })
+            .collect()
+    }
+
+    fn touch(&mut self, index: usize) {
+        let cell = index as isize + self.unwrap;
+        self.extent = Some(match self.extent {
+            Some((min, max)) => (min.min(cell), max.max(cell)),
+            None => (cell, cell),
+        });
+    }
+
+    fn read(&mut self, cell: usize) {
+        self.reads[cell] += 1;
+    }
+
+    fn write(&mut self, cell: usize) {
+        self.writes[cell] += 1;
+    }
+
+    /// Touches the cell `offset` away from `pointer` and returns its index,
+    /// counting a wraparound if it is past an end of the tape. The pointer
+    /// only moves there for a `move`, so only then does it stay unwrapped.
+    fn offset(&mut self, pointer: usize, offset: isize, moves: bool) -> usize {
+        let len = self.reads.len() as isize;
+        let target = pointer as isize + offset;
+        let index = target.rem_euclid(len);
+        if index != target {
+            self.wraparounds += 1;
+        }
+        let unwrap = self.unwrap;
+        self.unwrap += target - index;
+        self.touch(index as usize);
+        if !moves {
+            self.unwrap = unwrap;
+        }
+        index as usize
+    }
+}
+
+impl Observer for TapeStats<'_> {
+    fn before(&mut self, ip: usize, pointer: usize, _memory: &[u8]) {
+        self.steps += 1;
+        self.touch(pointer);
+        match &self.ops[ip] {
+            OptimizedIr::Ir(op) => match op {
+                Ir::Move(amount) => {
+                    self.offset(pointer, *amount, true);
+                }
+                Ir::Data(_) => {
+                    self.read(pointer);
+                    self.write(pointer);
+                }
+                Ir::IO(true) => self.write(pointer),
+                Ir::IO(false) => self.read(pointer),
+                Ir::Loop(_, _) | Ir::Procedure(IrLoopType::Start, _) | Ir::Call => {
+                    self.read(pointer)
+                }
+                Ir::Procedure(IrLoopType::End, _) | Ir::Debug => {}
+                Ir::Extended(op) => match op {
+                    ExtendedOp::End | ExtendedOp::Store => self.read(pointer),
+                    ExtendedOp::Load => self.write(pointer),
+                    ExtendedOp::ShiftRight
+                    | ExtendedOp::ShiftLeft
+                    | ExtendedOp::Not
+                    | ExtendedOp::Xor
+                    | ExtendedOp::And
+                    | ExtendedOp::Or => {
+                        self.read(pointer);
+                        self.write(pointer);
+                    }
+                },
+            },
+            OptimizedIr::ResetToZero => self.write(pointer),
+            OptimizedIr::AddAndZero(target) => {
+                self.read(pointer);
+                self.write(pointer);
+                let target = self.offset(pointer, *target, false);
+                self.read(target);
+                self.write(target);
+            }
+        }
+    }
+}
+
+/// Black for 0 through red and yellow to white for 1.
+fn heat_color(heat: f64) -> [u8; 3] {
+    let channel = |start: f64| ((heat * 3.0 - start).clamp(0.0, 1.0) * 255.0).round() as u8;
+    [channel(0.0), channel(1.0), channel(2.0)]
+}
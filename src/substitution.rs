@@ -0,0 +1,156 @@
+// Trivial-substitution languages: every command is spelled as a fixed word or
+// phrase instead of a single character. Mappings are small TOML or JSON files:
+//
+//   name = "ook"
+//   extensions = ["ook"]
+//
+//   [commands]
+//   ">" = "Ook. Ook?"
+//   "<" = "Ook? Ook."
+//   ...
+//
+// Whitespace is ignored both in the phrases and in the source, so phrases may
+// be split across lines. Anything that does not spell a command is a comment.
+
+use std::{collections::BTreeMap, fs, path::Path};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::parser::{Dialect, Token};
+
+const BUILTIN: [&str; 3] = [
+    include_str!("../dialects/ook.toml"),
+    include_str!("../dialects/blub.toml"),
+    include_str!("../dialects/alphuck.toml"),
+];
+
+#[derive(Error, Debug)]
+pub enum SubstitutionError {
+    #[error("io error")]
+    IoError(#[from] std::io::Error),
+    #[error("invalid TOML mapping")]
+    Toml(#[from] toml::de::Error),
+    #[error("invalid JSON mapping")]
+    Json(#[from] serde_json::Error),
+
+    #[error("`{command}` is not a command")]
+    UnknownCommand { command: String },
+    #[error("the phrase for `{command}` is empty")]
+    EmptyPhrase { command: String },
+}
+
+#[derive(Deserialize)]
+struct Mapping {
+    name: String,
+    #[serde(default)]
+    extensions: Vec<String>,
+    commands: BTreeMap<String, String>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Substitution {
+    pub name: String,
+    pub extensions: Vec<String>,
+    // Phrases without whitespace, longest first so that a phrase which is a
+    // prefix of another never shadows it
+    phrases: Vec<(Vec<char>, Token)>,
+}
+
+impl Substitution {
+    pub fn from_toml(mapping: &str) -> Result<Self, SubstitutionError> {
+        Self::from_mapping(toml::from_str(mapping)?)
+    }
+
+    pub fn from_json(mapping: &str) -> Result<Self, SubstitutionError> {
+        Self::from_mapping(serde_json::from_str(mapping)?)
+    }
+
+    /// Loads a mapping file, as JSON if it has a `.json` extension and as TOML otherwise.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SubstitutionError> {
+        let path = path.as_ref();
+        let mapping = fs::read_to_string(path)?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => Self::from_json(&mapping),
+            _ => Self::from_toml(&mapping),
+        }
+    }
+
+    /// One of the bundled languages: `ook`, `blub` or `alphuck`.
+    pub fn builtin(name: &str) -> Option<Self> {
+        builtins().find(|substitution| substitution.name == name)
+    }
+
+    /// The bundled language that uses `extension` for its source files.
+    pub fn for_extension(extension: &str) -> Option<Self> {
+        builtins().find(|substitution| {
+            substitution
+                .extensions
+                .iter()
+                .any(|candidate| candidate == extension)
+        })
+    }
+
+    fn from_mapping(mapping: Mapping) -> Result<Self, SubstitutionError> {
+        let mut phrases = Vec::with_capacity(mapping.commands.len());
+        for (command, phrase) in mapping.commands {
+            let mut chars = command.chars();
+            let token = match (chars.next(), chars.next()) {
+                (Some(c), None) => Dialect::Extended
+                    .token(c)
+                    .or_else(|| Dialect::PBrain.token(c)),
+                _ => None,
+            }
+            .ok_or(SubstitutionError::UnknownCommand {
+                command: command.clone(),
+            })?;
+
+            let phrase = phrase
+                .chars()
+                .filter(|c| !c.is_whitespace())
+                .collect::<Vec<_>>();
+            if phrase.is_empty() {
+                return Err(SubstitutionError::EmptyPhrase { command });
+            }
+            phrases.push((phrase, token));
+        }
+        phrases.sort_by_key(|(phrase, _)| std::cmp::Reverse(phrase.len()));
+
+        Ok(Substitution {
+            name: mapping.name,
+            extensions: mapping.extensions,
+            phrases,
+        })
+    }
+
+    pub fn tokenize(&self, input: &str) -> Vec<Token> {
+        let input = input
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<Vec<_>>();
+        let mut tokens = Vec::new();
+        let mut index = 0;
+
+        while index < input.len() {
+            let matched = self
+                .phrases
+                .iter()
+                .find(|(phrase, _)| input[index..].starts_with(phrase));
+            match matched {
+                Some((phrase, token)) => {
+                    tokens.push(token.clone());
+                    index += phrase.len();
+                }
+                None => index += 1,
+            }
+        }
+
+        tokens
+    }
+}
+
+fn builtins() -> impl Iterator<Item = Substitution> {
+    BUILTIN.iter().map(|mapping| {
+        Substitution::from_toml(mapping).expect("Bundled substitution mappings are valid")
+    })
+}
@@ -0,0 +1,88 @@
+// Tape settings shared by every way of running a program.
+
+use std::str::FromStr;
+
+use thiserror::Error;
+
+/// What `,` does to the current cell once the input is exhausted.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Hash)]
+pub enum EofPolicy {
+    /// Leave the cell as it was
+    #[default]
+    Unchanged,
+    /// Store 0
+    Zero,
+    /// Store -1, i.e. 255
+    MinusOne,
+}
+
+#[derive(Error, Debug)]
+#[error("unknown EOF policy `{0}`, expected 'unchanged', 'zero' or 'minus-one'")]
+pub struct UnknownEofPolicy(String);
+
+impl FromStr for EofPolicy {
+    type Err = UnknownEofPolicy;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "unchanged" => Ok(EofPolicy::Unchanged),
+            "zero" | "0" => Ok(EofPolicy::Zero),
+            "minus-one" | "-1" => Ok(EofPolicy::MinusOne),
+            _ => Err(UnknownEofPolicy(name.to_string())),
+        }
+    }
+}
+
+impl EofPolicy {
+    /// The new value of a cell holding `cell` after reading past the end of the input.
+    pub fn apply(self, cell: u8) -> u8 {
+        match self {
+            EofPolicy::Unchanged => cell,
+            EofPolicy::Zero => 0,
+            EofPolicy::MinusOne => u8::MAX,
+        }
+    }
+}
+
+/// The most cells a tape can have, as the VM addresses them with 32-bit operands
+pub const MAX_LEN: usize = u32::MAX as usize;
+
+#[derive(Error, Debug)]
+#[error("invalid tape size `{0}`, expected between 1 and {MAX_LEN} cells")]
+pub struct InvalidTapeLength(String);
+
+/// Parses a number of cells in `1..=MAX_LEN`.
+pub fn parse_len(len: &str) -> Result<usize, InvalidTapeLength> {
+    len.trim()
+        .parse()
+        .ok()
+        .filter(|len| (1..=MAX_LEN).contains(len))
+        .ok_or_else(|| InvalidTapeLength(len.to_string()))
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct TapeConfig {
+    /// Number of cells; the pointer wraps around at both ends
+    pub len: usize,
+    pub eof: EofPolicy,
+}
+
+impl TapeConfig {
+    /// Checks that the tape has between 1 and `MAX_LEN` cells.
+    pub fn validate(&self) -> Result<(), InvalidTapeLength> {
+        if (1..=MAX_LEN).contains(&self.len) {
+            Ok(())
+        } else {
+            Err(InvalidTapeLength(self.len.to_string()))
+        }
+    }
+}
+
+impl Default for TapeConfig {
+    fn default() -> Self {
+        TapeConfig {
+            len: 30_000,
+            eof: EofPolicy::default(),
+        }
+    }
+}
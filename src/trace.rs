@@ -0,0 +1,171 @@
+// Execution traces from the interpreter: one JSON object per line for every op
+// it runs, with the pointer and the current cell before and after, so two runs
+// of a program can be compared with a line diff.
+//
+//   {"step":0,"ip":0,"op":"add 1","line":1,"pointer":0,"before":0,"after":1}
+
+use std::{io::Write, ops::RangeInclusive};
+
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{
+    interpreter::Observer,
+    ir_text,
+    optimizer::OptimizedIr,
+    parser::{ir_token_ranges, Span, Token},
+};
+
+/// Which of the ops that run are written to the trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceFilter {
+    /// Write every nth op that passes the range filters, starting with the first
+    pub every: u64,
+    /// Only ops at these IR indices
+    pub ops: Option<RangeInclusive<usize>>,
+    /// Only ops from these source lines. Ops with no known line are left out.
+    pub lines: Option<RangeInclusive<usize>>,
+}
+
+impl Default for TraceFilter {
+    fn default() -> Self {
+        TraceFilter {
+            every: 1,
+            ops: None,
+            lines: None,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("invalid range `{0}`, expected a number or two joined by '-', such as 10-20")]
+pub struct InvalidRange(String);
+
+/// Parses `10-20` or a single `10` as an inclusive range.
+pub fn parse_range(range: &str) -> Result<RangeInclusive<usize>, InvalidRange> {
+    let bound = |bound: &str| {
+        bound
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| InvalidRange(range.to_string()))
+    };
+    match range.split_once('-') {
+        Some((start, end)) => Ok(bound(start)?..=bound(end)?),
+        None => {
+            let line = bound(range)?;
+            Ok(line..=line)
+        }
+    }
+}
+
+/// The source line each optimized op came from, given the spanned tokens of
+/// the source and the `Ir` index each op starts at (see
+/// `optimizer::optimize_with_origins`).
+pub fn op_lines(tokens: &[(Token, Span)], origins: &[usize]) -> Vec<usize> {
+    let ranges = ir_token_ranges(
+        tokens
+            .iter()
+            .map(|(token, _)| token.clone())
+            .collect::<Vec<_>>(),
+    );
+    origins
+        .iter()
+        .map(|origin| tokens[ranges[*origin].start].1.line)
+        .collect()
+}
+
+#[derive(Serialize)]
+struct TraceEntry<'a> {
+    /// Number of ops run before this one
+    step: u64,
+    ip: usize,
+    op: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<usize>,
+    pointer: usize,
+    before: u8,
+    after: u8,
+}
+
+/// Writes the trace of a run to `output` as an `Observer` of the interpreter.
+pub struct Tracer<'a> {
+    output: &'a mut dyn Write,
+    filter: TraceFilter,
+    /// Textual IR of every op
+    ops: Vec<String>,
+    /// Source line of every op, if known
+    lines: Option<Vec<usize>>,
+    step: u64,
+    /// Ops that passed the range filters so far
+    matched: u64,
+    /// IR index, pointer and cell of the op being run
+    current: Option<(usize, usize, u8)>,
+}
+
+impl<'a> Tracer<'a> {
+    pub fn new(output: &'a mut dyn Write, ir_ops: &[OptimizedIr], filter: TraceFilter) -> Self {
+        Tracer {
+            output,
+            filter,
+            ops: ir_ops
+                .iter()
+                .map(|op| ir_text::print([op.clone()]).trim().to_string())
+                .collect(),
+            lines: None,
+            step: 0,
+            matched: 0,
+            current: None,
+        }
+    }
+
+    /// Adds the source line of every op, from `op_lines`.
+    pub fn lines(self, lines: Vec<usize>) -> Self {
+        Tracer {
+            lines: Some(lines),
+            ..self
+        }
+    }
+}
+
+impl Observer for Tracer<'_> {
+    fn before(&mut self, ip: usize, pointer: usize, memory: &[u8]) {
+        self.current = Some((ip, pointer, memory[pointer]));
+    }
+
+    /// Writes the op noted by `before` now that it has run, if it passes the
+    /// filters.
+    fn after(&mut self, memory: &[u8]) -> std::io::Result<()> {
+        let Some((ip, pointer, before)) = self.current.take() else {
+            return Ok(());
+        };
+        let step = self.step;
+        self.step += 1;
+
+        let line = self.lines.as_ref().map(|lines| lines[ip]);
+        let in_range = self.filter.ops.as_ref().is_none_or(|ops| ops.contains(&ip))
+            && self
+                .filter
+                .lines
+                .as_ref()
+                .is_none_or(|lines| line.is_some_and(|line| lines.contains(&line)));
+        if !in_range {
+            return Ok(());
+        }
+        self.matched += 1;
+        if !(self.matched - 1).is_multiple_of(self.filter.every.max(1)) {
+            return Ok(());
+        }
+
+        let entry = TraceEntry {
+            step,
+            ip,
+            op: &self.ops[ip],
+            line,
+            pointer,
+            before,
+            after: memory[pointer],
+        };
+        serde_json::to_writer(&mut *self.output, &entry)?;
+        self.output.write_all(b"\n")
+    }
+}
@@ -0,0 +1,173 @@
+// C99 backend. The tape is a static array and procedures are `void (void)`
+// functions stored in a table indexed by cell value.
+
+use crate::{
+    optimizer::OptimizedIr,
+    parser::{ExtendedOp, Ir},
+    tape::{EofPolicy, TapeConfig},
+};
+
+use super::{cell_delta, ends_program, uses_storage, Node, Program, Writer};
+
+const WRAP: &str = "\
+static long wrap(long index) {
+    index %= TAPE_LEN;
+    return index < 0 ? index + TAPE_LEN : index;
+}
+";
+
+const DEBUG: &str = "\
+static void debug(void) {
+    long start = ptr < 8 ? 0 : ptr - 8;
+    long end = ptr + 9 < TAPE_LEN ? ptr + 9 : TAPE_LEN;
+    fflush(stdout);
+    fprintf(stderr, \"# pointer %ld, cells %ld..%ld:\", ptr, start, end);
+    for (long i = start; i < end; i++) {
+        fprintf(stderr, i == ptr ? \"[%3d]\" : \" %3d \", tape[i]);
+    }
+    fputc('\\n', stderr);
+}
+";
+
+pub(super) fn emit(program: &Program, tape: TapeConfig) -> String {
+    let mut writer = Writer::default();
+
+    writer.line("/* Generated by cranefuck */");
+    writer.line("#include <stdio.h>");
+    if program.uses(|op| matches!(op, OptimizedIr::Ir(Ir::Extended(ExtendedOp::End)))) {
+        writer.line("#include <stdlib.h>");
+    }
+    writer.line("");
+    writer.line(format!("#define TAPE_LEN {}L", tape.len));
+    writer.line("");
+    writer.line("static unsigned char tape[TAPE_LEN];");
+    writer.line("static long ptr = 0;");
+    if program.uses(uses_storage) {
+        writer.line("static unsigned char storage = 0;");
+    }
+    if !program.procedures.is_empty() {
+        writer.line("static void (*procedures[256])(void);");
+    }
+    writer.line("");
+
+    if program.uses(|op| {
+        matches!(
+            op,
+            OptimizedIr::Ir(Ir::Move(_)) | OptimizedIr::AddAndZero(_)
+        )
+    }) {
+        writer.lines(WRAP);
+        writer.line("");
+    }
+    if program.uses(|op| matches!(op, OptimizedIr::Ir(Ir::IO(true)))) {
+        writer.line("static void input(void) {");
+        writer.depth += 1;
+        writer.line("int c = getchar();");
+        match tape.eof {
+            EofPolicy::Unchanged => {
+                writer.line("if (c != EOF) {");
+                writer.line("    tape[ptr] = (unsigned char)c;");
+                writer.line("}");
+            }
+            EofPolicy::Zero => writer.line("tape[ptr] = c == EOF ? 0 : (unsigned char)c;"),
+            EofPolicy::MinusOne => writer.line("tape[ptr] = c == EOF ? 255 : (unsigned char)c;"),
+        }
+        writer.depth -= 1;
+        writer.line("}");
+        writer.line("");
+    }
+    if program.uses(|op| matches!(op, OptimizedIr::Ir(Ir::Debug))) {
+        writer.lines(DEBUG);
+        writer.line("");
+    }
+
+    for number in 1..=program.procedures.len() {
+        writer.line(format!("static void procedure_{number}(void);"));
+    }
+    if !program.procedures.is_empty() {
+        writer.line("");
+    }
+    for (index, body) in program.procedures.iter().enumerate() {
+        writer.line(format!("static void procedure_{}(void) {{", index + 1));
+        emit_block(&mut writer, body, false);
+        writer.line("}");
+        writer.line("");
+    }
+
+    writer.line("int main(void) {");
+    emit_block(&mut writer, &program.main, true);
+    writer.line("    return 0;");
+    writer.line("}");
+
+    writer.output
+}
+
+fn emit_block(writer: &mut Writer, nodes: &[Node], in_main: bool) {
+    writer.depth += 1;
+    for node in nodes {
+        match node {
+            Node::Loop(body) => {
+                writer.line("while (tape[ptr]) {");
+                emit_block(writer, body, in_main);
+                writer.line("}");
+            }
+            Node::Define(number) => {
+                writer.line(format!("procedures[tape[ptr]] = procedure_{number};"));
+            }
+            Node::Op(op) => emit_op(writer, op, in_main),
+        }
+        if ends_program(node) {
+            // Anything after `@` is unreachable
+            break;
+        }
+    }
+    writer.depth -= 1;
+}
+
+fn emit_op(writer: &mut Writer, op: &OptimizedIr, in_main: bool) {
+    match op {
+        OptimizedIr::Ir(Ir::Data(amount)) => match cell_delta(*amount) {
+            0 => {}
+            delta if delta > 0 => writer.line(format!("tape[ptr] += {delta};")),
+            delta => writer.line(format!("tape[ptr] -= {};", delta.unsigned_abs())),
+        },
+        OptimizedIr::Ir(Ir::Move(amount)) => match *amount {
+            0 => {}
+            amount if amount > 0 => writer.line(format!("ptr = wrap(ptr + {amount});")),
+            amount => writer.line(format!("ptr = wrap(ptr - {});", amount.unsigned_abs())),
+        },
+        OptimizedIr::Ir(Ir::IO(true)) => writer.line("input();"),
+        OptimizedIr::Ir(Ir::IO(false)) => writer.line("putchar(tape[ptr]);"),
+        OptimizedIr::Ir(Ir::Extended(op)) => writer.line(match op {
+            ExtendedOp::End if in_main => "return 0;",
+            ExtendedOp::End => "exit(0);",
+            ExtendedOp::Store => "storage = tape[ptr];",
+            ExtendedOp::Load => "tape[ptr] = storage;",
+            ExtendedOp::ShiftRight => "tape[ptr] >>= 1;",
+            ExtendedOp::ShiftLeft => "tape[ptr] <<= 1;",
+            ExtendedOp::Not => "tape[ptr] = ~tape[ptr];",
+            ExtendedOp::Xor => "tape[ptr] ^= storage;",
+            ExtendedOp::And => "tape[ptr] &= storage;",
+            ExtendedOp::Or => "tape[ptr] |= storage;",
+        }),
+        OptimizedIr::Ir(Ir::Call) => {
+            writer.line("if (procedures[tape[ptr]]) {");
+            writer.line("    procedures[tape[ptr]]();");
+            writer.line("}");
+        }
+        OptimizedIr::Ir(Ir::Debug) => writer.line("debug();"),
+        OptimizedIr::ResetToZero => writer.line("tape[ptr] = 0;"),
+        OptimizedIr::AddAndZero(target) => {
+            let target = if *target < 0 {
+                format!("ptr - {}", target.unsigned_abs())
+            } else {
+                format!("ptr + {target}")
+            };
+            writer.line(format!("tape[wrap({target})] += tape[ptr];"));
+            writer.line("tape[ptr] = 0;");
+        }
+        OptimizedIr::Ir(Ir::Loop(..) | Ir::Procedure(..)) => {
+            unreachable!("Loops and procedures are nodes of their own")
+        }
+    }
+}
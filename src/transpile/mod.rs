@@ -0,0 +1,215 @@
+// Source-to-source backends: turn `OptimizedIr` into a standalone program in
+// another language. Loops become structured `while` loops and pbrain
+// procedures become functions, so the flat IR is first rebuilt into a tree.
+
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::{
+    optimizer::OptimizedIr,
+    parser::{ExtendedOp, Ir, IrLoopType},
+    tape::{InvalidTapeLength, TapeConfig},
+};
+
+mod c;
+mod rust;
+mod wat;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Target {
+    C,
+    Rust,
+    /// WebAssembly text format, running under WASI
+    Wat,
+}
+
+/// How a WebAssembly module talks to the outside world.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum WasmImports {
+    /// `fd_read` and `fd_write` from `wasi_snapshot_preview1`
+    #[default]
+    Wasi,
+    /// `env.input() -> i32` (a byte, or -1 at the end of the input),
+    /// `env.output(i32)` and, for programs using `#`, `env.debug(i32)` which
+    /// receives the pointer while the tape is readable through the exported
+    /// `memory`
+    Host,
+}
+
+#[derive(Error, Debug)]
+pub enum TranspileError {
+    #[error("unknown target `{0}`, expected 'c', 'rust' or 'wat'")]
+    UnknownTarget(String),
+    #[error("unknown imports `{0}`, expected 'wasi' or 'host'")]
+    UnknownImports(String),
+    #[error("the loop or procedure starting at op {index} overlaps another one")]
+    Overlapping { index: usize },
+    #[error(transparent)]
+    Tape(#[from] InvalidTapeLength),
+}
+
+impl FromStr for Target {
+    type Err = TranspileError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "c" => Ok(Target::C),
+            "rust" | "rs" => Ok(Target::Rust),
+            "wat" => Ok(Target::Wat),
+            _ => Err(TranspileError::UnknownTarget(name.to_string())),
+        }
+    }
+}
+
+impl FromStr for WasmImports {
+    type Err = TranspileError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "wasi" => Ok(WasmImports::Wasi),
+            "host" => Ok(WasmImports::Host),
+            _ => Err(TranspileError::UnknownImports(name.to_string())),
+        }
+    }
+}
+
+pub fn transpile(
+    ir_ops: impl AsRef<[OptimizedIr]>,
+    target: Target,
+    tape: TapeConfig,
+) -> Result<String, TranspileError> {
+    tape.validate()?;
+    let program = Program::build(ir_ops.as_ref())?;
+    Ok(match target {
+        Target::C => c::emit(&program, tape),
+        Target::Rust => rust::emit(&program, tape),
+        Target::Wat => wat::emit(&program, tape, WasmImports::Wasi),
+    })
+}
+
+/// The WebAssembly text of `ir_ops` with the given imports, as assembled by
+/// the `wasm` backend.
+pub fn wat(
+    ir_ops: impl AsRef<[OptimizedIr]>,
+    tape: TapeConfig,
+    imports: WasmImports,
+) -> Result<String, TranspileError> {
+    tape.validate()?;
+    let program = Program::build(ir_ops.as_ref())?;
+    Ok(wat::emit(&program, tape, imports))
+}
+
+enum Node<'a> {
+    /// Any op other than the start or end of a loop or procedure
+    Op(&'a OptimizedIr),
+    Loop(Vec<Node<'a>>),
+    /// Defines procedure `n` (numbered from 1) under the current cell's value
+    Define(usize),
+}
+
+struct Program<'a> {
+    ir_ops: &'a [OptimizedIr],
+    main: Vec<Node<'a>>,
+    procedures: Vec<Vec<Node<'a>>>,
+}
+
+impl<'a> Program<'a> {
+    fn build(ir_ops: &'a [OptimizedIr]) -> Result<Self, TranspileError> {
+        let mut program = Program {
+            ir_ops,
+            main: Vec::new(),
+            procedures: Vec::new(),
+        };
+        program.main = program.build_range(0, ir_ops.len())?;
+        Ok(program)
+    }
+
+    fn build_range(&mut self, start: usize, end: usize) -> Result<Vec<Node<'a>>, TranspileError> {
+        let mut nodes = Vec::new();
+        let mut index = start;
+
+        while index < end {
+            match &self.ir_ops[index] {
+                OptimizedIr::Ir(Ir::Loop(IrLoopType::Start, body_end)) => {
+                    if *body_end >= end {
+                        return Err(TranspileError::Overlapping { index });
+                    }
+                    nodes.push(Node::Loop(self.build_range(index + 1, *body_end)?));
+                    index = *body_end;
+                }
+                OptimizedIr::Ir(Ir::Procedure(IrLoopType::Start, body_end)) => {
+                    if *body_end >= end {
+                        return Err(TranspileError::Overlapping { index });
+                    }
+                    // Reserve the number before building the body so nested
+                    // procedures are numbered after their parent
+                    self.procedures.push(Vec::new());
+                    let number = self.procedures.len();
+                    self.procedures[number - 1] = self.build_range(index + 1, *body_end)?;
+                    nodes.push(Node::Define(number));
+                    index = *body_end;
+                }
+                OptimizedIr::Ir(
+                    Ir::Loop(IrLoopType::End, _) | Ir::Procedure(IrLoopType::End, _),
+                ) => {
+                    return Err(TranspileError::Overlapping { index });
+                }
+                op => nodes.push(Node::Op(op)),
+            }
+            index += 1;
+        }
+
+        Ok(nodes)
+    }
+
+    /// Whether any op satisfies `predicate`, so backends only emit the helpers a
+    /// program needs.
+    fn uses(&self, predicate: impl Fn(&OptimizedIr) -> bool) -> bool {
+        self.ir_ops.iter().any(predicate)
+    }
+}
+
+fn uses_storage(op: &OptimizedIr) -> bool {
+    matches!(op, OptimizedIr::Ir(Ir::Extended(op)) if *op != ExtendedOp::End)
+}
+
+fn ends_program(node: &Node) -> bool {
+    matches!(
+        node,
+        Node::Op(OptimizedIr::Ir(Ir::Extended(ExtendedOp::End)))
+    )
+}
+
+/// A cell increment as the signed byte it wraps to.
+fn cell_delta(amount: i64) -> i8 {
+    amount as i8
+}
+
+/// Accumulates indented lines of output.
+#[derive(Default)]
+struct Writer {
+    output: String,
+    depth: usize,
+}
+
+impl Writer {
+    fn line(&mut self, text: impl AsRef<str>) {
+        let text = text.as_ref();
+        if !text.is_empty() {
+            for _ in 0..self.depth {
+                self.output.push_str("    ");
+            }
+        }
+        self.output.push_str(text);
+        self.output.push('\n');
+    }
+
+    /// Appends several lines at the current depth; each line of `text` keeps
+    /// its own relative indentation.
+    fn lines(&mut self, text: &str) {
+        for line in text.lines() {
+            self.line(line);
+        }
+    }
+}
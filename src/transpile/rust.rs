@@ -0,0 +1,225 @@
+// Rust backend. All state lives in a `Machine` so procedures can be plain
+// `fn(&mut Machine)` items stored in a table indexed by cell value.
+
+use crate::{
+    optimizer::OptimizedIr,
+    parser::{ExtendedOp, Ir},
+    tape::{EofPolicy, TapeConfig},
+};
+
+use super::{cell_delta, ends_program, uses_storage, Node, Program, Writer};
+
+const METHODS: &str = "\
+fn cell(&self) -> u8 {
+    self.tape[self.ptr]
+}
+
+fn add(&mut self, amount: i8) {
+    self.tape[self.ptr] = self.tape[self.ptr].wrapping_add_signed(amount);
+}
+
+fn shift(&mut self, amount: isize) {
+    self.ptr = self.wrap(amount);
+}
+
+fn wrap(&self, offset: isize) -> usize {
+    (self.ptr as isize + offset).rem_euclid(TAPE_LEN as isize) as usize
+}
+
+fn zero(&mut self) {
+    self.tape[self.ptr] = 0;
+}
+
+fn add_and_zero(&mut self, offset: isize) {
+    let target = self.wrap(offset);
+    self.tape[target] = self.tape[target].wrapping_add(self.tape[self.ptr]);
+    self.tape[self.ptr] = 0;
+}
+
+fn write(&mut self) {
+    self.output.write_all(&[self.cell()]).unwrap();
+}
+";
+
+const READ: &str = "\
+fn read(&mut self) {
+    self.output.flush().unwrap();
+    let mut byte = [0];
+    let read = self.input.read(&mut byte).unwrap();
+";
+
+const DEBUG: &str = "\
+fn debug(&mut self) {
+    let start = self.ptr.saturating_sub(8);
+    let end = (self.ptr + 9).min(TAPE_LEN);
+    self.output.flush().unwrap();
+    eprint!(\"# pointer {}, cells {start}..{end}:\", self.ptr);
+    for index in start..end {
+        if index == self.ptr {
+            eprint!(\"[{:3}]\", self.tape[index]);
+        } else {
+            eprint!(\" {:3} \", self.tape[index]);
+        }
+    }
+    eprintln!();
+}
+";
+
+const EXIT: &str = "\
+fn exit(&mut self) -> ! {
+    self.output.flush().unwrap();
+    std::process::exit(0)
+}
+";
+
+pub(super) fn emit(program: &Program, tape: TapeConfig) -> String {
+    let uses_storage = program.uses(uses_storage);
+    let uses_procedures = !program.procedures.is_empty();
+    let mut writer = Writer::default();
+
+    writer.line("// Generated by cranefuck");
+    writer.line("#![allow(dead_code)]");
+    writer.line("");
+    writer.line("use std::io::{Read, Write};");
+    writer.line("");
+    writer.line(format!("const TAPE_LEN: usize = {};", tape.len));
+    writer.line("");
+
+    writer.line("struct Machine {");
+    writer.depth += 1;
+    writer.line("tape: Vec<u8>,");
+    writer.line("ptr: usize,");
+    if uses_storage {
+        writer.line("storage: u8,");
+    }
+    if uses_procedures {
+        writer.line("procedures: [Option<fn(&mut Machine)>; 256],");
+    }
+    writer.line("input: std::io::StdinLock<'static>,");
+    writer.line("output: std::io::StdoutLock<'static>,");
+    writer.depth -= 1;
+    writer.line("}");
+    writer.line("");
+
+    writer.line("impl Machine {");
+    writer.depth += 1;
+    writer.lines(METHODS);
+    writer.line("");
+    writer.lines(READ);
+    writer.depth += 1;
+    match tape.eof {
+        EofPolicy::Unchanged => {
+            writer.line("if read == 1 {");
+            writer.line("    self.tape[self.ptr] = byte[0];");
+            writer.line("}");
+        }
+        EofPolicy::Zero => {
+            writer.line("self.tape[self.ptr] = if read == 1 { byte[0] } else { 0 };")
+        }
+        EofPolicy::MinusOne => {
+            writer.line("self.tape[self.ptr] = if read == 1 { byte[0] } else { 255 };")
+        }
+    }
+    writer.depth -= 1;
+    writer.line("}");
+    writer.line("");
+    writer.lines(DEBUG);
+    writer.line("");
+    writer.lines(EXIT);
+    writer.depth -= 1;
+    writer.line("}");
+    writer.line("");
+
+    for (index, body) in program.procedures.iter().enumerate() {
+        writer.line(format!("fn procedure_{}(m: &mut Machine) {{", index + 1));
+        emit_block(&mut writer, body);
+        writer.line("}");
+        writer.line("");
+    }
+
+    writer.line("fn main() {");
+    writer.depth += 1;
+    writer.line("let m = &mut Machine {");
+    writer.line("    tape: vec![0; TAPE_LEN],");
+    writer.line("    ptr: 0,");
+    if uses_storage {
+        writer.line("    storage: 0,");
+    }
+    if uses_procedures {
+        writer.line("    procedures: [None; 256],");
+    }
+    writer.line("    input: std::io::stdin().lock(),");
+    writer.line("    output: std::io::stdout().lock(),");
+    writer.line("};");
+    writer.depth -= 1;
+    if !emit_block(&mut writer, &program.main) {
+        writer.line("    m.output.flush().unwrap();");
+    }
+    writer.line("}");
+
+    writer.output
+}
+
+/// Returns whether the block always ends the program.
+fn emit_block(writer: &mut Writer, nodes: &[Node]) -> bool {
+    writer.depth += 1;
+    for node in nodes {
+        match node {
+            Node::Loop(body) => {
+                writer.line("while m.cell() != 0 {");
+                emit_block(writer, body);
+                writer.line("}");
+            }
+            Node::Define(number) => {
+                writer.line(format!(
+                    "m.procedures[m.cell() as usize] = Some(procedure_{number});"
+                ));
+            }
+            Node::Op(op) => emit_op(writer, op),
+        }
+        if ends_program(node) {
+            // Anything after `@` is unreachable
+            writer.depth -= 1;
+            return true;
+        }
+    }
+    writer.depth -= 1;
+    false
+}
+
+fn emit_op(writer: &mut Writer, op: &OptimizedIr) {
+    match op {
+        OptimizedIr::Ir(Ir::Data(amount)) => match cell_delta(*amount) {
+            0 => {}
+            delta => writer.line(format!("m.add({delta});")),
+        },
+        OptimizedIr::Ir(Ir::Move(amount)) => match *amount {
+            0 => {}
+            amount => writer.line(format!("m.shift({amount});")),
+        },
+        OptimizedIr::Ir(Ir::IO(true)) => writer.line("m.read();"),
+        OptimizedIr::Ir(Ir::IO(false)) => writer.line("m.write();"),
+        OptimizedIr::Ir(Ir::Extended(op)) => writer.line(match op {
+            ExtendedOp::End => "m.exit();",
+            ExtendedOp::Store => "m.storage = m.cell();",
+            ExtendedOp::Load => "m.tape[m.ptr] = m.storage;",
+            ExtendedOp::ShiftRight => "m.tape[m.ptr] >>= 1;",
+            ExtendedOp::ShiftLeft => "m.tape[m.ptr] <<= 1;",
+            ExtendedOp::Not => "m.tape[m.ptr] = !m.cell();",
+            ExtendedOp::Xor => "m.tape[m.ptr] ^= m.storage;",
+            ExtendedOp::And => "m.tape[m.ptr] &= m.storage;",
+            ExtendedOp::Or => "m.tape[m.ptr] |= m.storage;",
+        }),
+        OptimizedIr::Ir(Ir::Call) => {
+            writer.line("if let Some(procedure) = m.procedures[m.cell() as usize] {");
+            writer.line("    procedure(m);");
+            writer.line("}");
+        }
+        OptimizedIr::Ir(Ir::Debug) => writer.line("m.debug();"),
+        OptimizedIr::ResetToZero => writer.line("m.zero();"),
+        OptimizedIr::AddAndZero(target) => writer.line(format!("m.add_and_zero({target});")),
+        OptimizedIr::Ir(Ir::Loop(..) | Ir::Procedure(..)) => {
+            unreachable!("Loops and procedures are nodes of their own")
+        }
+    }
+}
@@ -0,0 +1,405 @@
+// WebAssembly text backend. I/O goes through either WASI (`fd_read`,
+// `fd_write`) or three plain host imports, see `WasmImports`. The tape sits at
+// the start of linear memory, followed by a small scratch area for I/O, the
+// procedure table and the `#` dump buffer. Procedures are functions called
+// through a table; `@` inside one sets `$halted` so every caller returns as
+// well.
+
+use crate::{
+    optimizer::OptimizedIr,
+    parser::{ExtendedOp, Ir},
+    tape::{EofPolicy, TapeConfig},
+};
+
+use super::{cell_delta, Node, Program, WasmImports, Writer};
+
+const PAGE_SIZE: usize = 65536;
+const DEBUG_TEXT: &str = "# pointer , cells ..:";
+
+/// Addresses of everything that follows the tape.
+struct Layout {
+    iovec: usize,
+    /// Bytes transferred by the last `fd_read` or `fd_write`
+    count: usize,
+    /// Byte read by `,`
+    scratch: usize,
+    procedures: usize,
+    debug_text: usize,
+    debug_buffer: usize,
+    end: usize,
+}
+
+impl Layout {
+    fn new(tape_len: usize) -> Self {
+        let iovec = tape_len.next_multiple_of(8);
+        let procedures = iovec + 16;
+        let debug_text = procedures + 256 * 4;
+        let debug_buffer = debug_text + DEBUG_TEXT.len().next_multiple_of(8);
+        Layout {
+            iovec,
+            count: iovec + 8,
+            scratch: iovec + 12,
+            procedures,
+            debug_text,
+            debug_buffer,
+            // Header plus 17 cells of 5 bytes each
+            end: debug_buffer + 256,
+        }
+    }
+}
+
+pub(super) fn emit(program: &Program, tape: TapeConfig, imports: WasmImports) -> String {
+    let layout = Layout::new(tape.len);
+    let uses_end = program.uses(|op| matches!(op, OptimizedIr::Ir(Ir::Extended(ExtendedOp::End))));
+    let mut writer = Writer::default();
+
+    writer.line(";; Generated by cranefuck");
+    writer.line("(module");
+    writer.depth += 1;
+    let uses_debug = program.uses(|op| matches!(op, OptimizedIr::Ir(Ir::Debug)));
+    match imports {
+        WasmImports::Wasi => {
+            writer.line("(import \"wasi_snapshot_preview1\" \"fd_read\"");
+            writer.line("    (func $fd_read (param i32 i32 i32 i32) (result i32)))");
+            writer.line("(import \"wasi_snapshot_preview1\" \"fd_write\"");
+            writer.line("    (func $fd_write (param i32 i32 i32 i32) (result i32)))");
+        }
+        WasmImports::Host => {
+            writer.line("(import \"env\" \"input\" (func $input (result i32)))");
+            writer.line("(import \"env\" \"output\" (func $output (param i32)))");
+            if uses_debug {
+                writer.line("(import \"env\" \"debug\" (func $host_debug (param i32)))");
+            }
+        }
+    }
+    writer.line("");
+    writer.line(format!(
+        "(memory (export \"memory\") {})",
+        layout.end.div_ceil(PAGE_SIZE)
+    ));
+    writer.line("(global $ptr (mut i32) (i32.const 0))");
+    writer.line("(global $storage (mut i32) (i32.const 0))");
+    writer.line("(global $halted (mut i32) (i32.const 0))");
+    if !program.procedures.is_empty() {
+        let names = (1..=program.procedures.len())
+            .map(|number| format!("$procedure_{number}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        writer.line("(type $procedure (func))");
+        // Slot 0 stays empty so a zero entry in the procedure table means undefined
+        writer.line(format!("(table {} funcref)", program.procedures.len() + 1));
+        writer.line(format!("(elem (i32.const 1) func {names})"));
+    }
+    writer.line("");
+
+    writer.lines(&format!(
+        "\
+(func $wrap (param $index i32) (result i32)
+    (local.set $index (i32.rem_s (local.get $index) (i32.const {len})))
+    (select
+        (i32.add (local.get $index) (i32.const {len}))
+        (local.get $index)
+        (i32.lt_s (local.get $index) (i32.const 0))))
+",
+        len = tape.len,
+    ));
+    writer.line("");
+    let eof = match tape.eof {
+        EofPolicy::Unchanged => "(return)",
+        EofPolicy::Zero => "(i32.store8 (global.get $ptr) (i32.const 0))",
+        EofPolicy::MinusOne => "(i32.store8 (global.get $ptr) (i32.const 255))",
+    };
+    match imports {
+        WasmImports::Wasi => writer.lines(&format!(
+            "\
+(func $write
+    (i32.store (i32.const {iovec}) (global.get $ptr))
+    (i32.store (i32.const {iovec_len}) (i32.const 1))
+    (drop (call $fd_write (i32.const 1) (i32.const {iovec}) (i32.const 1) (i32.const {count}))))
+
+(func $read
+    (i32.store (i32.const {iovec}) (i32.const {scratch}))
+    (i32.store (i32.const {iovec_len}) (i32.const 1))
+    (if (i32.or
+            (call $fd_read (i32.const 0) (i32.const {iovec}) (i32.const 1) (i32.const {count}))
+            (i32.eqz (i32.load (i32.const {count}))))
+        (then {eof})
+        (else (i32.store8 (global.get $ptr) (i32.load8_u (i32.const {scratch}))))))
+",
+            iovec = layout.iovec,
+            iovec_len = layout.iovec + 4,
+            count = layout.count,
+            scratch = layout.scratch,
+        )),
+        WasmImports::Host => writer.lines(&format!(
+            "\
+(func $write
+    (call $output (i32.load8_u (global.get $ptr))))
+
+(func $read
+    (local $byte i32)
+    (local.set $byte (call $input))
+    (if (i32.lt_s (local.get $byte) (i32.const 0))
+        (then {eof})
+        (else (i32.store8 (global.get $ptr) (local.get $byte)))))
+"
+        )),
+    }
+    if uses_debug {
+        writer.line("");
+        match imports {
+            WasmImports::Wasi => emit_debug(&mut writer, &layout, tape.len),
+            WasmImports::Host => {
+                writer.line("(func $debug");
+                writer.line("    (call $host_debug (global.get $ptr)))");
+            }
+        }
+    }
+
+    for (index, body) in program.procedures.iter().enumerate() {
+        writer.line("");
+        writer.line(format!("(func $procedure_{}", index + 1));
+        emit_body(&mut writer, body, &layout, uses_end);
+    }
+
+    writer.line("");
+    writer.line("(func $main (export \"_start\")");
+    emit_body(&mut writer, &program.main, &layout, uses_end);
+    writer.depth -= 1;
+    writer.line(")");
+
+    writer.output
+}
+
+fn emit_body(writer: &mut Writer, nodes: &[Node], layout: &Layout, uses_end: bool) {
+    writer.depth += 1;
+    writer.line("(local $target i32)");
+    writer.line("(local $procedure i32)");
+    let mut emitter = Emitter {
+        writer,
+        layout,
+        uses_end,
+        loops: 0,
+    };
+    emitter.emit_block(nodes);
+    // Close the function
+    let last = emitter.writer.output.pop();
+    debug_assert_eq!(last, Some('\n'));
+    emitter.writer.output.push_str(")\n");
+    emitter.writer.depth -= 1;
+}
+
+struct Emitter<'a> {
+    writer: &'a mut Writer,
+    layout: &'a Layout,
+    uses_end: bool,
+    /// Loops emitted so far in this function, for unique labels
+    loops: usize,
+}
+
+impl Emitter<'_> {
+    fn emit_block(&mut self, nodes: &[Node]) {
+        for node in nodes {
+            match node {
+                Node::Loop(body) => {
+                    self.loops += 1;
+                    let label = self.loops;
+                    self.writer.line(format!("(block $break_{label}"));
+                    self.writer.line(format!("    (loop $continue_{label}"));
+                    self.writer.depth += 2;
+                    self.writer.line(format!(
+                        "(br_if $break_{label} (i32.eqz (i32.load8_u (global.get $ptr))))"
+                    ));
+                    self.emit_block(body);
+                    self.writer.line(format!("(br $continue_{label})))"));
+                    self.writer.depth -= 2;
+                }
+                Node::Define(number) => self.writer.line(format!(
+                    "(i32.store {} (i32.const {number}))",
+                    self.procedure_slot()
+                )),
+                Node::Op(op) => self.emit_op(op),
+            }
+        }
+    }
+
+    fn emit_op(&mut self, op: &OptimizedIr) {
+        let cell = "(i32.load8_u (global.get $ptr))";
+        let store = |value: &str| format!("(i32.store8 (global.get $ptr) {value})");
+
+        match op {
+            OptimizedIr::Ir(Ir::Data(amount)) => match cell_delta(*amount) {
+                0 => {}
+                delta => self
+                    .writer
+                    .line(store(&format!("(i32.add {cell} (i32.const {delta}))"))),
+            },
+            OptimizedIr::Ir(Ir::Move(amount)) => match *amount {
+                0 => {}
+                amount => self.writer.line(format!(
+                    "(global.set $ptr (call $wrap (i32.add (global.get $ptr) (i32.const {amount}))))"
+                )),
+            },
+            OptimizedIr::Ir(Ir::IO(true)) => self.writer.line("(call $read)"),
+            OptimizedIr::Ir(Ir::IO(false)) => self.writer.line("(call $write)"),
+            OptimizedIr::Ir(Ir::Extended(ExtendedOp::End)) => {
+                self.writer.line("(global.set $halted (i32.const 1))");
+                self.writer.line("(return)");
+            }
+            OptimizedIr::Ir(Ir::Extended(ExtendedOp::Store)) => {
+                self.writer.line(format!("(global.set $storage {cell})"))
+            }
+            OptimizedIr::Ir(Ir::Extended(op)) => {
+                let value = match op {
+                    ExtendedOp::Load => "(global.get $storage)".to_string(),
+                    ExtendedOp::ShiftRight => format!("(i32.shr_u {cell} (i32.const 1))"),
+                    ExtendedOp::ShiftLeft => format!("(i32.shl {cell} (i32.const 1))"),
+                    ExtendedOp::Not => format!("(i32.xor {cell} (i32.const 255))"),
+                    ExtendedOp::Xor => format!("(i32.xor {cell} (global.get $storage))"),
+                    ExtendedOp::And => format!("(i32.and {cell} (global.get $storage))"),
+                    ExtendedOp::Or => format!("(i32.or {cell} (global.get $storage))"),
+                    ExtendedOp::End | ExtendedOp::Store => unreachable!(),
+                };
+                self.writer.line(store(&value));
+            }
+            OptimizedIr::Ir(Ir::Call) => {
+                self.writer.line(format!(
+                    "(local.set $procedure (i32.load {}))",
+                    self.procedure_slot()
+                ));
+                self.writer.line("(if (local.get $procedure)");
+                self.writer.line(
+                    "    (then (call_indirect (type $procedure) (local.get $procedure))))",
+                );
+                if self.uses_end {
+                    self.writer
+                        .line("(if (global.get $halted) (then (return)))");
+                }
+            }
+            OptimizedIr::Ir(Ir::Debug) => self.writer.line("(call $debug)"),
+            OptimizedIr::ResetToZero => self.writer.line(store("(i32.const 0)")),
+            OptimizedIr::AddAndZero(target) => {
+                self.writer.line(format!(
+                    "(local.set $target (call $wrap (i32.add (global.get $ptr) (i32.const {target}))))"
+                ));
+                self.writer.line(format!(
+                    "(i32.store8 (local.get $target) (i32.add (i32.load8_u (local.get $target)) {cell}))"
+                ));
+                self.writer.line(store("(i32.const 0)"));
+            }
+            OptimizedIr::Ir(Ir::Loop(..) | Ir::Procedure(..)) => {
+                unreachable!("Loops and procedures are nodes of their own")
+            }
+        }
+    }
+
+    /// Address of the procedure table entry for the current cell.
+    fn procedure_slot(&self) -> String {
+        format!(
+            "(i32.add (i32.const {}) (i32.shl (i32.load8_u (global.get $ptr)) (i32.const 2)))",
+            self.layout.procedures
+        )
+    }
+}
+
+/// `$debug` prints the same tape window as the interpreter's `#` to stderr.
+/// `$number` writes a decimal right-aligned to `$width` and `$text` copies
+/// part of the constant text, both returning the next free address.
+fn emit_debug(writer: &mut Writer, layout: &Layout, tape_len: usize) {
+    writer.lines(&format!(
+        "\
+(data (i32.const {text}) \"{DEBUG_TEXT}\")
+
+(func $text (param $at i32) (param $offset i32) (param $len i32) (result i32)
+    (memory.copy
+        (local.get $at)
+        (i32.add (i32.const {text}) (local.get $offset))
+        (local.get $len))
+    (i32.add (local.get $at) (local.get $len)))
+
+(func $number (param $at i32) (param $value i32) (param $width i32) (result i32)
+    (local $digits i32)
+    (local $rest i32)
+    (local $end i32)
+    (local.set $digits (i32.const 1))
+    (local.set $rest (local.get $value))
+    (block $counted
+        (loop $count
+            (br_if $counted (i32.lt_u (local.get $rest) (i32.const 10)))
+            (local.set $rest (i32.div_u (local.get $rest) (i32.const 10)))
+            (local.set $digits (i32.add (local.get $digits) (i32.const 1)))
+            (br $count)))
+    (block $padded
+        (loop $pad
+            (br_if $padded (i32.le_s (local.get $width) (local.get $digits)))
+            (i32.store8 (local.get $at) (i32.const 32))
+            (local.set $at (i32.add (local.get $at) (i32.const 1)))
+            (local.set $width (i32.sub (local.get $width) (i32.const 1)))
+            (br $pad)))
+    (local.set $end (i32.add (local.get $at) (local.get $digits)))
+    (local.set $at (local.get $end))
+    (loop $digit
+        (local.set $at (i32.sub (local.get $at) (i32.const 1)))
+        (i32.store8
+            (local.get $at)
+            (i32.add (i32.const 48) (i32.rem_u (local.get $value) (i32.const 10))))
+        (local.set $value (i32.div_u (local.get $value) (i32.const 10)))
+        (br_if $digit (local.get $value)))
+    (local.get $end))
+
+(func $debug
+    (local $at i32)
+    (local $start i32)
+    (local $end i32)
+    (local $index i32)
+    (local.set $start
+        (select
+            (i32.const 0)
+            (i32.sub (global.get $ptr) (i32.const 8))
+            (i32.lt_u (global.get $ptr) (i32.const 8))))
+    (local.set $end
+        (select
+            (i32.add (global.get $ptr) (i32.const 9))
+            (i32.const {tape_len})
+            (i32.lt_u (i32.add (global.get $ptr) (i32.const 9)) (i32.const {tape_len}))))
+    (local.set $at (call $text (i32.const {buffer}) (i32.const 0) (i32.const 10)))
+    (local.set $at (call $number (local.get $at) (global.get $ptr) (i32.const 0)))
+    (local.set $at (call $text (local.get $at) (i32.const 10) (i32.const 8)))
+    (local.set $at (call $number (local.get $at) (local.get $start) (i32.const 0)))
+    (local.set $at (call $text (local.get $at) (i32.const 18) (i32.const 2)))
+    (local.set $at (call $number (local.get $at) (local.get $end) (i32.const 0)))
+    (local.set $at (call $text (local.get $at) (i32.const 20) (i32.const 1)))
+    (local.set $index (local.get $start))
+    (block $done
+        (loop $cell
+            (br_if $done (i32.ge_u (local.get $index) (local.get $end)))
+            (i32.store8
+                (local.get $at)
+                (select (i32.const 91) (i32.const 32)
+                    (i32.eq (local.get $index) (global.get $ptr))))
+            (local.set $at
+                (call $number
+                    (i32.add (local.get $at) (i32.const 1))
+                    (i32.load8_u (local.get $index))
+                    (i32.const 3)))
+            (i32.store8
+                (local.get $at)
+                (select (i32.const 93) (i32.const 32)
+                    (i32.eq (local.get $index) (global.get $ptr))))
+            (local.set $at (i32.add (local.get $at) (i32.const 1)))
+            (local.set $index (i32.add (local.get $index) (i32.const 1)))
+            (br $cell)))
+    (i32.store8 (local.get $at) (i32.const 10))
+    (i32.store (i32.const {iovec}) (i32.const {buffer}))
+    (i32.store
+        (i32.const {iovec_len})
+        (i32.sub (i32.add (local.get $at) (i32.const 1)) (i32.const {buffer})))
+    (drop (call $fd_write (i32.const 2) (i32.const {iovec}) (i32.const 1) (i32.const {count}))))
+",
+        text = layout.debug_text,
+        buffer = layout.debug_buffer,
+        iovec = layout.iovec,
+        iovec_len = layout.iovec + 4,
+        count = layout.count,
+    ));
+}
@@ -0,0 +1,465 @@
+// Bytecode VM, a faster interpreter tier for hosts where the JIT is not
+// allowed. `OptimizedIr` is lowered to fixed-width `Op`s, one per IR op, with
+// jump targets resolved ahead of time and moves reduced modulo the tape length
+// so wrapping the pointer is a single compare. Scan loops and common pairs of
+// ops are then fused into superinstructions, and a side table maps every op
+// back to the IR index it started from.
+
+use crate::{
+    cancel::CancellationToken,
+    interpreter::{dump_tape, RuntimeError},
+    io::{Io, NullIo, StdIo},
+    optimizer::OptimizedIr,
+    parser::{ExtendedOp, Ir, IrLoopType},
+    snapshot::{PendingIo, Snapshot, SnapshotError},
+    tape::{InvalidTapeLength, TapeConfig},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Add(u8),
+    /// Move right by this many cells, already reduced modulo the tape length
+    Move(u32),
+    Zero,
+    /// Add the current cell to the cell this many cells to the right, then clear it
+    AddAndZero(u32),
+    /// `[`: jump to the op after the matching `]` when the cell is zero
+    JumpIfZero(u32),
+    /// `]`: jump to the op after the matching `[` when the cell is not zero
+    JumpIfNonZero(u32),
+    Input,
+    Output,
+    Extended(ExtendedOp),
+    /// Define a procedure starting at the next op, then jump past its body
+    Define(u32),
+    Return,
+    Call,
+    Debug,
+    /// `Add` followed by `Move`
+    AddMove(u8, u32),
+    /// `Move` followed by `Add`
+    MoveAdd(u32, u8),
+    /// `Move` followed by `AddAndZero`
+    MoveAddAndZero(u32, u32),
+    /// `Move` followed by `[`
+    MoveJumpIfZero(u32, u32),
+    /// `Move` followed by `]`
+    MoveJumpIfNonZero(u32, u32),
+    /// A loop that only moves, `[>>>>]`, leaving the pointer on the first zero cell
+    Scan(u32),
+    /// Appended after the last op
+    Halt,
+}
+
+#[derive(Debug, Clone)]
+pub struct Bytecode {
+    ops: Vec<Op>,
+    /// IR index of the first IR op behind each op
+    ir_indices: Vec<usize>,
+    tape: TapeConfig,
+}
+
+pub fn compile(
+    ir_ops: impl AsRef<[OptimizedIr]>,
+    tape: TapeConfig,
+) -> Result<Bytecode, InvalidTapeLength> {
+    tape.validate()?;
+    let ir_ops = ir_ops.as_ref();
+    let wrap = |amount: isize| amount.rem_euclid(tape.len as isize) as u32;
+
+    let ops = ir_ops
+        .iter()
+        .map(|op| match op {
+            OptimizedIr::Ir(Ir::Data(amount)) => Op::Add(*amount as u8),
+            OptimizedIr::Ir(Ir::Move(amount)) => Op::Move(wrap(*amount)),
+            OptimizedIr::Ir(Ir::IO(true)) => Op::Input,
+            OptimizedIr::Ir(Ir::IO(false)) => Op::Output,
+            OptimizedIr::Ir(Ir::Loop(IrLoopType::Start, end)) => Op::JumpIfZero(*end as u32 + 1),
+            OptimizedIr::Ir(Ir::Loop(IrLoopType::End, start)) => {
+                Op::JumpIfNonZero(*start as u32 + 1)
+            }
+            OptimizedIr::Ir(Ir::Extended(op)) => Op::Extended(*op),
+            OptimizedIr::Ir(Ir::Procedure(IrLoopType::Start, end)) => Op::Define(*end as u32 + 1),
+            OptimizedIr::Ir(Ir::Procedure(IrLoopType::End, _)) => Op::Return,
+            OptimizedIr::Ir(Ir::Call) => Op::Call,
+            OptimizedIr::Ir(Ir::Debug) => Op::Debug,
+            OptimizedIr::ResetToZero => Op::Zero,
+            OptimizedIr::AddAndZero(target) => Op::AddAndZero(wrap(*target)),
+        })
+        .collect::<Vec<_>>();
+
+    let (ops, ir_indices) = fuse(ops);
+    Ok(Bytecode {
+        ops,
+        ir_indices,
+        tape,
+    })
+}
+
+/// Fuses runs of ops and remaps jump targets. Only the op after a `[`, `]`,
+/// call or procedure end is ever jumped to, and that op never continues a run
+/// except inside a scan loop, whose own `]` is the only jump into it.
+fn fuse(ops: Vec<Op>) -> (Vec<Op>, Vec<usize>) {
+    let mut fused = Vec::with_capacity(ops.len() + 1);
+    let mut ir_indices = Vec::with_capacity(ops.len() + 1);
+    // Fused index of every unfused index, plus one past the end
+    let mut remap = vec![0; ops.len() + 1];
+
+    let mut index = 0;
+    while index < ops.len() {
+        remap[index] = fused.len();
+        ir_indices.push(index);
+        if let Some(offset) = scan_at(&ops, index) {
+            fused.push(Op::Scan(offset));
+            remap[index + 1] = fused.len() - 1;
+            remap[index + 2] = fused.len() - 1;
+            index += 3;
+            continue;
+        }
+        let pair = match (ops[index], ops.get(index + 1)) {
+            (Op::Add(amount), Some(&Op::Move(offset))) => Some(Op::AddMove(amount, offset)),
+            (Op::Move(offset), Some(&Op::Add(amount))) => Some(Op::MoveAdd(offset, amount)),
+            (Op::Move(offset), Some(&Op::AddAndZero(target))) => {
+                Some(Op::MoveAddAndZero(offset, target))
+            }
+            // Leave scan loops whole, they are worth more than the pair
+            (Op::Move(offset), Some(&Op::JumpIfZero(target)))
+                if scan_at(&ops, index + 1).is_none() =>
+            {
+                Some(Op::MoveJumpIfZero(offset, target))
+            }
+            (Op::Move(offset), Some(&Op::JumpIfNonZero(target))) => {
+                Some(Op::MoveJumpIfNonZero(offset, target))
+            }
+            _ => None,
+        };
+        match pair {
+            Some(op) => {
+                fused.push(op);
+                remap[index + 1] = fused.len() - 1;
+                index += 2;
+            }
+            None => {
+                fused.push(ops[index]);
+                index += 1;
+            }
+        }
+    }
+    remap[ops.len()] = fused.len();
+    fused.push(Op::Halt);
+    ir_indices.push(ops.len());
+
+    for op in &mut fused {
+        match op {
+            Op::JumpIfZero(target)
+            | Op::JumpIfNonZero(target)
+            | Op::Define(target)
+            | Op::MoveJumpIfZero(_, target)
+            | Op::MoveJumpIfNonZero(_, target) => *target = remap[*target as usize] as u32,
+            _ => {}
+        }
+    }
+
+    (fused, ir_indices)
+}
+
+/// Returns the move of the scan loop starting at `index`, if there is one.
+fn scan_at(ops: &[Op], index: usize) -> Option<u32> {
+    match ops.get(index..index + 3)? {
+        [Op::JumpIfZero(_), Op::Move(offset), Op::JumpIfNonZero(_)] => Some(*offset),
+        _ => None,
+    }
+}
+
+/// Compiles and runs `ir_ops`, returning the final value of the current cell
+/// like `interpreter::interpret`.
+pub fn run(
+    ir_ops: impl AsRef<[OptimizedIr]>,
+    tape: TapeConfig,
+    ignore_io: bool,
+) -> Result<u8, RuntimeError> {
+    compile(ir_ops, tape)?.run(ignore_io)
+}
+
+impl Bytecode {
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    /// IR index the op at `pc` was compiled from.
+    pub fn ir_index(&self, pc: usize) -> usize {
+        self.ir_indices[pc]
+    }
+
+    /// Runs against the terminal, or with `NullIo` when `ignore_io` is set.
+    pub fn run(&self, ignore_io: bool) -> Result<u8, RuntimeError> {
+        let cancel = CancellationToken::new();
+        if ignore_io {
+            self.run_with(&mut NullIo, &cancel)
+        } else {
+            self.run_with(&mut StdIo::default(), &cancel)
+        }
+    }
+
+    /// Runs until the program ends or `cancel` is cancelled, which is checked
+    /// whenever a loop jumps back.
+    pub fn run_with(
+        &self,
+        io: &mut dyn Io,
+        cancel: &CancellationToken,
+    ) -> Result<u8, RuntimeError> {
+        self.resume_with(Snapshot::new(self.tape), io, cancel)
+    }
+
+    /// Like `run_with`, but continues from `snapshot`.
+    pub fn resume_with(
+        &self,
+        snapshot: Snapshot,
+        io: &mut dyn Io,
+        cancel: &CancellationToken,
+    ) -> Result<u8, RuntimeError> {
+        snapshot.validate_tape(self.tape)?;
+        let ops = &self.ops[..];
+        let len = self.tape.len;
+        let mut memory = snapshot.tape;
+        let mut ptr = snapshot.pointer;
+        let mut pc = self.resume_pc(snapshot.ir_index, memory[ptr])?;
+        let mut procedures = [None; 256];
+        for (id, procedure) in snapshot.procedures {
+            // Procedures start after their `Define`
+            let pc = self.pc_at(procedure)?;
+            if !matches!(self.ops[pc], Op::Define(..)) {
+                return Err(SnapshotError::NotResumable(procedure).into());
+            }
+            procedures[id as usize] = Some(pc + 1);
+        }
+        let mut state = RareState {
+            io: &mut PendingIo::new(&snapshot.pending_input, io),
+            storage: snapshot.storage,
+            procedures,
+            call_stack: snapshot
+                .call_stack
+                .into_iter()
+                .map(|index| self.pc_at(index))
+                .collect::<Result<_, _>>()?,
+        };
+        // Cells are accessed through a raw pointer so the hot loop keeps the
+        // tape base in a register instead of reborrowing the vector
+        let tape = memory.as_mut_ptr();
+
+        // SAFETY: every jump target is at most the index of the final `Halt`,
+        // which returns, so `pc` stays in bounds. `ptr` starts on the tape and
+        // every move wraps it below `len`, so every cell access is in bounds.
+        unsafe {
+            loop {
+                let op = *ops.get_unchecked(pc);
+                pc += 1;
+                let cell = tape.add(ptr);
+                match op {
+                    Op::Add(amount) => *cell = (*cell).wrapping_add(amount),
+                    Op::Move(offset) => ptr = wrap(ptr, offset, len),
+                    Op::AddMove(amount, offset) => {
+                        *cell = (*cell).wrapping_add(amount);
+                        ptr = wrap(ptr, offset, len);
+                    }
+                    Op::MoveAdd(offset, amount) => {
+                        ptr = wrap(ptr, offset, len);
+                        let cell = tape.add(ptr);
+                        *cell = (*cell).wrapping_add(amount);
+                    }
+                    Op::MoveJumpIfZero(offset, target) => {
+                        ptr = wrap(ptr, offset, len);
+                        if *tape.add(ptr) == 0 {
+                            pc = target as usize;
+                        }
+                    }
+                    Op::MoveJumpIfNonZero(offset, target) => {
+                        ptr = wrap(ptr, offset, len);
+                        if *tape.add(ptr) != 0 {
+                            if cancel.is_cancelled() {
+                                // Stop at the `]`, which comes after the move
+                                let memory = std::slice::from_raw_parts(tape, len);
+                                let ir_index = self.ir_index(pc - 1) + 1;
+                                return Err(self.cancelled(ir_index, memory, ptr, &mut state));
+                            }
+                            pc = target as usize;
+                        }
+                    }
+                    Op::Zero => *cell = 0,
+                    Op::Scan(offset) => {
+                        while *tape.add(ptr) != 0 {
+                            ptr = wrap(ptr, offset, len);
+                        }
+                    }
+                    Op::AddAndZero(offset) => add_and_zero(tape, ptr, offset, len),
+                    Op::MoveAddAndZero(offset, target) => {
+                        ptr = wrap(ptr, offset, len);
+                        add_and_zero(tape, ptr, target, len);
+                    }
+                    Op::JumpIfZero(target) => {
+                        if *cell == 0 {
+                            pc = target as usize;
+                        }
+                    }
+                    Op::JumpIfNonZero(target) => {
+                        if *cell != 0 {
+                            if cancel.is_cancelled() {
+                                let memory = std::slice::from_raw_parts(tape, len);
+                                let ir_index = self.ir_index(pc - 1);
+                                return Err(self.cancelled(ir_index, memory, ptr, &mut state));
+                            }
+                            pc = target as usize;
+                        }
+                    }
+                    Op::Halt => return Ok(*cell),
+                    _ => {
+                        let memory = std::slice::from_raw_parts_mut(tape, len);
+                        if let Some(value) = self.rare_op(op, memory, ptr, &mut pc, &mut state)? {
+                            return Ok(value);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// The op starting at `ir_index`.
+    fn pc_at(&self, ir_index: usize) -> Result<usize, SnapshotError> {
+        self.ir_indices
+            .binary_search(&ir_index)
+            .map_err(|_| SnapshotError::NotResumable(ir_index))
+    }
+
+    /// Where to continue a snapshot taken before `ir_index`, where the current
+    /// cell is `cell`.
+    fn resume_pc(&self, ir_index: usize, cell: u8) -> Result<usize, SnapshotError> {
+        if let Ok(pc) = self.pc_at(ir_index) {
+            return Ok(pc);
+        }
+        // A `]` fused with the move before it: only take the jump
+        match ir_index.checked_sub(1).map(|index| self.pc_at(index)) {
+            Some(Ok(pc)) => match self.ops[pc] {
+                Op::MoveJumpIfNonZero(_, target) if cell != 0 => Ok(target as usize),
+                Op::MoveJumpIfNonZero(..) => Ok(pc + 1),
+                _ => Err(SnapshotError::NotResumable(ir_index)),
+            },
+            _ => Err(SnapshotError::NotResumable(ir_index)),
+        }
+    }
+
+    /// The error for stopping before `ir_index`, with a snapshot to resume from.
+    #[cold]
+    #[inline(never)]
+    fn cancelled(
+        &self,
+        ir_index: usize,
+        memory: &[u8],
+        ptr: usize,
+        state: &mut RareState,
+    ) -> RuntimeError {
+        RuntimeError::cancelled(Snapshot {
+            tape: memory.to_vec(),
+            pointer: ptr,
+            ir_index,
+            storage: state.storage,
+            procedures: state
+                .procedures
+                .iter()
+                .enumerate()
+                .filter_map(|(id, pc)| Some((id as u8, self.ir_index((*pc)? - 1))))
+                .collect(),
+            call_stack: state
+                .call_stack
+                .iter()
+                .map(|pc| self.ir_index(*pc))
+                .collect(),
+            pending_input: state.io.pending_input(),
+        })
+    }
+
+    /// Runs an op that does IO, touches storage or procedures. Kept out of
+    /// line so the hot loop above stays small. Returns the current cell if
+    /// the op ends the program.
+    #[cold]
+    #[inline(never)]
+    fn rare_op(
+        &self,
+        op: Op,
+        memory: &mut [u8],
+        ptr: usize,
+        pc: &mut usize,
+        state: &mut RareState,
+    ) -> Result<Option<u8>, RuntimeError> {
+        let cell = &mut memory[ptr];
+        match op {
+            Op::Input => {
+                *cell = match state.io.read()? {
+                    Some(value) => value,
+                    None => self.tape.eof.apply(*cell),
+                };
+            }
+            Op::Output => state.io.write(*cell)?,
+            Op::Extended(op) => match op {
+                ExtendedOp::End => return Ok(Some(*cell)),
+                ExtendedOp::Store => state.storage = *cell,
+                ExtendedOp::Load => *cell = state.storage,
+                ExtendedOp::ShiftRight => *cell >>= 1,
+                ExtendedOp::ShiftLeft => *cell <<= 1,
+                ExtendedOp::Not => *cell = !*cell,
+                ExtendedOp::Xor => *cell ^= state.storage,
+                ExtendedOp::And => *cell &= state.storage,
+                ExtendedOp::Or => *cell |= state.storage,
+            },
+            Op::Define(end) => {
+                state.procedures[*cell as usize] = Some(*pc);
+                *pc = end as usize;
+            }
+            Op::Return => {
+                if let Some(return_address) = state.call_stack.pop() {
+                    *pc = return_address;
+                }
+            }
+            Op::Call => {
+                if let Some(procedure) = state.procedures[*cell as usize] {
+                    state.call_stack.push(*pc);
+                    *pc = procedure;
+                }
+            }
+            Op::Debug => state.io.debug(&dump_tape(memory, ptr))?,
+            _ => unreachable!("{op:?} runs in the hot loop"),
+        }
+        Ok(None)
+    }
+}
+
+/// Machine state that only `Bytecode::rare_op` touches
+struct RareState<'a> {
+    io: &'a mut dyn Io,
+    storage: u8,
+    procedures: [Option<usize>; 256],
+    call_stack: Vec<usize>,
+}
+
+/// `ptr + offset` on a tape of `len` cells, where both are already below `len`.
+#[inline(always)]
+fn wrap(ptr: usize, offset: u32, len: usize) -> usize {
+    let ptr = ptr + offset as usize;
+    if ptr >= len {
+        ptr - len
+    } else {
+        ptr
+    }
+}
+
+/// Adds the cell at `ptr` to the cell `offset` to the right, then clears it.
+///
+/// # Safety
+///
+/// `tape` must point to `len` cells, and `ptr` and `offset` must be below `len`.
+#[inline(always)]
+unsafe fn add_and_zero(tape: *mut u8, ptr: usize, offset: u32, len: usize) {
+    let cell = tape.add(ptr);
+    let target = tape.add(wrap(ptr, offset, len));
+    *target = (*target).wrapping_add(*cell);
+    *cell = 0;
+}
@@ -0,0 +1,29 @@
+// Standalone WebAssembly modules. The text emitted by `transpile::wat` is
+// assembled into a binary, so the `.wat` and `.wasm` outputs never drift apart.
+
+use thiserror::Error;
+
+use crate::{
+    optimizer::OptimizedIr,
+    tape::TapeConfig,
+    transpile::{self, TranspileError, WasmImports},
+};
+
+#[derive(Error, Debug)]
+pub enum WasmError {
+    #[error(transparent)]
+    Transpile(#[from] TranspileError),
+    #[error("generated module failed to assemble")]
+    Assemble(#[from] wat::Error),
+}
+
+/// Compiles `ir_ops` into a module exporting its tape as `memory` and the
+/// program as `_start`.
+pub fn compile(
+    ir_ops: impl AsRef<[OptimizedIr]>,
+    tape: TapeConfig,
+    imports: WasmImports,
+) -> Result<Vec<u8>, WasmError> {
+    let text = transpile::wat(ir_ops, tape, imports)?;
+    Ok(wat::parse_str(text)?)
+}
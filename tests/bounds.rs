@@ -0,0 +1,68 @@
+use cranefuck::{
+    jit::bounds::PointerBounds,
+    optimizer::{self, OptimizedIr},
+    parser::{self, Dialect, Ir},
+};
+
+const TAPE_LEN: usize = 30000;
+
+fn analyze(source: &str, dialect: &Dialect) -> (Vec<OptimizedIr>, PointerBounds) {
+    let ir = parser::to_ir(parser::tokenize_dialect(source, dialect)).unwrap();
+    let ops = optimizer::noop_optimzer(&ir);
+    let bounds = PointerBounds::analyze(&ops, TAPE_LEN);
+    (ops, bounds)
+}
+
+/// Whether each `move` may wrap, in program order
+fn moves_wrap(source: &str, dialect: &Dialect) -> Vec<bool> {
+    let (ops, bounds) = analyze(source, dialect);
+    ops.iter()
+        .enumerate()
+        .filter(|(_, op)| matches!(op, OptimizedIr::Ir(Ir::Move(_))))
+        .map(|(index, _)| bounds.may_wrap(index))
+        .collect()
+}
+
+#[test]
+fn straight_line_moves_stay_on_the_tape() {
+    assert_eq!(moves_wrap(">>+<", &Dialect::Classic), [false, false]);
+    // Left of the first cell
+    assert_eq!(moves_wrap("<+>", &Dialect::Classic), [true, true]);
+
+    let (_, bounds) = analyze(">>+<", &Dialect::Classic);
+    assert_eq!(bounds.before(1), Some(2..=2));
+    assert_eq!(bounds.before(2), Some(2..=2));
+}
+
+#[test]
+fn balanced_loops_keep_their_bounds() {
+    // Nested loops that each come back to where they started
+    assert_eq!(
+        moves_wrap("+[>+[>+<-]<-]>", &Dialect::Classic),
+        [false, false, false, false, false]
+    );
+    let (_, bounds) = analyze("+[>+<-]", &Dialect::Classic);
+    assert_eq!(bounds.before(6), Some(0..=0));
+}
+
+#[test]
+fn unbalanced_loops_lose_their_bounds() {
+    // Everything in and after a scan could be anywhere
+    assert_eq!(
+        moves_wrap(">+[>]<+[>+<-]", &Dialect::Classic),
+        [false, true, true, true, true]
+    );
+    // Including any loop around it
+    assert_eq!(
+        moves_wrap("+[>+[>]<<-]", &Dialect::Classic),
+        [true, true, true]
+    );
+}
+
+#[test]
+fn procedures_run_anywhere() {
+    assert_eq!(
+        moves_wrap("(>+<)>:>", &Dialect::PBrain),
+        [true, true, false, true]
+    );
+}
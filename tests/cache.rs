@@ -0,0 +1,110 @@
+use std::{fs, path::PathBuf};
+
+use cranefuck::{
+    cache::{Cache, CacheEntry, CacheKey},
+    jit,
+    optimizer::optimize,
+    parser::{to_ir, tokenize},
+    tape::{EofPolicy, TapeConfig},
+};
+
+const HELLO: &str = include_str!("../examples/hello.bf");
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("cranefuck-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn key(source: &str, optimize: bool, tape: TapeConfig) -> CacheKey {
+    CacheKey::new(
+        source,
+        "Classic",
+        optimize,
+        tape,
+        &jit::target_description(),
+    )
+}
+
+#[test]
+fn entries_round_trip() {
+    let dir = scratch_dir("round-trip");
+    let cache = Cache::new(&dir);
+    let tape = TapeConfig::default();
+    let ir = optimize(to_ir(tokenize(HELLO)).unwrap());
+    let entry = CacheEntry {
+        code: Some(jit::compile(&ir, tape).unwrap()),
+        ir,
+    };
+
+    let key = key(HELLO, true, tape);
+    assert_eq!(cache.load(&key), None);
+    cache.store(&key, &entry).unwrap();
+    assert_eq!(cache.load(&key), Some(entry));
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn keys_cover_every_setting() {
+    let tape = TapeConfig::default();
+    let keys = [
+        key(HELLO, true, tape),
+        key("+[-]", true, tape),
+        key(HELLO, false, tape),
+        key(HELLO, true, TapeConfig { len: 16, ..tape }),
+        key(
+            HELLO,
+            true,
+            TapeConfig {
+                eof: EofPolicy::Zero,
+                ..tape
+            },
+        ),
+        CacheKey::new(HELLO, "Extended", true, tape, &jit::target_description()),
+        CacheKey::new(HELLO, "Classic", true, tape, "some other machine"),
+    ];
+    for (index, key) in keys.iter().enumerate() {
+        assert!(!keys[index + 1..].contains(key), "{key} is not unique");
+    }
+    assert_eq!(keys[0], key(HELLO, true, tape));
+}
+
+#[test]
+fn corrupt_entries_are_misses() {
+    let dir = scratch_dir("corrupt");
+    let cache = Cache::new(&dir);
+    let key = key(HELLO, true, TapeConfig::default());
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join(format!("{key}.json")), "{\"ir\": \"add").unwrap();
+    assert_eq!(cache.load(&key), None);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn entries_of_colliding_keys_are_misses() {
+    let dir = scratch_dir("collision");
+    let cache = Cache::new(&dir);
+    let tape = TapeConfig::default();
+    let stored = key("+[-]", true, tape);
+    cache
+        .store(
+            &stored,
+            &CacheEntry {
+                ir: optimize(to_ir(tokenize("+[-]")).unwrap()),
+                code: None,
+            },
+        )
+        .unwrap();
+    // As if the hash of HELLO's key were the same
+    let key = key(HELLO, true, tape);
+    fs::rename(
+        dir.join(format!("{stored}.json")),
+        dir.join(format!("{key}.json")),
+    )
+    .unwrap();
+    assert_eq!(cache.load(&key), None);
+
+    fs::remove_dir_all(dir).unwrap();
+}
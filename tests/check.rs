@@ -0,0 +1,136 @@
+use cranefuck::{
+    check::{self, Diagnostic},
+    parser::{self, Dialect},
+};
+use serde_json::Value;
+
+fn diagnostics(source: &str, dialect: &Dialect) -> Vec<Diagnostic> {
+    let tokens = parser::tokenize_dialect_with_spans(source, dialect);
+    let ir = parser::to_ir(
+        tokens
+            .iter()
+            .map(|(token, _)| token.clone())
+            .collect::<Vec<_>>(),
+    )
+    .unwrap();
+    check::check(&ir, &tokens)
+}
+
+/// Checks that `source` gets exactly the diagnostics `expected`, as the lint
+/// and where it points to, in both the human and the JSON output.
+fn assert_lints(source: &str, dialect: &Dialect, expected: &[(&str, usize, usize)]) {
+    let diagnostics = diagnostics(source, dialect);
+
+    let human = check::render(&diagnostics, source, "test.b");
+    let rendered = human
+        .lines()
+        .filter_map(|line| line.strip_prefix("warning["))
+        .map(|line| line.split(']').next().unwrap())
+        .zip(
+            human
+                .lines()
+                .filter_map(|line| line.split_once("--> test.b:")),
+        )
+        .map(|(lint, (_, location))| {
+            let (line, column) = location.split_once(':').unwrap();
+            (lint, line.parse().unwrap(), column.parse().unwrap())
+        })
+        .collect::<Vec<(&str, usize, usize)>>();
+    assert_eq!(rendered, expected, "{source}\n{human}");
+
+    let json: Value = serde_json::from_str(&check::to_json(&diagnostics)).unwrap();
+    let json = json
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|diagnostic| {
+            let span = &diagnostic["span"];
+            (
+                diagnostic["lint"].as_str().unwrap(),
+                span["line"].as_u64().unwrap() as usize,
+                span["column"].as_u64().unwrap() as usize,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(json, expected, "{source}");
+}
+
+#[test]
+fn unreachable_loop() {
+    // The first cell starts out zero, and is zero again after a loop
+    assert_lints("[-]+.", &Dialect::Classic, &[("unreachable-loop", 1, 1)]);
+    assert_lints(
+        ",[.,]\n[>]",
+        &Dialect::Classic,
+        &[("unreachable-loop", 2, 1)],
+    );
+}
+
+#[test]
+fn infinite_loop() {
+    assert_lints("+[]", &Dialect::Classic, &[("infinite-loop", 1, 2)]);
+    // Only once entered, as input may be zero
+    assert_lints(",[>+<]>.", &Dialect::Classic, &[("infinite-loop", 1, 2)]);
+}
+
+#[test]
+fn left_edge() {
+    assert_lints("+\n<+.", &Dialect::Classic, &[("left-edge", 2, 1)]);
+}
+
+#[test]
+fn cancelling_pair() {
+    assert_lints(
+        "+-+.>><.",
+        &Dialect::Classic,
+        &[("cancelling-pair", 1, 1), ("cancelling-pair", 1, 5)],
+    );
+}
+
+#[test]
+fn unreachable_code() {
+    assert_lints(
+        "+[].",
+        &Dialect::Classic,
+        &[("infinite-loop", 1, 2), ("unreachable-code", 1, 4)],
+    );
+    assert_lints("+.@.", &Dialect::Extended, &[("unreachable-code", 1, 4)]);
+}
+
+#[test]
+fn human_output() {
+    let source = "++\n  <-+\n";
+    assert_eq!(
+        check::render(&diagnostics(source, &Dialect::Classic), source, "test.b"),
+        "\
+warning[left-edge]: pointer moves left of the first cell and wraps around to the end of the tape
+ --> test.b:2:3
+  |
+2 |   <-+
+  |   ^
+
+warning[cancelling-pair]: `+` and `-` cancel each other out
+ --> test.b:2:4
+  |
+2 |   <-+
+  |    ^^
+
+"
+    );
+}
+
+#[test]
+fn correct_programs_have_no_diagnostics() {
+    let programs = [
+        include_str!("../examples/hello.bf"),
+        include_str!("../examples/complex-hello.bf"),
+        include_str!("programs/bsort.b"),
+        include_str!("programs/rot13.b"),
+        include_str!("programs/wc.b"),
+    ];
+    for source in programs {
+        assert_lints(source, &Dialect::Classic, &[]);
+    }
+    // Procedures may run on any cell, so their loops are not judged
+    assert_lints("+(>[-]<):", &Dialect::PBrain, &[]);
+}
@@ -0,0 +1,297 @@
+// Runs the `cranefuck` binary itself, for behaviour that lives in the CLI such
+// as how the terminal is read and written.
+
+use std::{
+    ffi::OsStr,
+    io::{Read, Write},
+    path::PathBuf,
+    process::{Command, Output, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+    time::Duration,
+};
+
+const MODES: [&str; 4] = ["interpreter", "vm", "tiered", "jit"];
+
+/// Writes `source` to a file of its own under the target directory, as tests
+/// run in parallel.
+fn source_file(source: &str) -> PathBuf {
+    static FILES: AtomicUsize = AtomicUsize::new(0);
+    let file = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!(
+        "cli-{}-{}.b",
+        std::process::id(),
+        FILES.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&file, source).unwrap();
+    file
+}
+
+/// Runs the binary with `args`, piping `stdin` into it.
+fn cranefuck(args: &[&OsStr], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_cranefuck"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Programs given their input on the command line exit without reading it
+    match child.stdin.take().unwrap().write_all(stdin) {
+        Err(error) if error.kind() == std::io::ErrorKind::BrokenPipe => {}
+        result => result.unwrap(),
+    }
+    child.wait_with_output().unwrap()
+}
+
+/// Runs `source` in every mode with `args`, feeding it `input`.
+fn run_all(source: &str, args: &[&str], input: &[u8]) -> Vec<(&'static str, Output)> {
+    let file = source_file(source);
+    let outputs = MODES
+        .iter()
+        .map(|mode| {
+            let mut all_args = vec!["--file".as_ref(), file.as_os_str()];
+            all_args.extend(["--mode", mode, "--no-cache"].map(OsStr::new));
+            all_args.extend(args.iter().map(OsStr::new));
+            (*mode, cranefuck(&all_args, input))
+        })
+        .collect();
+    std::fs::remove_file(file).unwrap();
+    outputs
+}
+
+/// Like `run_all`, for runs that succeed, returning what each printed.
+fn run(source: &str, args: &[&str], input: &[u8]) -> Vec<Vec<u8>> {
+    run_all(source, args, input)
+        .into_iter()
+        .map(|(mode, output)| {
+            assert!(output.status.success(), "{mode}: {output:?}");
+            output.stdout
+        })
+        .collect()
+}
+
+#[test]
+fn newlines_are_raw_by_default() {
+    for output in run(",[.,]", &["--eof", "zero"], b"a\r\nb\n") {
+        assert_eq!(output, b"a\r\nb\n");
+    }
+}
+
+#[test]
+fn newlines_can_be_translated() {
+    for output in run(
+        ",[.,]",
+        &["--eof", "zero", "--newlines", "crlf-to-lf"],
+        b"a\r\nb\n",
+    ) {
+        assert_eq!(output, b"a\nb\n");
+    }
+    for output in run(
+        ",[.,]",
+        &["--eof", "zero", "--newlines", "lf-to-crlf"],
+        b"a\nb\n",
+    ) {
+        assert_eq!(output, b"a\r\nb\r\n");
+    }
+}
+
+#[test]
+fn output_is_flushed_before_reading() {
+    // Prints a prompt, then echoes one byte of input
+    let source = "++++++++[>++++++++<-]>-.,.";
+    let file = source_file(source);
+
+    for buffering in ["unbuffered", "line", "full", "flush-before-read"] {
+        for mode in MODES {
+            let mut child = Command::new(env!("CARGO_BIN_EXE_cranefuck"))
+                .arg("--file")
+                .arg(&file)
+                .args(["--mode", mode, "--no-cache", "--buffering", buffering])
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap();
+            // The prompt must arrive while stdin is still open and empty
+            let mut stdout = child.stdout.take().unwrap();
+            let (sender, receiver) = mpsc::channel();
+            let reader = thread::spawn(move || {
+                let mut prompt = [0];
+                let _ = sender.send(stdout.read_exact(&mut prompt).map(|_| prompt[0]));
+                let mut rest = Vec::new();
+                stdout.read_to_end(&mut rest).unwrap();
+                rest
+            });
+            let prompt = receiver.recv_timeout(Duration::from_secs(30));
+            child.stdin.take().unwrap().write_all(b"!\n").unwrap();
+            let status = child.wait().unwrap();
+            assert!(status.success(), "{mode} {buffering}: {status}");
+            assert_eq!(reader.join().unwrap(), b"!", "{mode} {buffering}");
+            assert!(
+                matches!(prompt, Ok(Ok(b'?'))),
+                "{mode} {buffering}: {prompt:?}"
+            );
+        }
+    }
+    std::fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn bytes_pass_through_raw() {
+    let input = b"caf\xc3\xa9 \xff\xfe\n";
+    for output in run(",[.,]", &["--eof", "zero"], input) {
+        assert_eq!(output, input);
+    }
+}
+
+#[test]
+fn utf8_input_is_split_into_bytes() {
+    // Prints the second cell read, which is the second byte of `é`
+    for output in run(",,.", &["--encoding", "utf-8"], "éa".as_bytes()) {
+        assert_eq!(output, [0xa9]);
+    }
+    for (mode, output) in run_all(",[.,]", &["--encoding", "utf-8"], b"\xff\n") {
+        assert!(!output.status.success(), "{mode}: {output:?}");
+    }
+}
+
+#[test]
+fn code_points_fill_a_cell_each() {
+    for output in run(",,.", &["--encoding", "code-point"], "éa".as_bytes()) {
+        assert_eq!(output, b"a");
+    }
+    let text = "Grüße, señor\n";
+    for output in run(
+        ",[.,]",
+        &["--encoding", "code-point", "--eof", "zero"],
+        text.as_bytes(),
+    ) {
+        assert_eq!(String::from_utf8(output).unwrap(), text);
+    }
+    for (mode, output) in run_all(",.", &["--encoding", "code-point"], "€\n".as_bytes()) {
+        assert!(!output.status.success(), "{mode}: {output:?}");
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains("U+20AC"),
+            "{mode}: {output:?}"
+        );
+    }
+}
+
+/// Runs without `--file` in every mode and returns what each printed.
+fn run_args(args: &[&str], stdin: &[u8]) -> Vec<Vec<u8>> {
+    MODES
+        .iter()
+        .map(|mode| {
+            let mut all_args = args.iter().map(OsStr::new).collect::<Vec<_>>();
+            all_args.extend(["--mode", mode, "--eof", "zero"].map(OsStr::new));
+            let output = cranefuck(&all_args, stdin);
+            assert!(output.status.success(), "{mode}: {output:?}");
+            output.stdout
+        })
+        .collect()
+}
+
+#[test]
+fn input_can_follow_piped_source() {
+    for output in run_args(&[], b",[.,]!some input") {
+        assert_eq!(output, b"some input");
+    }
+    // Only the first `!` separates
+    for output in run_args(&[], b",[.,]!a!b") {
+        assert_eq!(output, b"a!b");
+    }
+}
+
+#[test]
+fn input_can_be_given_on_the_command_line() {
+    for output in run_args(&["--program", ",[.,]", "--input", "literal"], b"stdin") {
+        assert_eq!(output, b"literal");
+    }
+    let file = source_file("from a file\n");
+    let file = file.to_str().unwrap();
+    for output in run_args(&["--program", ",[.,]", "--input-file", file], b"stdin") {
+        assert_eq!(output, b"from a file\n");
+    }
+    // Takes precedence over input after the source
+    for output in run_args(&["--input", "literal"], b",[.,]!piped") {
+        assert_eq!(output, b"literal");
+    }
+    std::fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn bang_is_a_command_in_extended_brainfuck() {
+    // `!` loads the storage byte, here 65, instead of starting the input
+    let source = b"++++++++[>++++++++<-]>+$[-]!.";
+    for output in run_args(&["--dialect", "extended"], source) {
+        assert_eq!(output, b"A");
+    }
+}
+
+#[test]
+fn subcommands_take_the_file_either_way() {
+    let file = source_file("+[>+<-] loop");
+    let file = file.as_os_str();
+    let positional = cranefuck(&["fmt".as_ref(), file], b"");
+    let flag = cranefuck(&["fmt".as_ref(), "--file".as_ref(), file], b"");
+    assert!(positional.status.success(), "{positional:?}");
+    assert_eq!(flag.stdout, positional.stdout);
+    let both = cranefuck(&["minify".as_ref(), file, "-f".as_ref(), file], b"");
+    assert!(!both.status.success(), "{both:?}");
+    std::fs::remove_file(file).unwrap();
+}
+
+#[test]
+fn malformed_dialect_files_are_reported() {
+    let mapping = source_file("name = \"broken\"\n[commands]\n\"x\" = \"ex\"\n");
+    let output = cranefuck(
+        &[
+            "--program".as_ref(),
+            "+.".as_ref(),
+            "--dialect-file".as_ref(),
+            mapping.as_os_str(),
+        ],
+        b"",
+    );
+    assert!(!output.status.success(), "{output:?}");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("`x` is not a command"),
+        "{output:?}"
+    );
+    std::fs::remove_file(mapping).unwrap();
+}
+
+#[test]
+fn tape_sizes_out_of_range_are_rejected() {
+    for size in ["0", "4294967296", "lots"] {
+        let output = cranefuck(
+            &["--program", "+.", "--tape-size", size].map(OsStr::new),
+            b"",
+        );
+        assert!(!output.status.success(), "{output:?}");
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains("invalid tape size"),
+            "{output:?}"
+        );
+    }
+}
+
+#[test]
+fn classic_only_subcommands_reject_dialects() {
+    let source = "+$!.";
+    let file = source_file(source);
+    let file = file.as_os_str();
+    for args in [
+        ["minify", "--dialect", "extended", "--write"],
+        ["--dialect", "extended", "minify", "--write"],
+    ] {
+        let mut args = args.map(OsStr::new).to_vec();
+        args.push(file);
+        let output = cranefuck(&args, b"");
+        assert!(!output.status.success(), "{output:?}");
+    }
+    assert_eq!(std::fs::read_to_string(file).unwrap(), source);
+    std::fs::remove_file(file).unwrap();
+}
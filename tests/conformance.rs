@@ -0,0 +1,199 @@
+// Known programs with their expected output, run on every backend at every
+// optimization level.
+
+use cranefuck::{
+    io::BufferIo,
+    parser::Dialect,
+    tape::{EofPolicy, TapeConfig},
+    Backend, Error, OptLevel, Program,
+};
+
+const BACKENDS: [Backend; 4] = [
+    Backend::Interpreter,
+    Backend::Vm,
+    Backend::Tiered,
+    Backend::Jit,
+];
+
+fn assert_output(name: &str, program: &Program, input: &str, expected: &[u8]) {
+    for level in [OptLevel::None, OptLevel::Full] {
+        for backend in BACKENDS {
+            let mut io = BufferIo::new(input);
+            program
+                .clone()
+                .optimize(level)
+                .compile(backend)
+                .and_then(|executable| executable.run(&mut io))
+                .unwrap_or_else(|error| panic!("{name} on {backend:?} at {level:?}: {error}"));
+            assert_eq!(
+                String::from_utf8_lossy(&io.output),
+                String::from_utf8_lossy(expected),
+                "{name} on {backend:?} at {level:?}"
+            );
+        }
+    }
+}
+
+fn check(name: &str, source: &str, input: &str, expected: &[u8]) {
+    assert_output(name, &Program::parse(source).unwrap(), input, expected);
+}
+
+#[test]
+fn hello_world() {
+    check(
+        "hello",
+        include_str!("../examples/hello.bf"),
+        "",
+        b"Hello World!\n",
+    );
+    check(
+        "complex-hello",
+        include_str!("../examples/complex-hello.bf"),
+        "",
+        b"Hello World!\n",
+    );
+}
+
+#[test]
+fn rot13() {
+    check(
+        "rot13",
+        include_str!("programs/rot13.b"),
+        "Hello, World!\nThe Quick Brown Fox jumps over the lazy dog.\n",
+        b"Uryyb, Jbeyq!\nGur Dhvpx Oebja Sbk whzcf bire gur ynml qbt.\n",
+    );
+}
+
+#[test]
+fn squares() {
+    let expected = (0..=100)
+        .map(|n| format!("{}\n", n * n))
+        .collect::<String>();
+    check(
+        "squares",
+        include_str!("programs/squares.b"),
+        "",
+        expected.as_bytes(),
+    );
+}
+
+#[test]
+fn bsort() {
+    check(
+        "bsort",
+        include_str!("programs/bsort.b"),
+        "sort me please",
+        b"  aeeelmoprsst",
+    );
+}
+
+#[test]
+fn wc() {
+    check(
+        "wc",
+        include_str!("programs/wc.b"),
+        "hello world\nfoo  bar baz\n",
+        b"\t2\t5\t25\n",
+    );
+    check("wc", include_str!("programs/wc.b"), "", b"\t0\t0\t0\n");
+}
+
+#[test]
+fn eof_policies() {
+    for (eof, expected) in [
+        (EofPolicy::Zero, b"LB\nLB\n"),
+        (EofPolicy::MinusOne, b"LA\nLA\n"),
+        (EofPolicy::Unchanged, b"LK\nLK\n"),
+    ] {
+        let program = Program::parse(include_str!("programs/eof.b"))
+            .unwrap()
+            .tape(TapeConfig {
+                eof,
+                ..TapeConfig::default()
+            });
+        assert_output(&format!("eof {eof:?}"), &program, "\n", expected);
+    }
+}
+
+#[test]
+fn tape_has_30000_cells() {
+    check("tape", include_str!("programs/tape.b"), "", b"#\n");
+}
+
+#[test]
+fn pointer_wraps_around_a_short_tape() {
+    // Adds 65 onto the last cell from the first, then goes around the tape
+    // further than its length in both directions
+    let source = format!(
+        "++++++++[<++++++++>-]<+.{}+++{}.>>>>.",
+        ">".repeat(20),
+        "<".repeat(20)
+    );
+    let program = Program::parse(&source).unwrap().tape(TapeConfig {
+        len: 16,
+        ..TapeConfig::default()
+    });
+    assert_output("wraparound", &program, "", b"AA\x03");
+}
+
+#[test]
+fn cells_are_reused_around_io() {
+    // Reads, changes and prints the same two cells without leaving the block
+    check("reuse", ",>,<+.>+.<-->--..<.", "ab", b"bcaa`");
+}
+
+#[test]
+fn obscure_problems() {
+    check("obscure", include_str!("programs/obscure.b"), "", b"H\n");
+}
+
+#[test]
+fn unbalanced_brackets_are_rejected() {
+    for source in ["+++++[>+++++++>++<<-]>.>.[", "+++++[>+++++++>++<<-]>.>.]["] {
+        assert!(
+            matches!(Program::parse(source), Err(Error::Parse(_))),
+            "{source}"
+        );
+    }
+}
+
+#[test]
+fn deep_nesting() {
+    const DEPTH: usize = 1000;
+    // Enters every loop once and leaves through all of them at once
+    let entered = format!(
+        "+{}-{}++++++++[>++++++++<-]>+.",
+        "[".repeat(DEPTH),
+        "]".repeat(DEPTH)
+    );
+    check("entered", &entered, "", b"A");
+    // Skips the whole nest on the first `[`
+    let skipped = format!("{}{}+.", "[".repeat(DEPTH), "]".repeat(DEPTH));
+    check("skipped", &skipped, "", b"\x01");
+    // Loops inside one another, each running three times per iteration of the
+    // one around it
+    let counted = format!("{}+{}>>>>>.", "+++[>".repeat(5), "<-]".repeat(5));
+    check("counted", &counted, "", &[243]);
+}
+
+#[test]
+fn extended_type_one_ops() {
+    // Stores 'A', shifts it right and left, loads it back, then combines it
+    // with 255 and 3, and ends before the last `.`
+    let source = "++++++++[>++++++++<-]>+$}.{.!.>~&.>+++^.|.&.@+.";
+    let program = Program::parse_dialect(source, &Dialect::Extended).unwrap();
+    assert_output("extended", &program, "", b" @AABCA");
+}
+
+#[test]
+fn procedures_must_nest_within_loops() {
+    for source in ["+([)]:", "+[(])"] {
+        assert!(
+            matches!(
+                Program::parse_dialect(source, &Dialect::PBrain),
+                Err(Error::Parse(_))
+            ),
+            "{source}"
+        );
+    }
+}
@@ -0,0 +1,39 @@
+use cranefuck::parser;
+
+const PROGRAMS: [&str; 6] = [
+    include_str!("../examples/mandelbrot.bf"),
+    include_str!("../examples/complex-hello.bf"),
+    include_str!("programs/bsort.b"),
+    include_str!("programs/obscure.b"),
+    include_str!("programs/rot13.b"),
+    // Comments before, between and after loops, on lines of their own or not
+    "set up ++[>+\n\n<-]>.\n  [ skipped, not - a + loop ]   done\n<<>>+-",
+];
+
+#[test]
+fn formatting_is_idempotent() {
+    for source in PROGRAMS {
+        let formatted = parser::format(source);
+        assert_eq!(parser::format(&formatted), formatted, "{formatted}");
+    }
+}
+
+#[test]
+fn formatting_keeps_the_commands() {
+    for source in PROGRAMS {
+        let formatted = parser::format(source);
+        for cancel_pairs in [false, true] {
+            assert_eq!(
+                parser::minify(&formatted, cancel_pairs),
+                parser::minify(source, cancel_pairs)
+            );
+        }
+    }
+}
+
+#[test]
+fn minifying_cancels_pairs() {
+    let source = "a +-+ b <>> [-]";
+    assert_eq!(parser::minify(source, false), "+-+<>>[-]");
+    assert_eq!(parser::minify(source, true), "+>[-]");
+}
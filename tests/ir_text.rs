@@ -0,0 +1,92 @@
+use cranefuck::{
+    ir_text::{self, IrTextError},
+    optimizer::{self, OptimizedIr},
+    parser::{self, Dialect},
+};
+
+fn ir(source: &str, dialect: &Dialect) -> Vec<parser::Ir> {
+    parser::to_ir(parser::tokenize_dialect(source, dialect)).unwrap()
+}
+
+fn optimized(source: &str) -> String {
+    ir_text::print(optimizer::optimize(ir(source, &Dialect::Classic)))
+}
+
+fn assert_round_trip(ops: Vec<OptimizedIr>) {
+    let text = ir_text::print(&ops);
+    assert_eq!(ir_text::parse(&text).unwrap(), ops, "{text}");
+}
+
+#[test]
+fn printed_ir_parses_back() {
+    let programs = [
+        include_str!("../examples/mandelbrot.bf"),
+        include_str!("../examples/complex-hello.bf"),
+        include_str!("programs/bsort.b"),
+        include_str!("programs/wc.b"),
+    ];
+    for source in programs {
+        let ir = ir(source, &Dialect::Classic);
+        assert_round_trip(optimizer::noop_optimzer(&ir));
+        assert_round_trip(optimizer::optimize(&ir));
+    }
+    // Procedures and every Extended Type I op
+    assert_round_trip(optimizer::optimize(ir("+(>+<-[-]):#", &Dialect::PBrain)));
+    assert_round_trip(optimizer::optimize(ir(
+        "+$>!}{~^&|[-]<[->+<]@",
+        &Dialect::Extended,
+    )));
+}
+
+#[test]
+fn optimizer_snapshots() {
+    // A unit-step transfer becomes `addzero`, `[-]` becomes `zero`, and any
+    // other loop is left alone
+    assert_eq!(
+        optimized("++>+++[-<+>]<[>++<-]>[-]."),
+        "\
+add 2
+move 1
+add 3
+addzero -1
+move -1
+loop {
+    move 1
+    add 2
+    move -1
+    add -1
+}
+move 1
+zero
+out
+"
+    );
+    // Runs of the same command are folded, and a transfer to the right too
+    assert_eq!(
+        optimized(">>>--<[->>+<<],"),
+        "\
+move 3
+add -2
+move -1
+addzero +2
+in
+"
+    );
+}
+
+#[test]
+fn offsets_out_of_range_are_rejected() {
+    assert_eq!(
+        ir_text::parse("move -2147483647\naddzero +2147483647").unwrap(),
+        [
+            OptimizedIr::Ir(parser::Ir::Move(-2147483647)),
+            OptimizedIr::AddAndZero(2147483647)
+        ]
+    );
+    for text in ["move 9223372036854775807", "add 1\naddzero -2147483648"] {
+        assert!(matches!(
+            ir_text::parse(text),
+            Err(IrTextError::OperandOutOfRange { .. })
+        ));
+    }
+}
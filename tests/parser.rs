@@ -0,0 +1,90 @@
+use cranefuck::parser::{self, Dialect, ExtendedOp, Ir, IrError, IrLoopType, Token};
+
+fn ir(source: &str, dialect: &Dialect) -> Result<Vec<Ir>, IrError> {
+    parser::to_ir(parser::tokenize_dialect(source, dialect))
+}
+
+#[test]
+fn extended_type_one_ops() {
+    assert_eq!(
+        parser::tokenize_dialect("@$!}{~^&|#", &Dialect::Extended),
+        [
+            Token::End,
+            Token::Store,
+            Token::Load,
+            Token::ShiftRight,
+            Token::ShiftLeft,
+            Token::Not,
+            Token::Xor,
+            Token::And,
+            Token::Or,
+            Token::Debug,
+        ]
+    );
+    assert_eq!(
+        ir("+$>!}}{~^&|@", &Dialect::Extended).unwrap(),
+        [
+            Ir::Data(1),
+            Ir::Extended(ExtendedOp::Store),
+            Ir::Move(1),
+            Ir::Extended(ExtendedOp::Load),
+            Ir::Extended(ExtendedOp::ShiftRight),
+            Ir::Extended(ExtendedOp::ShiftRight),
+            Ir::Extended(ExtendedOp::ShiftLeft),
+            Ir::Extended(ExtendedOp::Not),
+            Ir::Extended(ExtendedOp::Xor),
+            Ir::Extended(ExtendedOp::And),
+            Ir::Extended(ExtendedOp::Or),
+            Ir::Extended(ExtendedOp::End),
+        ]
+    );
+    // Only commands in other dialects
+    assert_eq!(ir("@$!}{~^&|", &Dialect::Classic).unwrap(), []);
+    assert_eq!(ir("@$!}{~^&|", &Dialect::PBrain).unwrap(), []);
+}
+
+#[test]
+fn loops_and_procedures_nest() {
+    assert_eq!(
+        ir("([]):", &Dialect::PBrain).unwrap(),
+        [
+            Ir::Procedure(IrLoopType::Start, 3),
+            Ir::Loop(IrLoopType::Start, 2),
+            Ir::Loop(IrLoopType::End, 1),
+            Ir::Procedure(IrLoopType::End, 0),
+            Ir::Call,
+        ]
+    );
+}
+
+#[test]
+fn crossed_brackets_are_rejected() {
+    assert!(matches!(
+        ir("+([)]:", &Dialect::PBrain),
+        Err(IrError::CrossedBrackets { open: 2, close: 3 })
+    ));
+    assert!(matches!(
+        ir("+[(])", &Dialect::PBrain),
+        Err(IrError::CrossedBrackets { open: 2, close: 3 })
+    ));
+}
+
+#[test]
+fn unmatched_brackets_are_rejected() {
+    assert!(matches!(
+        ir("+[[]", &Dialect::Classic),
+        Err(IrError::UnmatchedLoop { index: 1 })
+    ));
+    assert!(matches!(
+        ir("[]]", &Dialect::Classic),
+        Err(IrError::UnmatchedLoop { index: 2 })
+    ));
+    assert!(matches!(
+        ir("[(", &Dialect::PBrain),
+        Err(IrError::UnmatchedLoop { index: 0 })
+    ));
+    assert!(matches!(
+        ir(")", &Dialect::PBrain),
+        Err(IrError::UnmatchedProcedure { index: 0 })
+    ));
+}
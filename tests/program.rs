@@ -0,0 +1,184 @@
+use std::{thread, time::Duration};
+
+use cranefuck::{
+    cancel::CancellationToken,
+    interpreter::RuntimeError,
+    io::{BufferIo, Io},
+    jit,
+    parser::Dialect,
+    run_batch,
+    tape::{EofPolicy, TapeConfig},
+    vm, Backend, Error, OptLevel, Program,
+};
+
+const HELLO: &str = include_str!("../examples/hello.bf");
+
+const BACKENDS: [Backend; 4] = [
+    Backend::Interpreter,
+    Backend::Vm,
+    Backend::Tiered,
+    Backend::Jit,
+];
+
+fn run(program: &Program, input: &str) -> Vec<Vec<u8>> {
+    let mut outputs = Vec::new();
+    for level in [OptLevel::None, OptLevel::Full] {
+        for backend in BACKENDS {
+            let mut io = BufferIo::new(input);
+            program
+                .clone()
+                .optimize(level)
+                .compile(backend)
+                .unwrap()
+                .run(&mut io)
+                .unwrap();
+            outputs.push(io.output);
+        }
+    }
+    outputs
+}
+
+#[test]
+fn every_backend_prints_hello_world() {
+    for output in run(&Program::parse(HELLO).unwrap(), "") {
+        assert_eq!(output, b"Hello World!\n");
+    }
+}
+
+#[test]
+fn every_backend_echoes_its_input() {
+    let tape = TapeConfig {
+        eof: EofPolicy::Zero,
+        ..TapeConfig::default()
+    };
+    let program = Program::parse(",[.,]").unwrap().tape(tape);
+    // Long enough for the tiered backend to compile the loop
+    let input = "the quick brown fox\n".repeat(100);
+    for output in run(&program, &input) {
+        assert_eq!(output, input.as_bytes());
+    }
+}
+
+#[test]
+fn dumps_go_to_the_io() {
+    let program = Program::parse_dialect("+>++#", &Dialect::Debug).unwrap();
+    for backend in BACKENDS {
+        let mut io = BufferIo::new("");
+        program.compile(backend).unwrap().run(&mut io).unwrap();
+        assert_eq!(io.dumps.len(), 1, "{backend:?}");
+        assert!(
+            io.dumps[0].contains("[  2]"),
+            "{backend:?}: {}",
+            io.dumps[0]
+        );
+    }
+}
+
+struct BrokenPipe;
+
+impl Io for BrokenPipe {
+    fn read(&mut self) -> std::io::Result<Option<u8>> {
+        Ok(None)
+    }
+
+    fn write(&mut self, _: u8) -> std::io::Result<()> {
+        Err(std::io::ErrorKind::BrokenPipe.into())
+    }
+}
+
+#[test]
+fn io_errors_stop_the_program() {
+    let program = Program::parse(HELLO).unwrap();
+    for backend in BACKENDS {
+        let result = program.compile(backend).unwrap().run(&mut BrokenPipe);
+        assert!(matches!(result, Err(Error::Runtime(_))), "{backend:?}");
+    }
+}
+
+#[test]
+fn errors_are_typed() {
+    assert!(matches!(Program::parse("[[]"), Err(Error::Parse(_))));
+    let empty = TapeConfig {
+        len: 0,
+        ..TapeConfig::default()
+    };
+    let program = Program::parse("+").unwrap().tape(empty);
+    for backend in BACKENDS {
+        assert!(matches!(program.compile(backend), Err(Error::Tape(_))));
+    }
+    assert!(vm::compile(program.optimized_ir(), empty).is_err());
+    assert!(jit::compile(program.optimized_ir(), empty).is_err());
+}
+
+#[test]
+fn batches_keep_their_io_apart() {
+    let tape = TapeConfig {
+        eof: EofPolicy::Zero,
+        ..TapeConfig::default()
+    };
+    let program = Program::parse(",[.,]").unwrap().tape(tape);
+    let executables = BACKENDS.map(|backend| program.compile(backend).unwrap());
+    let inputs = (0..32)
+        .map(|job| format!("job {job}\n").repeat(200))
+        .collect::<Vec<_>>();
+
+    let results = run_batch(
+        inputs
+            .iter()
+            .enumerate()
+            .map(|(job, input)| (&executables[job % BACKENDS.len()], BufferIo::new(input))),
+    );
+    assert_eq!(results.len(), inputs.len());
+    for ((io, result), input) in results.into_iter().zip(&inputs) {
+        result.unwrap();
+        assert_eq!(io.output, input.as_bytes());
+    }
+}
+
+#[test]
+fn threads_can_run_the_same_executable() {
+    let executable = Program::parse(HELLO)
+        .unwrap()
+        .compile(Backend::Jit)
+        .unwrap();
+    thread::scope(|scope| {
+        let runs = (0..8)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut io = BufferIo::new("");
+                    executable.run(&mut io).unwrap();
+                    io.output
+                })
+            })
+            .collect::<Vec<_>>();
+        for run in runs {
+            assert_eq!(run.join().unwrap(), b"Hello World!\n");
+        }
+    });
+}
+
+#[test]
+fn cancelled_runs_report_where_they_stopped() {
+    // Counts up in the second cell forever
+    let program = Program::parse("+[>+<]").unwrap();
+    for level in [OptLevel::None, OptLevel::Full] {
+        for backend in BACKENDS {
+            let executable = program.clone().optimize(level).compile(backend).unwrap();
+            let cancel = CancellationToken::new();
+            let result = thread::scope(|scope| {
+                let run =
+                    scope.spawn(|| executable.run_cancellable(&mut BufferIo::new(""), &cancel));
+                thread::sleep(Duration::from_millis(50));
+                cancel.cancel();
+                run.join().unwrap()
+            });
+            match result {
+                Err(Error::Runtime(RuntimeError::Cancelled { ir_index, tape, .. })) => {
+                    assert!(ir_index < program.optimized_ir().len(), "{backend:?}");
+                    assert!(tape.starts_with("# pointer 0,"), "{backend:?}: {tape}");
+                }
+                result => panic!("{backend:?} at {level:?}: {result:?}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,3 @@
+Daniel B Cristofani's EOF test: prints LB twice if EOF stores 0 and LA twice if it stores 255 and LK twice if it leaves the cell unchanged
+
+>,>+++++++++,>+++++++++++[<++++++<++++++<+>>>-]<<.>.<<-.>.>.<<.
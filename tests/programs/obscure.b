@@ -0,0 +1,3 @@
+Daniel B Cristofani's test of obscure problems: prints H
+
+[]++++++++++[>>+>+>++++++[<<+<+++>>>-]<<<<-]"A*$";?@![#>>+<<]>[>>]<<<<[>++<[-]]>.>.
@@ -0,0 +1,3 @@
+Daniel B Cristofani's tape test: prints a hash sign if the tape has at least 30000 cells
+
+++++[>++++++<-]>[>+++++>+++++++<<-]>>++++<[[>[[>>+<<-]<]>>>-]>-[>+>+<<-]>]+++++[>+++++++<<++>-]>.<<.
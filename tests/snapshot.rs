@@ -0,0 +1,187 @@
+use std::path::PathBuf;
+
+use cranefuck::{
+    cancel::CancellationToken,
+    interpreter::RuntimeError,
+    io::{BufferIo, Io},
+    parser::Dialect,
+    snapshot::{Snapshot, SnapshotError},
+    tape::{EofPolicy, TapeConfig},
+    Backend, Error, Executable, OptLevel, Program,
+};
+
+const BACKENDS: [Backend; 4] = [
+    Backend::Interpreter,
+    Backend::Vm,
+    Backend::Tiered,
+    Backend::Jit,
+];
+
+// Defines procedure 0, which prints and bumps the letter in cell 1 and goes
+// around a loop of its own twice, then calls it 26 times
+const ALPHABET: &str =
+    "(>.+>>+>+<[+>]<<<<<)>>++++++++[<++++++++>-]<+<>>++++++++++++++++++++++++++[<<:>>-]";
+
+/// Cancels its token once the program has written `after` bytes, and hands
+/// back the rest of its input as if it had been read ahead.
+struct CancelAfter {
+    io: BufferIo,
+    after: usize,
+    cancel: CancellationToken,
+}
+
+impl Io for CancelAfter {
+    fn read(&mut self) -> std::io::Result<Option<u8>> {
+        self.io.read()
+    }
+
+    fn write(&mut self, value: u8) -> std::io::Result<()> {
+        self.io.write(value)?;
+        if self.io.output.len() == self.after {
+            self.cancel.cancel();
+        }
+        Ok(())
+    }
+
+    fn pending_input(&mut self) -> Vec<u8> {
+        self.io.input.drain(..).collect()
+    }
+}
+
+fn scratch_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("cranefuck-{name}-{}.json", std::process::id()))
+}
+
+/// Cancels `executable` after `after` bytes of output and returns the output
+/// so far along with the snapshot, after a round trip through a file.
+fn stop(executable: &Executable, input: &str, after: usize) -> (Vec<u8>, Snapshot) {
+    let cancel = CancellationToken::new();
+    let mut io = CancelAfter {
+        io: BufferIo::new(input),
+        after,
+        cancel: cancel.clone(),
+    };
+    match executable.run_cancellable(&mut io, &cancel) {
+        Err(Error::Runtime(RuntimeError::Cancelled { snapshot, .. })) => {
+            let path = scratch_file("snapshot");
+            snapshot.save(&path).unwrap();
+            let loaded = Snapshot::load(&path).unwrap();
+            std::fs::remove_file(path).unwrap();
+            assert_eq!(loaded, *snapshot);
+            (io.io.output, loaded)
+        }
+        result => panic!("{result:?}"),
+    }
+}
+
+fn resume_everywhere(program: &Program, input: &str, after: usize, expected: &[u8]) {
+    for level in [OptLevel::None, OptLevel::Full] {
+        let program = program.clone().optimize(level);
+        let executables = BACKENDS.map(|backend| program.compile(backend).unwrap());
+        for (stopped_in, executable) in BACKENDS.iter().zip(&executables) {
+            let (mut output, snapshot) = stop(executable, input, after);
+            for (resumed_in, executable) in BACKENDS.iter().zip(&executables) {
+                let mut io = BufferIo::new("");
+                executable
+                    .resume(snapshot.clone(), &mut io, &CancellationToken::new())
+                    .unwrap_or_else(|error| {
+                        panic!("{stopped_in:?} to {resumed_in:?} at {level:?}: {error:?}")
+                    });
+                let mut combined = output.clone();
+                combined.extend(io.output);
+                assert_eq!(
+                    combined, expected,
+                    "{stopped_in:?} to {resumed_in:?} at {level:?}"
+                );
+            }
+            output.clear();
+        }
+    }
+}
+
+#[test]
+fn snapshots_resume_in_any_backend() {
+    let program = Program::parse_dialect(ALPHABET, &Dialect::PBrain).unwrap();
+    // Every backend notices inside the procedure's loop
+    for backend in BACKENDS {
+        let (_, snapshot) = stop(&program.compile(backend).unwrap(), "", 5);
+        assert_eq!(
+            snapshot.procedures.keys().collect::<Vec<_>>(),
+            [&0],
+            "{backend:?}"
+        );
+        assert_eq!(snapshot.call_stack.len(), 1, "{backend:?}");
+    }
+    resume_everywhere(&program, "", 5, b"ABCDEFGHIJKLMNOPQRSTUVWXYZ");
+}
+
+#[test]
+fn snapshots_keep_pending_input() {
+    let tape = TapeConfig {
+        eof: EofPolicy::Zero,
+        ..TapeConfig::default()
+    };
+    let program = Program::parse(",[.,]").unwrap().tape(tape);
+    let input = "the quick brown fox\n";
+    resume_everywhere(&program, input, 4, input.as_bytes());
+}
+
+#[test]
+fn mismatched_snapshots_are_rejected() {
+    let program = Program::parse("+[-]").unwrap();
+    let short = TapeConfig {
+        len: 16,
+        ..TapeConfig::default()
+    };
+    for backend in BACKENDS {
+        let executable = program.compile(backend).unwrap();
+        let result = executable.resume(
+            Snapshot::new(short),
+            &mut BufferIo::new(""),
+            &CancellationToken::new(),
+        );
+        assert!(
+            matches!(
+                result,
+                Err(Error::Runtime(RuntimeError::Snapshot(
+                    SnapshotError::TapeLength { .. }
+                )))
+            ),
+            "{backend:?}: {result:?}"
+        );
+    }
+}
+
+#[test]
+fn unreachable_snapshots_are_rejected_by_the_jit() {
+    // The loop always runs on cell 0, so its moves never wrap in compiled code
+    let program = Program::parse("+[>+<-]").unwrap().optimize(OptLevel::None);
+    let snapshot = Snapshot {
+        pointer: 100,
+        ir_index: 6,
+        ..Snapshot::new(TapeConfig::default())
+    };
+    let executable = program.compile(Backend::Jit).unwrap();
+    let result = executable.resume(
+        snapshot.clone(),
+        &mut BufferIo::new(""),
+        &CancellationToken::new(),
+    );
+    assert!(
+        matches!(
+            result,
+            Err(Error::Runtime(RuntimeError::Snapshot(
+                SnapshotError::Unreachable {
+                    ir_index: 6,
+                    pointer: 100
+                }
+            )))
+        ),
+        "{result:?}"
+    );
+    // Backends that always wrap run it anyway
+    let executable = program.compile(Backend::Vm).unwrap();
+    executable
+        .resume(snapshot, &mut BufferIo::new(""), &CancellationToken::new())
+        .unwrap();
+}
@@ -0,0 +1,30 @@
+use std::{fs, path::Path};
+
+fn sources(dir: &Path, files: &mut Vec<String>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            sources(&path, files);
+        } else if path.extension().is_some_and(|extension| extension == "rs") {
+            files.push(fs::read_to_string(path).unwrap());
+        }
+    }
+}
+
+#[test]
+fn no_unstable_features() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut files = Vec::new();
+    for dir in ["src", "tests", "benches"] {
+        sources(&root.join(dir), &mut files);
+    }
+    assert!(!files.is_empty());
+    for file in files {
+        assert!(!file.contains(concat!("#![", "feature(")));
+    }
+}
+
+#[test]
+fn msrv_is_declared() {
+    assert!(!env!("CARGO_PKG_RUST_VERSION").is_empty());
+}
@@ -0,0 +1,83 @@
+use cranefuck::{
+    cancel::CancellationToken,
+    interpreter,
+    io::BufferIo,
+    optimizer::{self, OptimizedIr},
+    parser,
+    snapshot::Snapshot,
+    stats::TapeStats,
+    tape::{EofPolicy, TapeConfig},
+};
+
+const TAPE: TapeConfig = TapeConfig {
+    len: 16,
+    eof: EofPolicy::Zero,
+};
+
+fn compile(source: &str) -> Vec<OptimizedIr> {
+    optimizer::optimize(parser::to_ir(parser::tokenize(source)).unwrap())
+}
+
+fn run<'a>(ops: &'a [OptimizedIr]) -> TapeStats<'a> {
+    let mut stats = TapeStats::new(ops, TAPE);
+    interpreter::observe_with(
+        ops,
+        TAPE,
+        Snapshot::new(TAPE),
+        &mut BufferIo::new("ab"),
+        &CancellationToken::new(),
+        &mut stats,
+    )
+    .unwrap();
+    stats
+}
+
+#[test]
+fn accesses_are_counted() {
+    // Reads two bytes into cells 1 and 2, then moves the first onto the second
+    let ops = compile(">,>,<[->+<]>.");
+    let stats = run(&ops);
+    assert_eq!(stats.extent(), Some(0..=2));
+    assert_eq!(stats.min_tape_len(), 3);
+    assert_eq!(stats.wraparounds(), 0);
+    // `,` writes, the loop becomes an `addzero` that reads and clears the
+    // first and adds it to the second, and `.` reads
+    assert_eq!(&stats.reads()[..3], [0, 1, 2]);
+    assert_eq!(&stats.writes()[..3], [0, 2, 2]);
+}
+
+#[test]
+fn wraparounds_are_counted() {
+    // Steps left off the start of the tape onto its last cell and back
+    let ops = compile("<+>");
+    let stats = run(&ops);
+    assert_eq!(stats.wraparounds(), 2);
+    assert_eq!(stats.extent(), Some(-1..=0));
+    assert_eq!(stats.min_tape_len(), 2);
+    assert_eq!(stats.writes()[TAPE.len - 1], 1);
+
+    let ops = compile("+[<+]");
+    let stats = run(&ops);
+    assert!(stats.wraparounds() > 0);
+    assert!(stats.min_tape_len() > TAPE.len);
+}
+
+#[test]
+fn heatmaps_cover_the_touched_cells() {
+    let ops = compile("+>>++[-]");
+    let stats = run(&ops);
+
+    let mut ascii = Vec::new();
+    stats.ascii_heatmap(&mut ascii).unwrap();
+    let ascii = String::from_utf8(ascii).unwrap();
+    // The cleared cell is the busiest and the skipped one untouched
+    assert_eq!(ascii.trim_end(), "       0 |# @|");
+
+    let mut ppm = Vec::new();
+    stats.ppm_heatmap(&mut ppm).unwrap();
+    let header = b"P6\n24 8\n255\n";
+    assert_eq!(&ppm[..header.len()], header);
+    assert_eq!(ppm.len(), header.len() + 24 * 8 * 3);
+    // The busiest cell is white
+    assert_eq!(&ppm[ppm.len() - 3..], [255, 255, 255]);
+}
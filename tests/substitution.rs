@@ -0,0 +1,80 @@
+use std::path::PathBuf;
+
+use cranefuck::{
+    parser::{self, Dialect},
+    substitution::{Substitution, SubstitutionError},
+};
+
+const HELLO: &str = include_str!("../examples/hello.bf");
+
+/// `source` spelled in the bundled language `mapping`, a phrase per line.
+fn translate(source: &str, mapping: &str) -> String {
+    let mapping: toml::Table = toml::from_str(mapping).unwrap();
+    let commands = mapping["commands"].as_table().unwrap();
+    parser::minify(source, false)
+        .chars()
+        .map(|command| commands[&command.to_string()].as_str().unwrap())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[test]
+fn bundled_languages_match_classic() {
+    let classic = parser::to_ir(parser::tokenize(HELLO)).unwrap();
+    let mappings = [
+        ("ook", include_str!("../dialects/ook.toml")),
+        ("blub", include_str!("../dialects/blub.toml")),
+        ("alphuck", include_str!("../dialects/alphuck.toml")),
+    ];
+    for (name, mapping) in mappings {
+        let source = translate(HELLO, mapping);
+        let dialect: Dialect = name.parse().unwrap();
+        let ir = parser::to_ir(parser::tokenize_dialect(&source, &dialect)).unwrap();
+        assert_eq!(ir, classic, "{name}");
+        assert_eq!(
+            Substitution::for_extension(name),
+            Substitution::builtin(name),
+            "{name}"
+        );
+    }
+}
+
+#[test]
+fn phrases_may_be_split_by_whitespace() {
+    let ook = Substitution::builtin("ook").unwrap();
+    assert_eq!(
+        ook.tokenize("Ook.\n  Ook. comment Ook! Ook. Ook.Ook?"),
+        parser::tokenize("+.>")
+    );
+}
+
+#[test]
+fn malformed_mappings_are_rejected() {
+    assert!(matches!(
+        Substitution::from_toml("name = \"broken\"\n[commands\n"),
+        Err(SubstitutionError::Toml(_))
+    ));
+    assert!(matches!(
+        Substitution::from_json("{\"name\": \"broken\", \"commands\": {\"+\": 1}}"),
+        Err(SubstitutionError::Json(_))
+    ));
+    assert!(matches!(
+        Substitution::from_toml("name = \"broken\"\n[commands]\n\"++\" = \"plus\"\n"),
+        Err(SubstitutionError::UnknownCommand { .. })
+    ));
+    assert!(matches!(
+        Substitution::from_toml("name = \"broken\"\n[commands]\n\"+\" = \" \"\n"),
+        Err(SubstitutionError::EmptyPhrase { .. })
+    ));
+
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("broken-dialect.json");
+    std::fs::write(&path, "name = \"toml in a json file\"").unwrap();
+    assert!(matches!(
+        Substitution::load(&path),
+        Err(SubstitutionError::Json(_))
+    ));
+    assert!(matches!(
+        Substitution::load(path.with_extension("missing")),
+        Err(SubstitutionError::IoError(_))
+    ));
+}
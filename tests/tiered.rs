@@ -0,0 +1,66 @@
+use cranefuck::{
+    cancel::CancellationToken,
+    interpreter,
+    io::BufferIo,
+    jit::tiered::{self, HOT_LOOP_THRESHOLD},
+    optimizer::{self, OptimizedIr},
+    parser::{self, Dialect},
+    tape::TapeConfig,
+};
+
+/// Iterations of a loop counting down from 251, which 1000 isn't a multiple of
+const ITERATIONS: usize = 251;
+
+fn compile(source: &str, dialect: &Dialect) -> Vec<OptimizedIr> {
+    optimizer::optimize(parser::to_ir(parser::tokenize_dialect(source, dialect)).unwrap())
+}
+
+/// What `ops` prints in the tiered backend, checked against the interpreter.
+fn run(ops: &[OptimizedIr]) -> Vec<u8> {
+    let tape = TapeConfig::default();
+    let cancel = CancellationToken::new();
+    let mut tiered_io = BufferIo::new("");
+    tiered::tiered_with(ops, tape, &mut tiered_io, &cancel).unwrap();
+    let mut interpreted_io = BufferIo::new("");
+    interpreter::interpret_with(ops, tape, &mut interpreted_io, &cancel).unwrap();
+    assert_eq!(tiered_io.output, interpreted_io.output);
+    tiered_io.output
+}
+
+#[test]
+fn loops_are_compiled_mid_run() {
+    // The inner loop counts down from 251 printing every value, `runs` times,
+    // between an 'a' and a 'b'. Its iterations add up across runs, so it gets
+    // hot in the middle of one.
+    let hot = HOT_LOOP_THRESHOLD as usize / ITERATIONS + 1;
+    for runs in [hot - 1, hot, hot + 3] {
+        let source = format!(
+            ">>{}.<<{}[>-----[.-]<-]>>+.",
+            "+".repeat(97),
+            "+".repeat(runs)
+        );
+        let mut expected = b"a".to_vec();
+        for _ in 0..runs {
+            expected.extend((1..=ITERATIONS as u8).rev());
+        }
+        expected.push(b'b');
+        assert_eq!(
+            run(&compile(&source, &Dialect::Classic)),
+            expected,
+            "{runs}"
+        );
+    }
+}
+
+#[test]
+fn loops_with_procedures_stay_interpreted() {
+    // Procedure 1 counts and prints the second cell. It is called from a loop
+    // that runs once per iteration of a loop that runs well past the threshold,
+    // and so has to stay interpreted too
+    let runs = HOT_LOOP_THRESHOLD as usize / ITERATIONS + 2;
+    let source = format!("+(>+.<)>>{}[>-----[>+[<<<<:>>>>-]<-]<-]", "+".repeat(runs));
+    let calls = runs * ITERATIONS;
+    assert!(calls > HOT_LOOP_THRESHOLD as usize);
+    let expected = (1..=calls).map(|call| call as u8).collect::<Vec<_>>();
+    assert_eq!(run(&compile(&source, &Dialect::PBrain)), expected);
+}
@@ -0,0 +1,133 @@
+use cranefuck::{
+    cancel::CancellationToken,
+    interpreter,
+    io::BufferIo,
+    optimizer::{self, OptimizedIr},
+    parser::{self, Token},
+    snapshot::Snapshot,
+    tape::TapeConfig,
+    trace::{self, TraceFilter, Tracer},
+};
+use serde_json::Value;
+
+// Moves 2 from the first cell to the second and prints it
+const SOURCE: &str = "++\n[->+<]\n>.";
+
+fn ir(optimize: bool) -> (Vec<OptimizedIr>, Vec<usize>) {
+    let tokens = parser::tokenize_with_spans(SOURCE);
+    let ir = parser::to_ir(
+        tokens
+            .iter()
+            .map(|(token, _)| token.clone())
+            .collect::<Vec<Token>>(),
+    )
+    .unwrap();
+    let (ops, origins) = if optimize {
+        optimizer::optimize_with_origins(&ir)
+    } else {
+        (optimizer::noop_optimzer(&ir), (0..ir.len()).collect())
+    };
+    (ops, trace::op_lines(&tokens, &origins))
+}
+
+fn run(optimize: bool, filter: TraceFilter) -> Vec<Value> {
+    let (ops, lines) = ir(optimize);
+    let mut output = Vec::new();
+    let mut tracer = Tracer::new(&mut output, &ops, filter).lines(lines);
+    let mut io = BufferIo::new("");
+    interpreter::observe_with(
+        &ops,
+        TapeConfig::default(),
+        Snapshot::new(TapeConfig::default()),
+        &mut io,
+        &CancellationToken::new(),
+        &mut tracer,
+    )
+    .unwrap();
+    assert_eq!(io.output, [2]);
+    String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn every_op_is_traced() {
+    let entries = run(false, TraceFilter::default());
+    // Two passes through the five ops of the loop body and its `]`
+    assert_eq!(entries.len(), 15);
+    for (step, entry) in entries.iter().enumerate() {
+        assert_eq!(entry["step"], step);
+    }
+    assert_eq!(
+        entries[0],
+        serde_json::json!({
+            "step": 0, "ip": 0, "op": "add 2", "line": 1, "pointer": 0, "before": 0, "after": 2,
+        })
+    );
+    let last = &entries[14];
+    assert_eq!((&last["op"], &last["line"]), (&"out".into(), &3.into()));
+    assert_eq!((&last["pointer"], &last["after"]), (&1.into(), &2.into()));
+}
+
+#[test]
+fn optimized_ops_keep_their_lines() {
+    let entries = run(true, TraceFilter::default());
+    let ops = entries
+        .iter()
+        .map(|entry| {
+            (
+                entry["op"].as_str().unwrap(),
+                entry["line"].as_u64().unwrap(),
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        ops,
+        [("add 2", 1), ("addzero +1", 2), ("move 1", 3), ("out", 3)]
+    );
+}
+
+#[test]
+fn filters_pick_ops() {
+    let by_line = run(
+        false,
+        TraceFilter {
+            lines: Some(3..=3),
+            ..TraceFilter::default()
+        },
+    );
+    assert_eq!(by_line.len(), 2);
+    assert_eq!(by_line[0]["step"], 13);
+
+    let by_op = run(
+        false,
+        TraceFilter {
+            ops: Some(6..=6),
+            ..TraceFilter::default()
+        },
+    );
+    let steps = by_op.iter().map(|entry| &entry["step"]).collect::<Vec<_>>();
+    assert_eq!(steps, [6, 12]);
+
+    let sampled = run(
+        false,
+        TraceFilter {
+            every: 4,
+            ..TraceFilter::default()
+        },
+    );
+    let steps = sampled
+        .iter()
+        .map(|entry| &entry["step"])
+        .collect::<Vec<_>>();
+    assert_eq!(steps, [0, 4, 8, 12]);
+}
+
+#[test]
+fn ranges_parse() {
+    assert_eq!(trace::parse_range("10-20").unwrap(), 10..=20);
+    assert_eq!(trace::parse_range("7").unwrap(), 7..=7);
+    assert!(trace::parse_range("ten").is_err());
+}
@@ -0,0 +1,44 @@
+use cranefuck::{
+    optimizer,
+    parser::{self, Dialect},
+    tape::{EofPolicy, TapeConfig},
+    transpile::{self, Target},
+};
+
+/// Reads a byte, moves it one cell right, counts it down there printing every
+/// value, then sets and clears a cell and dumps the tape.
+const SOURCE: &str = ",[->+<]>[.-]>+[-]#";
+
+const TAPE: TapeConfig = TapeConfig {
+    len: 16,
+    eof: EofPolicy::Zero,
+};
+
+fn transpile(target: Target) -> String {
+    let ir = parser::to_ir(parser::tokenize_dialect(SOURCE, &Dialect::Debug)).unwrap();
+    transpile::transpile(optimizer::optimize(&ir), target, TAPE).unwrap()
+}
+
+// The snapshots are the output of
+//   cranefuck transpile -t <target> --optimize --dialect debug --tape-size 16 --eof zero
+
+#[test]
+fn c_snapshot() {
+    assert_eq!(transpile(Target::C), include_str!("transpile/snapshot.c"));
+}
+
+#[test]
+fn rust_snapshot() {
+    assert_eq!(
+        transpile(Target::Rust),
+        include_str!("transpile/snapshot.rs")
+    );
+}
+
+#[test]
+fn wat_snapshot() {
+    assert_eq!(
+        transpile(Target::Wat),
+        include_str!("transpile/snapshot.wat")
+    );
+}
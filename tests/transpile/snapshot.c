@@ -0,0 +1,44 @@
+/* Generated by cranefuck */
+#include <stdio.h>
+
+#define TAPE_LEN 16L
+
+static unsigned char tape[TAPE_LEN];
+static long ptr = 0;
+
+static long wrap(long index) {
+    index %= TAPE_LEN;
+    return index < 0 ? index + TAPE_LEN : index;
+}
+
+static void input(void) {
+    int c = getchar();
+    tape[ptr] = c == EOF ? 0 : (unsigned char)c;
+}
+
+static void debug(void) {
+    long start = ptr < 8 ? 0 : ptr - 8;
+    long end = ptr + 9 < TAPE_LEN ? ptr + 9 : TAPE_LEN;
+    fflush(stdout);
+    fprintf(stderr, "# pointer %ld, cells %ld..%ld:", ptr, start, end);
+    for (long i = start; i < end; i++) {
+        fprintf(stderr, i == ptr ? "[%3d]" : " %3d ", tape[i]);
+    }
+    fputc('\n', stderr);
+}
+
+int main(void) {
+    input();
+    tape[wrap(ptr + 1)] += tape[ptr];
+    tape[ptr] = 0;
+    ptr = wrap(ptr + 1);
+    while (tape[ptr]) {
+        putchar(tape[ptr]);
+        tape[ptr] -= 1;
+    }
+    ptr = wrap(ptr + 1);
+    tape[ptr] += 1;
+    tape[ptr] = 0;
+    debug();
+    return 0;
+}
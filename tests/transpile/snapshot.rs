@@ -0,0 +1,93 @@
+// Generated by cranefuck
+#![allow(dead_code)]
+
+use std::io::{Read, Write};
+
+const TAPE_LEN: usize = 16;
+
+struct Machine {
+    tape: Vec<u8>,
+    ptr: usize,
+    input: std::io::StdinLock<'static>,
+    output: std::io::StdoutLock<'static>,
+}
+
+impl Machine {
+    fn cell(&self) -> u8 {
+        self.tape[self.ptr]
+    }
+
+    fn add(&mut self, amount: i8) {
+        self.tape[self.ptr] = self.tape[self.ptr].wrapping_add_signed(amount);
+    }
+
+    fn shift(&mut self, amount: isize) {
+        self.ptr = self.wrap(amount);
+    }
+
+    fn wrap(&self, offset: isize) -> usize {
+        (self.ptr as isize + offset).rem_euclid(TAPE_LEN as isize) as usize
+    }
+
+    fn zero(&mut self) {
+        self.tape[self.ptr] = 0;
+    }
+
+    fn add_and_zero(&mut self, offset: isize) {
+        let target = self.wrap(offset);
+        self.tape[target] = self.tape[target].wrapping_add(self.tape[self.ptr]);
+        self.tape[self.ptr] = 0;
+    }
+
+    fn write(&mut self) {
+        self.output.write_all(&[self.cell()]).unwrap();
+    }
+
+    fn read(&mut self) {
+        self.output.flush().unwrap();
+        let mut byte = [0];
+        let read = self.input.read(&mut byte).unwrap();
+        self.tape[self.ptr] = if read == 1 { byte[0] } else { 0 };
+    }
+
+    fn debug(&mut self) {
+        let start = self.ptr.saturating_sub(8);
+        let end = (self.ptr + 9).min(TAPE_LEN);
+        self.output.flush().unwrap();
+        eprint!("# pointer {}, cells {start}..{end}:", self.ptr);
+        for index in start..end {
+            if index == self.ptr {
+                eprint!("[{:3}]", self.tape[index]);
+            } else {
+                eprint!(" {:3} ", self.tape[index]);
+            }
+        }
+        eprintln!();
+    }
+
+    fn exit(&mut self) -> ! {
+        self.output.flush().unwrap();
+        std::process::exit(0)
+    }
+}
+
+fn main() {
+    let m = &mut Machine {
+        tape: vec![0; TAPE_LEN],
+        ptr: 0,
+        input: std::io::stdin().lock(),
+        output: std::io::stdout().lock(),
+    };
+    m.read();
+    m.add_and_zero(1);
+    m.shift(1);
+    while m.cell() != 0 {
+        m.write();
+        m.add(-1);
+    }
+    m.shift(1);
+    m.add(1);
+    m.zero();
+    m.debug();
+    m.output.flush().unwrap();
+}
@@ -0,0 +1,140 @@
+;; Generated by cranefuck
+(module
+    (import "wasi_snapshot_preview1" "fd_read"
+        (func $fd_read (param i32 i32 i32 i32) (result i32)))
+    (import "wasi_snapshot_preview1" "fd_write"
+        (func $fd_write (param i32 i32 i32 i32) (result i32)))
+
+    (memory (export "memory") 1)
+    (global $ptr (mut i32) (i32.const 0))
+    (global $storage (mut i32) (i32.const 0))
+    (global $halted (mut i32) (i32.const 0))
+
+    (func $wrap (param $index i32) (result i32)
+        (local.set $index (i32.rem_s (local.get $index) (i32.const 16)))
+        (select
+            (i32.add (local.get $index) (i32.const 16))
+            (local.get $index)
+            (i32.lt_s (local.get $index) (i32.const 0))))
+
+    (func $write
+        (i32.store (i32.const 16) (global.get $ptr))
+        (i32.store (i32.const 20) (i32.const 1))
+        (drop (call $fd_write (i32.const 1) (i32.const 16) (i32.const 1) (i32.const 24))))
+
+    (func $read
+        (i32.store (i32.const 16) (i32.const 28))
+        (i32.store (i32.const 20) (i32.const 1))
+        (if (i32.or
+                (call $fd_read (i32.const 0) (i32.const 16) (i32.const 1) (i32.const 24))
+                (i32.eqz (i32.load (i32.const 24))))
+            (then (i32.store8 (global.get $ptr) (i32.const 0)))
+            (else (i32.store8 (global.get $ptr) (i32.load8_u (i32.const 28))))))
+
+    (data (i32.const 1056) "# pointer , cells ..:")
+
+    (func $text (param $at i32) (param $offset i32) (param $len i32) (result i32)
+        (memory.copy
+            (local.get $at)
+            (i32.add (i32.const 1056) (local.get $offset))
+            (local.get $len))
+        (i32.add (local.get $at) (local.get $len)))
+
+    (func $number (param $at i32) (param $value i32) (param $width i32) (result i32)
+        (local $digits i32)
+        (local $rest i32)
+        (local $end i32)
+        (local.set $digits (i32.const 1))
+        (local.set $rest (local.get $value))
+        (block $counted
+            (loop $count
+                (br_if $counted (i32.lt_u (local.get $rest) (i32.const 10)))
+                (local.set $rest (i32.div_u (local.get $rest) (i32.const 10)))
+                (local.set $digits (i32.add (local.get $digits) (i32.const 1)))
+                (br $count)))
+        (block $padded
+            (loop $pad
+                (br_if $padded (i32.le_s (local.get $width) (local.get $digits)))
+                (i32.store8 (local.get $at) (i32.const 32))
+                (local.set $at (i32.add (local.get $at) (i32.const 1)))
+                (local.set $width (i32.sub (local.get $width) (i32.const 1)))
+                (br $pad)))
+        (local.set $end (i32.add (local.get $at) (local.get $digits)))
+        (local.set $at (local.get $end))
+        (loop $digit
+            (local.set $at (i32.sub (local.get $at) (i32.const 1)))
+            (i32.store8
+                (local.get $at)
+                (i32.add (i32.const 48) (i32.rem_u (local.get $value) (i32.const 10))))
+            (local.set $value (i32.div_u (local.get $value) (i32.const 10)))
+            (br_if $digit (local.get $value)))
+        (local.get $end))
+
+    (func $debug
+        (local $at i32)
+        (local $start i32)
+        (local $end i32)
+        (local $index i32)
+        (local.set $start
+            (select
+                (i32.const 0)
+                (i32.sub (global.get $ptr) (i32.const 8))
+                (i32.lt_u (global.get $ptr) (i32.const 8))))
+        (local.set $end
+            (select
+                (i32.add (global.get $ptr) (i32.const 9))
+                (i32.const 16)
+                (i32.lt_u (i32.add (global.get $ptr) (i32.const 9)) (i32.const 16))))
+        (local.set $at (call $text (i32.const 1080) (i32.const 0) (i32.const 10)))
+        (local.set $at (call $number (local.get $at) (global.get $ptr) (i32.const 0)))
+        (local.set $at (call $text (local.get $at) (i32.const 10) (i32.const 8)))
+        (local.set $at (call $number (local.get $at) (local.get $start) (i32.const 0)))
+        (local.set $at (call $text (local.get $at) (i32.const 18) (i32.const 2)))
+        (local.set $at (call $number (local.get $at) (local.get $end) (i32.const 0)))
+        (local.set $at (call $text (local.get $at) (i32.const 20) (i32.const 1)))
+        (local.set $index (local.get $start))
+        (block $done
+            (loop $cell
+                (br_if $done (i32.ge_u (local.get $index) (local.get $end)))
+                (i32.store8
+                    (local.get $at)
+                    (select (i32.const 91) (i32.const 32)
+                        (i32.eq (local.get $index) (global.get $ptr))))
+                (local.set $at
+                    (call $number
+                        (i32.add (local.get $at) (i32.const 1))
+                        (i32.load8_u (local.get $index))
+                        (i32.const 3)))
+                (i32.store8
+                    (local.get $at)
+                    (select (i32.const 93) (i32.const 32)
+                        (i32.eq (local.get $index) (global.get $ptr))))
+                (local.set $at (i32.add (local.get $at) (i32.const 1)))
+                (local.set $index (i32.add (local.get $index) (i32.const 1)))
+                (br $cell)))
+        (i32.store8 (local.get $at) (i32.const 10))
+        (i32.store (i32.const 16) (i32.const 1080))
+        (i32.store
+            (i32.const 20)
+            (i32.sub (i32.add (local.get $at) (i32.const 1)) (i32.const 1080)))
+        (drop (call $fd_write (i32.const 2) (i32.const 16) (i32.const 1) (i32.const 24))))
+
+    (func $main (export "_start")
+        (local $target i32)
+        (local $procedure i32)
+        (call $read)
+        (local.set $target (call $wrap (i32.add (global.get $ptr) (i32.const 1))))
+        (i32.store8 (local.get $target) (i32.add (i32.load8_u (local.get $target)) (i32.load8_u (global.get $ptr))))
+        (i32.store8 (global.get $ptr) (i32.const 0))
+        (global.set $ptr (call $wrap (i32.add (global.get $ptr) (i32.const 1))))
+        (block $break_1
+            (loop $continue_1
+                (br_if $break_1 (i32.eqz (i32.load8_u (global.get $ptr))))
+                (call $write)
+                (i32.store8 (global.get $ptr) (i32.add (i32.load8_u (global.get $ptr)) (i32.const -1)))
+                (br $continue_1)))
+        (global.set $ptr (call $wrap (i32.add (global.get $ptr) (i32.const 1))))
+        (i32.store8 (global.get $ptr) (i32.add (i32.load8_u (global.get $ptr)) (i32.const 1)))
+        (i32.store8 (global.get $ptr) (i32.const 0))
+        (call $debug))
+)
@@ -0,0 +1,111 @@
+use std::mem::discriminant;
+
+use cranefuck::{
+    cancel::CancellationToken,
+    interpreter::{self, RuntimeError},
+    io::{BufferIo, Io},
+    optimizer::{self, OptimizedIr},
+    parser::{self, Dialect},
+    tape::{EofPolicy, TapeConfig},
+    vm::{self, Op},
+};
+
+const TAPE: TapeConfig = TapeConfig {
+    len: 32,
+    eof: EofPolicy::Zero,
+};
+
+/// Programs that between them fuse every pair and scan loop, wrap around the
+/// tape and jump into and out of fused ops
+const PROGRAMS: [&str; 8] = [
+    // `AddMove`, `MoveAdd` and `MoveJumpIfNonZero`
+    "+++[>++>+++<<-]>.>.",
+    // `MoveAddAndZero` after the move onto the source cell
+    "++++>+++<[->>+<<]>[->+<]>.",
+    // `MoveJumpIfZero` onto a loop that is skipped, then one that is entered
+    "++>[>.<-]<[>+[-<+>]<--]>[>.<-]>+++>+<[>.<-]",
+    // Scans both ways, also around the ends of the tape
+    "+>+>+>>+<<<<[>]>[<]<[<<]>+[>>>>>>>>>>]<.",
+    // Input and output inside fused loops
+    ",[>,]<[.<]",
+    // Procedures defined and called from inside fused code
+    "+(>++<)>+(<+>)<<:>:>+[-<:>]<.",
+    // Nested loops ending on moves
+    "++[>++[>++<-]<-]>>[<<+>>-<]",
+    // Moves longer than the tape
+    "+>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>+<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<.",
+];
+
+/// Ends every run on a `#` that cancels it, so that the next `]` stops it and
+/// hands over the final tape.
+struct Stop<'a> {
+    io: BufferIo,
+    cancel: &'a CancellationToken,
+}
+
+impl Io for Stop<'_> {
+    fn read(&mut self) -> std::io::Result<Option<u8>> {
+        self.io.read()
+    }
+
+    fn write(&mut self, value: u8) -> std::io::Result<()> {
+        self.io.write(value)
+    }
+
+    fn debug(&mut self, _: &str) -> std::io::Result<()> {
+        self.cancel.cancel();
+        Ok(())
+    }
+}
+
+fn compile(source: &str) -> Vec<OptimizedIr> {
+    let source = format!("{source}#+[]");
+    optimizer::optimize(parser::to_ir(parser::tokenize_dialect(&source, &Dialect::PBrain)).unwrap())
+}
+
+/// The output, final tape and pointer of a run through `run`.
+fn outcome(
+    run: impl FnOnce(&mut dyn Io, &CancellationToken) -> Result<u8, RuntimeError>,
+) -> (Vec<u8>, Vec<u8>, usize) {
+    let cancel = CancellationToken::new();
+    let mut io = Stop {
+        io: BufferIo::new("abc"),
+        cancel: &cancel,
+    };
+    match run(&mut io, &cancel) {
+        Err(RuntimeError::Cancelled { snapshot, .. }) => {
+            (io.io.output, snapshot.tape, snapshot.pointer)
+        }
+        other => panic!("expected the run to stop at the end, got {other:?}"),
+    }
+}
+
+#[test]
+fn fused_bytecode_matches_the_interpreter() {
+    for source in PROGRAMS {
+        let ops = compile(source);
+        let interpreted = outcome(|io, cancel| interpreter::interpret_with(&ops, TAPE, io, cancel));
+        let bytecode = vm::compile(&ops, TAPE).unwrap();
+        let fused = outcome(|io, cancel| bytecode.run_with(io, cancel));
+        assert_eq!(fused, interpreted, "{source}\n{:?}", bytecode.ops());
+    }
+}
+
+#[test]
+fn every_fusion_is_exercised() {
+    let fused = PROGRAMS
+        .iter()
+        .flat_map(|source| vm::compile(compile(source), TAPE).unwrap().ops().to_vec())
+        .map(|op| discriminant(&op))
+        .collect::<Vec<_>>();
+    for op in [
+        Op::AddMove(0, 0),
+        Op::MoveAdd(0, 0),
+        Op::MoveAddAndZero(0, 0),
+        Op::MoveJumpIfZero(0, 0),
+        Op::MoveJumpIfNonZero(0, 0),
+        Op::Scan(0),
+    ] {
+        assert!(fused.contains(&discriminant(&op)), "{op:?}");
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::VecDeque;
+
+use cranefuck::{
+    optimizer::optimize,
+    parser::{to_ir, tokenize},
+    tape::{EofPolicy, TapeConfig},
+    transpile::{self, Target, WasmImports},
+    wasm,
+};
+use wasmi::{Caller, Engine, Linker, Module, Store};
+
+const HELLO: &str = include_str!("../examples/hello.bf");
+
+#[derive(Default)]
+struct Io {
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+}
+
+fn compile(source: &str, tape: TapeConfig, imports: WasmImports) -> Vec<u8> {
+    let ir = to_ir(tokenize(source)).unwrap();
+    wasm::compile(optimize(&ir), tape, imports).unwrap()
+}
+
+fn memory_of(caller: &Caller<'_, Io>) -> wasmi::Memory {
+    caller.get_export("memory").unwrap().into_memory().unwrap()
+}
+
+fn read_u32(memory: &[u8], address: i32) -> usize {
+    let address = address as usize;
+    u32::from_le_bytes(memory[address..address + 4].try_into().unwrap()) as usize
+}
+
+/// Runs `module` with `input` on stdin and returns what it wrote to stdout.
+fn run(module: &[u8], imports: WasmImports, input: &[u8]) -> Vec<u8> {
+    let engine = Engine::default();
+    let module = Module::new(&engine, module).unwrap();
+    let mut store = Store::new(
+        &engine,
+        Io {
+            input: input.iter().copied().collect(),
+            output: Vec::new(),
+        },
+    );
+    let mut linker = Linker::<Io>::new(&engine);
+
+    match imports {
+        WasmImports::Host => {
+            linker
+                .func_wrap("env", "input", |mut caller: Caller<'_, Io>| -> i32 {
+                    caller
+                        .data_mut()
+                        .input
+                        .pop_front()
+                        .map_or(-1, |byte| byte as i32)
+                })
+                .unwrap();
+            linker
+                .func_wrap("env", "output", |mut caller: Caller<'_, Io>, byte: i32| {
+                    caller.data_mut().output.push(byte as u8)
+                })
+                .unwrap();
+        }
+        // Single iovec reads and writes are all the generated code needs
+        WasmImports::Wasi => {
+            linker
+                .func_wrap(
+                    "wasi_snapshot_preview1",
+                    "fd_read",
+                    |mut caller: Caller<'_, Io>, _fd: i32, iovec: i32, _len: i32, read: i32| {
+                        let memory = memory_of(&caller);
+                        let buffer = read_u32(memory.data(&caller), iovec);
+                        let byte = caller.data_mut().input.pop_front();
+                        let data = memory.data_mut(&mut caller);
+                        if let Some(byte) = byte {
+                            data[buffer] = byte;
+                        }
+                        let count = byte.is_some() as u32;
+                        data[read as usize..read as usize + 4]
+                            .copy_from_slice(&count.to_le_bytes());
+                        0
+                    },
+                )
+                .unwrap();
+            linker
+                .func_wrap(
+                    "wasi_snapshot_preview1",
+                    "fd_write",
+                    |mut caller: Caller<'_, Io>, fd: i32, iovec: i32, _len: i32, _written: i32| {
+                        let memory = memory_of(&caller);
+                        let data = memory.data(&caller);
+                        let buffer = read_u32(data, iovec);
+                        let len = read_u32(data, iovec + 4);
+                        let bytes = data[buffer..buffer + len].to_vec();
+                        if fd == 1 {
+                            caller.data_mut().output.extend(bytes);
+                        }
+                        0
+                    },
+                )
+                .unwrap();
+        }
+    }
+
+    let instance = linker
+        .instantiate(&mut store, &module)
+        .unwrap()
+        .start(&mut store)
+        .unwrap();
+    instance
+        .get_typed_func::<(), ()>(&store, "_start")
+        .unwrap()
+        .call(&mut store, ())
+        .unwrap();
+    store.into_data().output
+}
+
+#[test]
+fn hello_world_with_either_imports() {
+    for imports in [WasmImports::Wasi, WasmImports::Host] {
+        let module = compile(HELLO, TapeConfig::default(), imports);
+        assert_eq!(run(&module, imports, b""), b"Hello World!\n");
+    }
+}
+
+#[test]
+fn echoes_input_until_eof() {
+    let tape = TapeConfig {
+        eof: EofPolicy::Zero,
+        ..TapeConfig::default()
+    };
+    for imports in [WasmImports::Wasi, WasmImports::Host] {
+        let module = compile(",[.,]", tape, imports);
+        assert_eq!(run(&module, imports, b"sandboxed"), b"sandboxed");
+    }
+}
+
+#[test]
+fn eof_policy_and_tape_size() {
+    // Read twice, print both cells, then step left from cell 0 and wrap to the last cell
+    let source = "+++++++,.,.<+.";
+    let expected = [
+        (EofPolicy::Unchanged, [b'A', b'A', 1]),
+        (EofPolicy::Zero, [b'A', 0, 1]),
+        (EofPolicy::MinusOne, [b'A', 255, 1]),
+    ];
+    for (eof, output) in expected {
+        let tape = TapeConfig { len: 16, eof };
+        let module = compile(source, tape, WasmImports::Host);
+        assert_eq!(run(&module, WasmImports::Host, b"A"), output);
+    }
+}
+
+#[test]
+fn transpiled_wat_runs() {
+    let tape = TapeConfig {
+        eof: EofPolicy::Zero,
+        ..TapeConfig::default()
+    };
+    // Counts down from the byte read, one cell to the right
+    let ir = optimize(to_ir(tokenize(",[->+<]>[.-]")).unwrap());
+    let wat = transpile::transpile(ir, Target::Wat, tape).unwrap();
+    let module = wat::parse_str(wat).unwrap();
+    assert_eq!(run(&module, WasmImports::Wasi, b"\x03"), [3, 2, 1]);
+}